@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::thread;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use semcache::cache::eviction::EvictionStrategy;
+use semcache::cache::response_store::ResponseStore;
+
+const ENTRIES: u64 = 10_000;
+const OPERATIONS_PER_THREAD: u64 = 10_000;
+
+fn filled_store() -> Arc<ResponseStore<Vec<u8>>> {
+    let store = Arc::new(ResponseStore::new());
+    for id in 0..ENTRIES {
+        store.put(id, vec![0; 1024], None);
+    }
+    store
+}
+
+// Every thread looks up entries, replacing one in ten and evicting one in a hundred, like a cache
+// which mostly hits and is full
+fn run_mixed(store: &Arc<ResponseStore<Vec<u8>>>, threads: u64) {
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            scope.spawn(move || {
+                for operation in 0..OPERATIONS_PER_THREAD {
+                    let id = (thread * OPERATIONS_PER_THREAD + operation * 7919) % ENTRIES;
+                    match operation % 100 {
+                        0 => {
+                            if let Some(evicted) = store.evict(&EvictionStrategy::Lru, None) {
+                                store.put(evicted, vec![0; 1024], None);
+                            }
+                        }
+                        remainder if remainder % 10 == 0 => store.put(id, vec![0; 1024], None),
+                        _ => {
+                            store.get(id);
+                        }
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn concurrent_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("response_store_get");
+    for threads in [1, 4, 16] {
+        let store = filled_store();
+        group.throughput(Throughput::Elements(threads * OPERATIONS_PER_THREAD));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    thread::scope(|scope| {
+                        for thread in 0..threads {
+                            let store = &store;
+                            scope.spawn(move || {
+                                for operation in 0..OPERATIONS_PER_THREAD {
+                                    store.get((thread + operation * 7919) % ENTRIES);
+                                }
+                            });
+                        }
+                    });
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn concurrent_mixed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("response_store_mixed");
+    for threads in [1, 4, 16] {
+        let store = filled_store();
+        group.throughput(Throughput::Elements(threads * OPERATIONS_PER_THREAD));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| run_mixed(&store, threads));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_get, concurrent_mixed);
+criterion_main!(benches);
@@ -24,6 +24,7 @@ const sidebars: SidebarsConfig = {
 			items: [
 				'configuration/cache-settings',
 				'configuration/embedding-model',
+				'configuration/authentication',
 			],
 		},
 		{
@@ -1,40 +1,99 @@
-use crate::cache::cache::Cache;
-use crate::cache::cache_impl::{CacheImpl, EvictionPolicy};
-use crate::cache::response_store::ResponseStore;
-use crate::cache::semantic_store::flat_ip_faiss_store::FlatIPFaissStore;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::cache::cache_impl::EvictionPolicy;
+use crate::cache::eviction::EvictionStrategy;
+use crate::cache::semantic_store::index_type::IndexType;
 use crate::clients::client::Client;
 use crate::clients::http_client::HttpClient;
-use crate::embedding::fastembed::FastEmbedService;
+use crate::embedding::config::EmbeddingConfig;
 use crate::embedding::service::EmbeddingService;
+use crate::endpoints::chat::partition::PartitionField;
+use crate::namespaces::config::NamespaceConfig;
+use crate::namespaces::registry::{CacheSettings, Namespaces};
+use crate::providers::context::{ContextConfig, NonTextContent};
+use crate::rerank::config::RerankConfig;
+use crate::rerank::guard::{GuardConfig, HitGuard};
+use crate::rerank::verifier::HitVerifier;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::error;
 
 pub struct AppState {
     pub http_client: Box<dyn Client>,
     pub embedding_service: Box<dyn EmbeddingService>,
-    pub cache: Box<dyn Cache<Vec<u8>>>,
+    // the isolated caches (and coalescers) requests are served from
+    pub namespaces: Namespaces,
+    pub partition_fields: Vec<PartitionField>,
+    pub context_config: ContextConfig,
+    pub non_text_content: NonTextContent,
 }
 
 impl AppState {
-    pub fn new(semantic_threshold: f32, eviction_policy: EvictionPolicy) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        semantic_threshold: f32,
+        eviction_policy: EvictionPolicy,
+        eviction_strategy: EvictionStrategy,
+        partition_fields: Vec<PartitionField>,
+        context_config: ContextConfig,
+        non_text_content: NonTextContent,
+        default_ttl: Option<Duration>,
+        persistence_directory: Option<PathBuf>,
+        index_type: IndexType,
+        embedding_config: EmbeddingConfig,
+        coalescing_wait_timeout: Option<Duration>,
+        namespace_config: NamespaceConfig,
+        rerank_config: RerankConfig,
+        guard_config: GuardConfig,
+        eviction_signal: Arc<Notify>,
+    ) -> Self {
         // client for upstream LLM requests
         let http_client = Box::new(HttpClient::new());
         // cache fields
-        let embedding_service = Box::new(FastEmbedService::new());
-        let semantic_store = Box::new(FlatIPFaissStore::new(
-            embedding_service.get_dimensionality(),
-        ));
-        let response_store = ResponseStore::new();
-        // create cache
-        let cache = Box::new(CacheImpl::new(
-            semantic_store,
-            response_store,
-            semantic_threshold,
+        let embedding_service = embedding_config.new_service();
+        let verifier = rerank_config
+            .enabled
+            .then(|| Arc::new(HitVerifier::new(rerank_config)));
+        let guard = guard_config
+            .enabled
+            .then(|| Arc::new(HitGuard::new(guard_config)));
+        let dimensionality = embedding_service.get_dimensionality();
+        let index_type = index_type
+            .validate_for(dimensionality)
+            .unwrap_or_else(|err| {
+                error!(
+                    error = err,
+                    "Semantic index doesn't fit the embedding model"
+                );
+                panic!("Semantic index doesn't fit the embedding model")
+            });
+        let cache_settings = CacheSettings {
+            similarity_threshold: semantic_threshold,
             eviction_policy,
-        ));
+            eviction_strategy,
+            default_ttl,
+            index_type,
+            dimensionality,
+            persistence_directory,
+            coalescing_wait_timeout,
+            verifier,
+            guard,
+            eviction_signal: Some(eviction_signal),
+        };
+        // create the default namespace, restoring the entries persisted by a previous run
+        let namespaces = Namespaces::new(cache_settings, namespace_config).unwrap_or_else(|err| {
+            error!(error = ?err);
+            panic!("Failed to create cache")
+        });
         // put service dependencies into app state
         Self {
             http_client,
             embedding_service,
-            cache,
+            namespaces,
+            partition_fields,
+            context_config,
+            non_text_content,
         }
     }
 }
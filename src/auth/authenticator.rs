@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::Next;
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use super::config::{AuthConfig, Scope};
+use super::error::AuthError;
+use crate::utils::header_utils::SEMCACHE_KEY_HEADER;
+
+struct ApiKey {
+    name: String,
+    scopes: HashSet<Scope>,
+}
+
+// Checks the api key of a request against the configured keys
+pub struct Authenticator {
+    // keyed by the hex encoded sha256 of the key, None if every request is allowed
+    keys: Option<HashMap<String, ApiKey>>,
+}
+
+impl Authenticator {
+    pub fn new(config: AuthConfig) -> Self {
+        let keys = config.enabled.then(|| {
+            config
+                .keys
+                .into_iter()
+                .map(|key| {
+                    let api_key = ApiKey {
+                        name: key.name,
+                        scopes: key.scopes.into_iter().collect(),
+                    };
+                    (key.sha256.to_ascii_lowercase(), api_key)
+                })
+                .collect()
+        });
+        Self { keys }
+    }
+
+    pub fn authorize(&self, headers: &HeaderMap, scope: Scope) -> Result<(), AuthError> {
+        let Some(keys) = &self.keys else {
+            return Ok(());
+        };
+        let key = presented_key(headers, scope).ok_or(AuthError::MissingKey)?;
+        let api_key = keys.get(&sha256_hex(key)).ok_or(AuthError::InvalidKey)?;
+        if !api_key.scopes.contains(&scope) {
+            return Err(AuthError::MissingScope(api_key.name.clone(), scope));
+        }
+        debug!(key = api_key.name, ?scope, "Authorized request");
+        Ok(())
+    }
+}
+
+// Proxied requests pass the authorization header on to the llm provider, so there the key can only
+// be sent in the x-semcache-key header. The other endpoints also accept a bearer token.
+fn presented_key(headers: &HeaderMap, scope: Scope) -> Option<&[u8]> {
+    if let Some(key) = headers.get(&SEMCACHE_KEY_HEADER) {
+        return Some(key.as_bytes());
+    }
+    if scope == Scope::Proxy {
+        return None;
+    }
+    headers
+        .get(AUTHORIZATION)?
+        .as_bytes()
+        .strip_prefix(b"Bearer ")
+}
+
+fn sha256_hex(key: &[u8]) -> String {
+    Sha256::digest(key)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+// Axum middleware rejecting requests whose key lacks `scope`
+pub async fn require_scope(
+    State((authenticator, scope)): State<(Arc<Authenticator>, Scope)>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    authenticator.authorize(request.headers(), scope)?;
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{Authenticator, sha256_hex};
+    use crate::auth::config::{ApiKeyConfig, AuthConfig, Scope};
+    use crate::auth::error::AuthError;
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(AuthConfig {
+            enabled: true,
+            keys: vec![ApiKeyConfig {
+                name: String::from("reader"),
+                sha256: sha256_hex(b"sk-reader"),
+                scopes: vec![Scope::Proxy, Scope::CacheRead],
+            }],
+            keys_file: None,
+        })
+    }
+
+    fn headers(name: &str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_static(value),
+        );
+        headers
+    }
+
+    #[test]
+    fn should_allow_key_with_scope() {
+        // given
+        let authenticator = authenticator();
+
+        // then
+        assert!(
+            authenticator
+                .authorize(&headers("x-semcache-key", "sk-reader"), Scope::Proxy)
+                .is_ok()
+        );
+        assert!(
+            authenticator
+                .authorize(
+                    &headers("authorization", "Bearer sk-reader"),
+                    Scope::CacheRead
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn should_reject_missing_unknown_and_underscoped_keys() {
+        // given
+        let authenticator = authenticator();
+
+        // then
+        assert!(matches!(
+            authenticator.authorize(&HeaderMap::new(), Scope::CacheRead),
+            Err(AuthError::MissingKey)
+        ));
+        assert!(matches!(
+            authenticator.authorize(&headers("x-semcache-key", "sk-other"), Scope::CacheRead),
+            Err(AuthError::InvalidKey)
+        ));
+        assert!(matches!(
+            authenticator.authorize(&headers("x-semcache-key", "sk-reader"), Scope::CacheWrite),
+            Err(AuthError::MissingScope(_, Scope::CacheWrite))
+        ));
+    }
+
+    #[test]
+    fn proxy_should_not_accept_upstream_authorization_header() {
+        // given
+        let authenticator = authenticator();
+
+        // when
+        let result =
+            authenticator.authorize(&headers("authorization", "Bearer sk-reader"), Scope::Proxy);
+
+        // then
+        assert!(matches!(result, Err(AuthError::MissingKey)));
+    }
+
+    #[test]
+    fn disabled_auth_should_allow_everything() {
+        // given
+        let authenticator = Authenticator::new(AuthConfig::default());
+
+        // then
+        assert!(
+            authenticator
+                .authorize(&HeaderMap::new(), Scope::Admin)
+                .is_ok()
+        );
+    }
+}
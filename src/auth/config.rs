@@ -0,0 +1,63 @@
+use serde::Deserialize;
+
+use super::error::AuthError;
+
+// What a key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    // the read through cache endpoints of the llm providers
+    Proxy,
+    // reads from the cache aside api
+    CacheRead,
+    // writes to the cache aside api, and invalidation
+    CacheWrite,
+    // the admin dashboard and metrics
+    Admin,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    // shows up in logs instead of the key
+    pub name: String,
+    // hex encoded sha256 of the key, keys themselves are never stored
+    pub sha256: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    #[serde(default)]
+    pub keys: Vec<ApiKeyConfig>,
+    // a yaml file with more `keys`, so they can be kept out of the main config
+    #[serde(default)]
+    pub keys_file: Option<String>,
+}
+
+impl AuthConfig {
+    // Reads the keys file into `keys`
+    pub fn load_keys(mut self) -> Result<Self, AuthError> {
+        if let Some(keys_file) = &self.keys_file {
+            let keys = config::Config::builder()
+                .add_source(config::File::with_name(keys_file))
+                .build()
+                .and_then(|file| file.get::<Vec<ApiKeyConfig>>("keys"))
+                .map_err(|err| {
+                    AuthError::InvalidConfig(format!("Failed to read keys file {keys_file}: {err}"))
+                })?;
+            self.keys.extend(keys);
+        }
+        for key in &self.keys {
+            let valid_hash =
+                key.sha256.len() == 64 && key.sha256.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid_hash {
+                return Err(AuthError::InvalidConfig(format!(
+                    "sha256 of key '{}' must be 64 hex digits",
+                    key.name
+                )));
+            }
+        }
+        Ok(self)
+    }
+}
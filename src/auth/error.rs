@@ -0,0 +1,38 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use thiserror::Error;
+use tracing::warn;
+
+use super::config::Scope;
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Missing api key, expected it in the x-semcache-key or authorization header")]
+    MissingKey,
+
+    #[error("Invalid api key")]
+    InvalidKey,
+
+    #[error("Api key '{0}' lacks the {1:?} scope")]
+    MissingScope(String, Scope),
+
+    #[error("Invalid auth config: {0}")]
+    InvalidConfig(String),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::MissingKey | Self::InvalidKey => StatusCode::UNAUTHORIZED,
+            Self::MissingScope(..) => StatusCode::FORBIDDEN,
+            Self::InvalidConfig(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        warn!("Rejecting request, {}", self);
+        // don't tell which key lacked which scope
+        let message = match self {
+            Self::MissingScope(..) => String::from("Api key lacks the scope for this endpoint"),
+            other => other.to_string(),
+        };
+        (status, message).into_response()
+    }
+}
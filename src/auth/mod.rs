@@ -0,0 +1,3 @@
+pub mod authenticator;
+pub mod config;
+pub mod error;
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::app_state::AppState;
+use crate::namespaces::registry::Namespace;
+
+// Runs `job` on the cache of every namespace on the blocking thread pool. Background tasks which
+// change the caches delete vectors from the semantic index, which can take a while and would
+// otherwise hold up an async worker.
+pub async fn run_blocking_per_namespace<F>(state: &Arc<AppState>, task: &str, job: F)
+where
+    F: Fn(&Namespace) + Send + 'static,
+{
+    let state = state.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        for namespace in state.namespaces.all() {
+            job(&namespace);
+        }
+    })
+    .await;
+    if let Err(err) = result {
+        warn!(error = ?err, task, "Background cache task failed");
+    }
+}
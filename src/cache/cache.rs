@@ -1,8 +1,83 @@
+use std::time::Duration;
+
 use crate::cache::error::CacheError;
+use crate::cache::partition::PartitionKey;
+
+// A cached response together with how it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheHit<T> {
+    pub response: T,
+    pub entry_id: u64,
+    // similarity of the entry to the query, on the same [0, 1] scale as the threshold
+    pub similarity: f32,
+    // the similarity threshold the entry had to meet
+    pub similarity_threshold: f32,
+}
+
+// Entries found in only one of the stores by a consistency check, and removed from it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Orphans {
+    // responses without a vector, which no lookup can find
+    pub responses: usize,
+    // vectors without a response, which lookups find but can't serve
+    pub vectors: usize,
+}
 
 #[cfg_attr(test, mockall::automock)]
 pub trait Cache<T: Send + Sync>: Send + Sync {
-    fn get_if_present(&self, embedding: &[f32]) -> Result<Option<T>, CacheError>;
-    fn insert(&self, embedding: Vec<f32>, response: T) -> Result<(), CacheError>;
-    fn try_update(&self, embedding: &[f32], response: T) -> Result<bool, CacheError>;
+    // `similarity_threshold` overrides the threshold of the cache for this lookup
+    fn get_if_present(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        similarity_threshold: Option<f32>,
+    ) -> Result<Option<CacheHit<T>>, CacheError>;
+    // up to `top_k` entries within the similarity threshold, most similar first
+    fn get_candidates(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        top_k: usize,
+        similarity_threshold: Option<f32>,
+    ) -> Result<Vec<CacheHit<T>>, CacheError>;
+    // counts the entry as served, candidates which were looked up but not served don't count
+    fn record_hit(&self, entry_id: u64);
+    // `ttl` overrides the default time to live of the cache for this entry
+    fn insert(
+        &self,
+        partition: PartitionKey,
+        embedding: Vec<f32>,
+        response: T,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError>;
+    fn try_update(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        response: T,
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError>;
+    // removes the entry matching `embedding` exactly (like try_update), returns whether there was one
+    fn delete(&self, partition: PartitionKey, embedding: &[f32]) -> Result<bool, CacheError>;
+    // removes all entries within `similarity_threshold` of `embedding`, returns the number removed
+    fn delete_similar(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        similarity_threshold: f32,
+    ) -> Result<usize, CacheError>;
+    // removes all entries in `partition`, returns the number removed
+    fn delete_partition(&self, partition: PartitionKey) -> Result<usize, CacheError>;
+    // removes all entries, returns the number removed
+    fn flush(&self) -> Result<usize, CacheError>;
+    // removes expired entries from the cache, returns the number of entries removed
+    fn remove_expired(&self) -> Result<usize, CacheError>;
+    // evicts entries until the cache is within its eviction policy, returns the number evicted
+    fn evict(&self) -> Result<usize, CacheError>;
+    // estimate of the memory taken up by the responses and the semantic index
+    fn memory_usage_bytes(&self) -> usize;
+    // removes entries left in only one of the stores by failed or interleaved changes
+    fn repair_orphans(&self) -> Result<Orphans, CacheError>;
+    // writes a snapshot of the cache to disk, a no-op unless persistence is enabled
+    fn snapshot(&self) -> Result<(), CacheError>;
 }
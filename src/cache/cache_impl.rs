@@ -1,19 +1,103 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 
-use super::cache::Cache;
+use super::cache::{Cache, CacheHit, Orphans};
 use super::error::CacheError;
+use super::eviction::{EvictionCost, EvictionStrategy, TinyLfuAdmission};
+use super::partition::PartitionKey;
+use super::persistence::Persistence;
+use super::persistence::record::Record;
+use super::persistence::snapshot::{ResponseSnapshot, SnapshotEntry};
 use super::semantic_store::semantic_store::SemanticStore;
+use super::size::HeapSize;
 use crate::cache::response_store::ResponseStore;
-use crate::metrics::metrics::CACHE_SIZE;
-use tracing::{debug, info};
-
-#[derive(Debug, Clone)]
+use crate::metrics::metrics::{CACHE_SIZE, EVICTIONS, NAMESPACE_CACHE_SIZE, ORPHANS_REPAIRED};
+use crate::namespaces::registry::DEFAULT_NAMESPACE;
+use serde::Deserialize;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+// Configured as e.g. `{policy_type: entry_limit, value: 1000}`, or with several limits as
+// `{policy_type: combined, value: {max_entries: 1000, max_memory_mb: 512}}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "policy_type", content = "value", rename_all = "snake_case")]
 pub enum EvictionPolicy {
     EntryLimit(usize),
-    MemoryLimitMb(usize), // Could also implement a "combined" of both limits
+    MemoryLimitMb(usize),
+    Combined(CombinedLimits),
+}
+
+// Eviction starts once any limit is used up to `high_watermark`, and then frees entries until all
+// limits are used below `low_watermark`, so that it doesn't have to run again on the next insert
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CombinedLimits {
+    pub max_entries: Option<usize>,
+    pub max_memory_mb: Option<usize>,
+    #[serde(default = "default_high_watermark")]
+    pub high_watermark: f64,
+    #[serde(default = "default_low_watermark")]
+    pub low_watermark: f64,
+}
+
+fn default_high_watermark() -> f64 {
+    1.0
+}
+
+fn default_low_watermark() -> f64 {
+    0.9
+}
+
+impl EvictionPolicy {
+    pub fn validate(self) -> Result<Self, String> {
+        if let EvictionPolicy::Combined(limits) = &self {
+            if limits.max_entries.is_none() && limits.max_memory_mb.is_none() {
+                return Err(String::from(
+                    "Combined eviction policy needs max_entries or max_memory_mb",
+                ));
+            }
+            if !(0.0 < limits.low_watermark
+                && limits.low_watermark <= limits.high_watermark
+                && limits.high_watermark <= 1.0)
+            {
+                return Err(format!(
+                    "Watermarks must satisfy 0 < low_watermark <= high_watermark <= 1: {:?}",
+                    limits
+                ));
+            }
+        }
+        Ok(self)
+    }
+
+    fn max_entries(&self) -> Option<usize> {
+        match self {
+            EvictionPolicy::EntryLimit(limit) => Some(*limit),
+            EvictionPolicy::MemoryLimitMb(_) => None,
+            EvictionPolicy::Combined(limits) => limits.max_entries,
+        }
+    }
+
+    fn max_memory_mb(&self) -> Option<usize> {
+        match self {
+            EvictionPolicy::EntryLimit(_) => None,
+            EvictionPolicy::MemoryLimitMb(limit) => Some(*limit),
+            EvictionPolicy::Combined(limits) => limits.max_memory_mb,
+        }
+    }
+
+    // single limits evict as soon as they are reached, and only until they aren't anymore
+    fn watermarks(&self) -> (f64, f64) {
+        match self {
+            EvictionPolicy::Combined(limits) => (limits.high_watermark, limits.low_watermark),
+            _ => (1.0, 1.0),
+        }
+    }
 }
 
 const TOP_K: usize = 1;
+// last_inserted_id before anything was inserted
+const NO_ENTRY: u64 = u64::MAX;
 // set similarity_threshold to 0.99 to allow for floating point rounding
 const EXACT_MATCH_SIMILARITY: f32 = 0.99;
 
@@ -23,17 +107,35 @@ pub struct CacheImpl<T> {
     semantic_store: Box<dyn SemanticStore>,
     id_generator: AtomicU64,
     eviction_policy: EvictionPolicy,
+    eviction_strategy: EvictionStrategy,
+    // frequencies of prompts and entries, only kept for the TinyLFU strategy
+    admission: Option<TinyLfuAdmission>,
+    // None if entries only leave the cache through eviction
+    default_ttl: Option<Duration>,
+    // None if the cache only lives in memory
+    persistence: Option<Persistence>,
+    // label of the per namespace metrics
+    namespace: String,
+    // the size last added to CACHE_SIZE, which is the total across namespaces
+    reported_size: AtomicI64,
+    // wakes the background eviction task, None if inserts evict themselves
+    eviction_signal: Option<Arc<Notify>>,
+    // kept from being evicted by the background task, NO_ENTRY before the first insert
+    last_inserted_id: AtomicU64,
+    // the next id as of the previous consistency check, later entries might still be mid insert
+    consistency_cutoff: AtomicU64,
 }
 
 impl<T> CacheImpl<T>
 where
-    T: Clone + Send + Sync + 'static,
+    T: Clone + Send + Sync + HeapSize + EvictionCost + Into<Vec<u8>> + TryFrom<Vec<u8>> + 'static,
 {
     pub fn new(
         semantic_store: Box<dyn SemanticStore>,
         response_store: ResponseStore<T>,
         similarity_threshold: f32,
         eviction_policy: EvictionPolicy,
+        default_ttl: Option<Duration>,
     ) -> Self {
         assert!(
             (0.0..=1.0).contains(&similarity_threshold),
@@ -48,110 +150,571 @@ where
             semantic_store,
             id_generator,
             eviction_policy,
+            eviction_strategy: EvictionStrategy::default(),
+            admission: None,
+            default_ttl,
+            persistence: None,
+            namespace: String::from(DEFAULT_NAMESPACE),
+            reported_size: AtomicI64::new(0),
+            eviction_signal: None,
+            last_inserted_id: AtomicU64::new(NO_ENTRY),
+            consistency_cutoff: AtomicU64::new(0),
         }
     }
 
-    fn is_full(&self) -> bool {
-        match &self.eviction_policy {
-            EvictionPolicy::EntryLimit(limit) => {
-                debug!(
-                    "Cache size: {}, limit: {}",
-                    self.response_store.len(),
-                    limit
-                );
-                self.response_store.len() >= *limit
+    // Reports the metrics of this cache under `namespace`
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = String::from(namespace);
+        self
+    }
+
+    // Picks the entries evicted once the eviction policy's limit is reached, lru by default
+    pub fn with_eviction_strategy(mut self, eviction_strategy: EvictionStrategy) -> Self {
+        self.admission =
+            (eviction_strategy == EvictionStrategy::TinyLfu).then(TinyLfuAdmission::new);
+        self.eviction_strategy = eviction_strategy;
+        self
+    }
+
+    // Evicts in a background task woken by `signal` instead of during inserts, which then only
+    // signal it once the cache is full
+    pub fn with_eviction_signal(mut self, signal: Arc<Notify>) -> Self {
+        self.eviction_signal = Some(signal);
+        self
+    }
+
+    // Restores the entries persisted by a previous run, and persists all changes from here on
+    pub fn with_persistence(mut self, persistence: Persistence) -> Result<Self, CacheError> {
+        let mut next_id = 0;
+        let mut from_seq = 0;
+        if let Some(snapshot) = persistence.read_snapshot()? {
+            self.semantic_store.load(&persistence.vectors_dir())?;
+            for entry in snapshot.entries {
+                match T::try_from(entry.response) {
+                    Ok(response) => self.response_store.put_with_stats(
+                        entry.id,
+                        response,
+                        entry.expires_at,
+                        entry.stats,
+                    ),
+                    Err(_) => warn!(id = entry.id, "Dropping unreadable persisted response"),
+                }
+            }
+            next_id = snapshot.next_id;
+            from_seq = snapshot.log_seq;
+        }
+        let replayed = persistence.replay(from_seq, |record| {
+            next_id = next_id.max(record.id() + 1);
+            self.apply(record)
+        })?;
+
+        // a vector without a response could never be served, but would shadow newer entries
+        for id in self.semantic_store.ids() {
+            if !self.response_store.contains(id) {
+                self.semantic_store.delete(id)?;
+            }
+        }
+        self.id_generator = AtomicU64::new(next_id);
+        self.persistence = Some(persistence);
+
+        // the eviction policy might have been lowered since the entries were persisted
+        self.evict_while_full(None)?;
+        self.report_size();
+        info!(
+            "Restored {} entries from disk, replayed {} logged changes",
+            self.response_store.len(),
+            replayed
+        );
+        Ok(self)
+    }
+
+    // Applies a logged change on top of the restored state
+    fn apply(&self, record: Record) -> Result<(), CacheError> {
+        match record {
+            Record::Insert {
+                id,
+                partition,
+                embedding,
+                response,
+                expires_at,
+            } => {
+                let Ok(response) = T::try_from(response) else {
+                    warn!(id, "Dropping unreadable persisted response");
+                    return Ok(());
+                };
+                self.response_store.put(id, response, expires_at);
+                // the snapshot might already contain the vector
+                self.semantic_store.delete(id)?;
+                self.semantic_store.put(partition, id, embedding)?;
+            }
+            Record::Update {
+                id,
+                response,
+                expires_at,
+            } => {
+                // the entry might have been removed after the update
+                if let Ok(response) = T::try_from(response) {
+                    self.response_store.replace(id, response, expires_at);
+                }
+            }
+            Record::Remove { id } => {
+                self.response_store.remove(id);
+                self.semantic_store.delete(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.persistence.is_some()
+    }
+
+    // Changes are persisted after they were applied in memory, so that a snapshot taken in between
+    // doesn't lose them. Failing to persist a change doesn't fail the request, the change is still
+    // part of the next snapshot.
+    fn persist(&self, record: Record) {
+        if let Some(persistence) = &self.persistence
+            && let Err(err) = persistence.append(&record)
+        {
+            warn!(error = ?err, "Failed to persist cache change");
+        }
+    }
+
+    // Removes the given entries from both stores, returns how many of them were in the cache
+    fn remove_entries(&self, ids: &[u64]) -> Result<usize, CacheError> {
+        let mut removed = 0;
+        for id in ids {
+            if self.response_store.remove(*id) {
+                removed += 1;
+            }
+            self.semantic_store.delete(*id)?;
+            self.persist(Record::Remove { id: *id });
+        }
+        self.report_size();
+        debug!("Removed {} entries", removed);
+        Ok(removed)
+    }
+
+    // Swapping in the new size makes concurrent reports add up to the right total
+    fn report_size(&self) {
+        let size = self.response_store.len() as i64;
+        let previous = self.reported_size.swap(size, Ordering::Relaxed);
+        CACHE_SIZE.add(size - previous);
+        NAMESPACE_CACHE_SIZE
+            .with_label_values(&[&self.namespace])
+            .set(size);
+    }
+
+    // Evicts down to the low watermark once the high watermark is reached. `excluded` is the entry
+    // inserted last, which would otherwise be the first victim of strategies that rank entries by
+    // how often they were served. Returns the number of evicted entries.
+    fn evict_while_full(&self, excluded: Option<u64>) -> Result<usize, CacheError> {
+        if !self.is_full() {
+            return Ok(0);
+        }
+        info!("cache is full, evicting!");
+        let (_, low_watermark) = self.eviction_policy.watermarks();
+        let mut evicted = 0;
+        loop {
+            let (utilization, reason) = self.utilization();
+            if utilization < low_watermark {
+                break;
+            }
+            // entries take up about the same share of every limit, so this many get below the low
+            // watermark, unless the victims are smaller than average
+            let overshoot = 1.0 - low_watermark / utilization;
+            let batch = (self.response_store.len() as f64 * overshoot).floor() as usize + 1;
+            let evicted_ids =
+                self.response_store
+                    .evict_batch(&self.eviction_strategy, excluded, batch);
+            if evicted_ids.is_empty() {
+                break; // No more entries to evict
             }
-            EvictionPolicy::MemoryLimitMb(limit) => {
-                let response_store_memory_used_mb =
-                    self.response_store.memory_usage_bytes() as f64 / (1024.0 * 1024.0);
-                let semantic_store_memory_used_mb =
-                    self.semantic_store.memory_usage_bytes() as f64 / (1024.0 * 1024.0);
-                let total_memory_used_mb =
-                    response_store_memory_used_mb + semantic_store_memory_used_mb;
-                let limit_mb = *limit as f64;
-                debug!("Cache size: {}, limit: {}", total_memory_used_mb, limit_mb);
-                total_memory_used_mb >= limit_mb
+            for evicted_id in &evicted_ids {
+                self.semantic_store.delete(*evicted_id)?;
+                self.persist(Record::Remove { id: *evicted_id });
             }
+            EVICTIONS
+                .with_label_values(&[reason])
+                .inc_by(evicted_ids.len() as u64);
+            evicted += evicted_ids.len();
         }
+        Ok(evicted)
+    }
+
+    // Leaves eviction to the background task if there is one, so the insert isn't held up by it
+    fn request_eviction(&self, inserted_id: u64) -> Result<(), CacheError> {
+        self.last_inserted_id.store(inserted_id, Ordering::Relaxed);
+        match &self.eviction_signal {
+            Some(signal) if self.is_full() => signal.notify_one(),
+            Some(_) => {}
+            None => {
+                self.evict_while_full(Some(inserted_id))?;
+            }
+        }
+        Ok(())
+    }
+
+    // With TinyLFU, a new entry which would push out an entry served more often than its prompt
+    // was asked for is dropped again instead
+    fn is_admitted(&self, id: u64, frequency: Option<u8>) -> bool {
+        let (Some(admission), Some(frequency)) = (&self.admission, frequency) else {
+            return true;
+        };
+        if !self.is_full() {
+            return true;
+        }
+        self.response_store
+            .peek_victim(&self.eviction_strategy, Some(id))
+            .is_none_or(|victim_id| admission.admits(frequency, victim_id))
+    }
+
+    fn expires_at(&self, ttl: Option<Duration>) -> Option<SystemTime> {
+        ttl.or(self.default_ttl).map(|ttl| SystemTime::now() + ttl)
+    }
+
+    fn is_full(&self) -> bool {
+        let (high_watermark, _) = self.eviction_policy.watermarks();
+        self.utilization().0 >= high_watermark
+    }
+
+    // The share of its limit the fullest limit is used to, and the eviction reason of that limit
+    fn utilization(&self) -> (f64, &'static str) {
+        let entries = self.eviction_policy.max_entries().map(|limit| {
+            debug!(
+                "Cache size: {}, limit: {}",
+                self.response_store.len(),
+                limit
+            );
+            (
+                self.response_store.len() as f64 / limit as f64,
+                "entry_limit",
+            )
+        });
+        let memory = self.eviction_policy.max_memory_mb().map(|limit| {
+            let total_memory_used_mb = self.memory_usage_bytes() as f64 / (1024.0 * 1024.0);
+            let limit_mb = limit as f64;
+            debug!("Cache size: {}, limit: {}", total_memory_used_mb, limit_mb);
+            (total_memory_used_mb / limit_mb, "memory_limit")
+        });
+        entries
+            .into_iter()
+            .chain(memory)
+            .fold((0.0, "entry_limit"), |fullest, limit| {
+                if limit.0 > fullest.0 { limit } else { fullest }
+            })
     }
 }
 
 impl<T> Cache<T> for CacheImpl<T>
 where
-    T: Clone + Send + Sync + 'static,
+    T: Clone + Send + Sync + HeapSize + EvictionCost + Into<Vec<u8>> + TryFrom<Vec<u8>> + 'static,
 {
-    fn get_if_present(&self, embedding: &[f32]) -> Result<Option<T>, CacheError> {
-        // search semantic store for vectors similar to our query vector
-        let search_result = self
-            .semantic_store
-            .get(embedding, TOP_K, self.similarity_threshold)?;
-
-        // return early if no fitting match found
-        if search_result.is_empty() {
-            return Ok(None);
-        }
-        // choose best match
-        let id = search_result[0];
+    fn get_if_present(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        similarity_threshold: Option<f32>,
+    ) -> Result<Option<CacheHit<T>>, CacheError> {
+        // only the best match, which is a miss if it expired
+        let best_match = self
+            .get_candidates(partition, embedding, TOP_K, similarity_threshold)?
+            .into_iter()
+            .next();
+        Ok(best_match)
+    }
 
-        // extract saved response using the index of the nearest vector
-        let cached_response = self.response_store.get(id);
+    fn get_candidates(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        top_k: usize,
+        similarity_threshold: Option<f32>,
+    ) -> Result<Vec<CacheHit<T>>, CacheError> {
+        let similarity_threshold = similarity_threshold.unwrap_or(self.similarity_threshold);
+        // search semantic store for vectors similar to our query vector within the same partition
+        let search_result =
+            self.semantic_store
+                .get(partition, embedding, top_k, similarity_threshold)?;
+
+        // extract saved responses using the index of the matching vectors, expired entries are misses
+        let candidates = search_result
+            .into_iter()
+            .filter_map(|found| {
+                self.response_store.get(found.id).map(|response| CacheHit {
+                    response,
+                    entry_id: found.id,
+                    similarity: found.similarity,
+                    similarity_threshold,
+                })
+            })
+            .collect();
+
+        Ok(candidates)
+    }
 
-        Ok(cached_response)
+    fn record_hit(&self, entry_id: u64) {
+        // the entry might have been evicted since it was looked up
+        self.response_store.record_hit(entry_id, SystemTime::now());
+        if let Some(admission) = &self.admission {
+            admission.record_hit(entry_id);
+        }
     }
 
-    fn insert(&self, embedding: Vec<f32>, response: T) -> Result<(), CacheError> {
+    fn insert(
+        &self,
+        partition: PartitionKey,
+        embedding: Vec<f32>,
+        response: T,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
         let id = self.id_generator.fetch_add(1, Ordering::Relaxed);
+        let expires_at = self.expires_at(ttl);
+        let frequency = self
+            .admission
+            .as_ref()
+            .map(|admission| admission.record_miss(partition, &embedding));
+        let record = self.is_persistent().then(|| Record::Insert {
+            id,
+            partition,
+            embedding: embedding.clone(),
+            response: response.clone().into(),
+            expires_at,
+        });
 
-        self.response_store.put(id, response);
-        self.semantic_store.put(id, embedding)?;
+        // responses are put before and removed before their vectors, so a lookup never finds a
+        // vector without a response unless the entry is being removed
+        self.response_store.put(id, response, expires_at);
+        if let Err(err) = self.semantic_store.put(partition, id, embedding) {
+            self.response_store.remove(id);
+            return Err(err);
+        }
+        // the response might have been evicted before its vector was put, which then stays orphaned
+        if !self.response_store.contains(id) {
+            debug!(id, "Entry was evicted while being inserted");
+            self.semantic_store.delete(id)?;
+            return Ok(());
+        }
+        if !self.is_admitted(id, frequency) {
+            debug!("Entry not admitted, its prompt was asked for less often than the victim");
+            self.response_store.remove(id);
+            self.semantic_store.delete(id)?;
+            EVICTIONS.with_label_values(&["admission"]).inc();
+            return Ok(());
+        }
+        if let Some(record) = record {
+            self.persist(record);
+        }
 
         // Evict entries if policy limits are exceeded
-        // todo maybe this should just trigger an idempotent background job to initiate eviction?
-        while self.is_full() {
-            info!("cache is full, evicting!");
-            if let Some(evicted_id) = self.response_store.pop() {
-                self.semantic_store.delete(evicted_id)?;
-            } else {
-                break; // No more entries to evict
-            }
-        }
-        CACHE_SIZE.set(self.response_store.len() as i64);
+        self.request_eviction(id)?;
+        self.report_size();
         debug!("Cache size: {}", self.response_store.len());
         Ok(())
     }
 
     // checks cache for an exact match, if it finds one it updates the response_store of found id
-    // with new body and returns true, otherwise it returns false
-    fn try_update(&self, embedding: &[f32], response: T) -> Result<bool, CacheError> {
+    // with new body (restarting its time to live) and returns true, otherwise it returns false
+    fn try_update(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        response: T,
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError> {
         let maybe_existing_id: Option<u64> = match self
             .semantic_store
-            .get(embedding, 1, EXACT_MATCH_SIMILARITY)?
+            .get(partition, embedding, 1, EXACT_MATCH_SIMILARITY)?
             .as_slice()
         {
             [] => return Ok(false),
-            [head, ..] => Some(*head),
+            [head, ..] => Some(head.id),
         };
         if let Some(id) = maybe_existing_id {
-            self.response_store.put(id, response)
+            let expires_at = self.expires_at(ttl);
+            let record = self.is_persistent().then(|| Record::Update {
+                id,
+                response: response.clone().into(),
+                expires_at,
+            });
+            // the entry might have been removed since it was found, then it's inserted anew
+            if !self.response_store.replace(id, response, expires_at) {
+                return Ok(false);
+            }
+            if let Some(record) = record {
+                self.persist(record);
+            }
         }
         Ok(true)
     }
+
+    fn delete(&self, partition: PartitionKey, embedding: &[f32]) -> Result<bool, CacheError> {
+        let ids = self
+            .semantic_store
+            .get(partition, embedding, 1, EXACT_MATCH_SIMILARITY)?
+            .iter()
+            .map(|found| found.id)
+            .collect::<Vec<_>>();
+        Ok(self.remove_entries(&ids)? > 0)
+    }
+
+    fn delete_similar(
+        &self,
+        partition: PartitionKey,
+        embedding: &[f32],
+        similarity_threshold: f32,
+    ) -> Result<usize, CacheError> {
+        // every entry could be within the threshold
+        let top_k = self.semantic_store.partition_ids(partition).len();
+        if top_k == 0 {
+            return Ok(0);
+        }
+        let ids = self
+            .semantic_store
+            .get(partition, embedding, top_k, similarity_threshold)?
+            .iter()
+            .map(|found| found.id)
+            .collect::<Vec<_>>();
+        self.remove_entries(&ids)
+    }
+
+    fn delete_partition(&self, partition: PartitionKey) -> Result<usize, CacheError> {
+        self.remove_entries(&self.semantic_store.partition_ids(partition))
+    }
+
+    fn flush(&self) -> Result<usize, CacheError> {
+        self.remove_entries(&self.semantic_store.ids())
+    }
+
+    fn evict(&self) -> Result<usize, CacheError> {
+        let last_inserted_id = self.last_inserted_id.load(Ordering::Relaxed);
+        let evicted =
+            self.evict_while_full((last_inserted_id != NO_ENTRY).then_some(last_inserted_id))?;
+        if evicted > 0 {
+            debug!("Evicted {} entries", evicted);
+            self.report_size();
+        }
+        Ok(evicted)
+    }
+
+    fn memory_usage_bytes(&self) -> usize {
+        self.response_store.memory_usage_bytes() + self.semantic_store.memory_usage_bytes()
+    }
+
+    fn remove_expired(&self) -> Result<usize, CacheError> {
+        let expired_ids = self.response_store.remove_expired(SystemTime::now());
+        for id in &expired_ids {
+            self.semantic_store.delete(*id)?;
+            self.persist(Record::Remove { id: *id });
+        }
+        if !expired_ids.is_empty() {
+            debug!("Removed {} expired entries", expired_ids.len());
+            EVICTIONS
+                .with_label_values(&["expired"])
+                .inc_by(expired_ids.len() as u64);
+            self.report_size();
+        }
+        Ok(expired_ids.len())
+    }
+
+    fn repair_orphans(&self) -> Result<Orphans, CacheError> {
+        let next_id = self.id_generator.load(Ordering::Relaxed);
+        let cutoff = self.consistency_cutoff.swap(next_id, Ordering::Relaxed);
+        let mut orphans = Orphans::default();
+
+        // a vector is put after its response, so its response is already there unless it was removed
+        for id in self.semantic_store.ids() {
+            if !self.response_store.contains(id) {
+                self.semantic_store.delete(id)?;
+                orphans.vectors += 1;
+            }
+        }
+        // listing the vectors after the responses keeps responses removed in between from being
+        // taken for orphans, and entries inserted since the last check are left to finish
+        let response_ids = self.response_store.ids();
+        let vector_ids: HashSet<u64> = self.semantic_store.ids().into_iter().collect();
+        for id in response_ids {
+            if id < cutoff && !vector_ids.contains(&id) && self.response_store.remove(id) {
+                self.persist(Record::Remove { id });
+                orphans.responses += 1;
+            }
+        }
+
+        if orphans != Orphans::default() {
+            warn!(
+                namespace = self.namespace,
+                responses = orphans.responses,
+                vectors = orphans.vectors,
+                "Removed orphaned cache entries"
+            );
+            ORPHANS_REPAIRED
+                .with_label_values(&["response"])
+                .inc_by(orphans.responses as u64);
+            ORPHANS_REPAIRED
+                .with_label_values(&["vector"])
+                .inc_by(orphans.vectors as u64);
+            self.report_size();
+        }
+        Ok(orphans)
+    }
+
+    fn snapshot(&self) -> Result<(), CacheError> {
+        let Some(persistence) = &self.persistence else {
+            return Ok(());
+        };
+        persistence.write_snapshot(
+            |log_seq| ResponseSnapshot {
+                next_id: self.id_generator.load(Ordering::Relaxed),
+                log_seq,
+                entries: self
+                    .response_store
+                    .entries(SystemTime::now())
+                    .into_iter()
+                    .map(|entry| SnapshotEntry {
+                        id: entry.id,
+                        response: entry.response.into(),
+                        expires_at: entry.expires_at,
+                        stats: entry.stats,
+                    })
+                    .collect(),
+            },
+            |dir| self.semantic_store.save(dir),
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
     use faiss::error::Error;
     use mockall::predicate::eq;
+    use tokio::sync::Notify;
 
-    use crate::cache::cache::Cache;
-    use crate::cache::cache_impl::{EXACT_MATCH_SIMILARITY, EvictionPolicy};
+    use crate::cache::cache::{Cache, CacheHit, Orphans};
+    use crate::cache::cache_impl::{CombinedLimits, EXACT_MATCH_SIMILARITY, EvictionPolicy};
+    use crate::cache::eviction::EvictionStrategy;
+    use crate::cache::partition::PartitionKey;
+    use crate::cache::persistence::Persistence;
     use crate::cache::response_store::ResponseStore;
+    use crate::cache::semantic_store::flat_ip_faiss_store::FlatIPFaissStore;
     use crate::cache::{
         cache_impl::{CacheImpl, TOP_K},
         error::CacheError,
-        semantic_store::semantic_store::MockSemanticStore,
+        semantic_store::semantic_store::{MockSemanticStore, ScoredMatch},
     };
 
+    const PARTITION: PartitionKey = PartitionKey(0);
+
+    fn scored(id: u64) -> ScoredMatch {
+        ScoredMatch {
+            id,
+            similarity: 1.0,
+        }
+    }
+
     // GET
 
     #[test]
@@ -162,24 +725,99 @@ mod tests {
         let mut mock_semantic_store = MockSemanticStore::new();
         mock_semantic_store
             .expect_get()
-            .with(eq(embedding.clone()), eq(TOP_K), eq(0.9))
-            .return_once(|_, _, _| Ok(vec![0, 1, 2]));
+            .with(eq(PARTITION), eq(embedding.clone()), eq(TOP_K), eq(0.9))
+            .return_once(|_, _, _, _| Ok(vec![scored(0), scored(1), scored(2)]));
+
+        let response_store = ResponseStore::new();
+        response_store.put(0, saved_response.clone(), None);
+
+        let under_test = CacheImpl::new(
+            Box::new(mock_semantic_store),
+            response_store,
+            0.9,
+            EvictionPolicy::EntryLimit(100),
+            None,
+        );
+
+        // when
+        let response = under_test
+            .get_if_present(PARTITION, &embedding, None)
+            .unwrap();
+
+        // then
+        assert_eq!(
+            response.unwrap(),
+            CacheHit {
+                response: saved_response,
+                entry_id: 0,
+                similarity: 1.0,
+                similarity_threshold: 0.9,
+            }
+        );
+    }
+
+    #[test]
+    fn get_should_search_with_threshold_override() {
+        let embedding = vec![0_f32, 1.0, 0.0];
+
+        // given
+        let mut mock_semantic_store = MockSemanticStore::new();
+        mock_semantic_store
+            .expect_get()
+            .with(eq(PARTITION), eq(embedding.clone()), eq(TOP_K), eq(0.97))
+            .return_once(|_, _, _, _| Ok(vec![scored(0)]));
+
+        let response_store = ResponseStore::new();
+        response_store.put(0, String::from("strict match"), None);
+
+        let under_test = CacheImpl::new(
+            Box::new(mock_semantic_store),
+            response_store,
+            0.9,
+            EvictionPolicy::EntryLimit(100),
+            None,
+        );
+
+        // when
+        let response = under_test
+            .get_if_present(PARTITION, &embedding, Some(0.97))
+            .unwrap();
+
+        // then
+        assert_eq!(response.unwrap().similarity_threshold, 0.97);
+    }
+
+    #[test]
+    fn get_candidates_should_return_unexpired_matches_in_order() {
+        let embedding = vec![0_f32, 1.0, 0.0];
+
+        // given
+        let mut mock_semantic_store = MockSemanticStore::new();
+        mock_semantic_store
+            .expect_get()
+            .with(eq(PARTITION), eq(embedding.clone()), eq(3), eq(0.9))
+            .return_once(|_, _, _, _| Ok(vec![scored(2), scored(0), scored(1)]));
 
         let response_store = ResponseStore::new();
-        response_store.put(0, saved_response.clone());
+        response_store.put(0, String::from("second"), None);
+        response_store.put(2, String::from("first"), None);
 
         let under_test = CacheImpl::new(
             Box::new(mock_semantic_store),
             response_store,
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // when
-        let response = under_test.get_if_present(&embedding).unwrap();
+        let candidates = under_test
+            .get_candidates(PARTITION, &embedding, 3, None)
+            .unwrap();
 
         // then
-        assert_eq!(response.unwrap(), saved_response);
+        let entry_ids: Vec<u64> = candidates.iter().map(|hit| hit.entry_id).collect();
+        assert_eq!(entry_ids, vec![2, 0]);
     }
 
     #[test]
@@ -190,18 +828,21 @@ mod tests {
         let mut mock_semantic_store = MockSemanticStore::new();
         mock_semantic_store
             .expect_get()
-            .with(eq(embedding.clone()), eq(TOP_K), eq(0.9))
-            .return_once(|_, _, _| Ok(vec![]));
+            .with(eq(PARTITION), eq(embedding.clone()), eq(TOP_K), eq(0.9))
+            .return_once(|_, _, _, _| Ok(vec![]));
 
         let under_test: CacheImpl<String> = CacheImpl::new(
             Box::new(mock_semantic_store),
             ResponseStore::new(),
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // when
-        let response = under_test.get_if_present(&embedding).unwrap();
+        let response = under_test
+            .get_if_present(PARTITION, &embedding, None)
+            .unwrap();
 
         // then
         assert!(match response {
@@ -218,18 +859,19 @@ mod tests {
         let mut mock_semantic_store = MockSemanticStore::new();
         mock_semantic_store
             .expect_get()
-            .with(eq(embedding.clone()), eq(TOP_K), eq(0.9))
-            .return_once(|_, _, _| Err(CacheError::FaissRetrievalError(Error::ParameterName)));
+            .with(eq(PARTITION), eq(embedding.clone()), eq(TOP_K), eq(0.9))
+            .return_once(|_, _, _, _| Err(CacheError::FaissRetrievalError(Error::ParameterName)));
 
         let cache: CacheImpl<String> = CacheImpl::new(
             Box::new(mock_semantic_store),
             ResponseStore::new(),
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // when
-        let result = cache.get_if_present(&embedding);
+        let result = cache.get_if_present(PARTITION, &embedding, None);
 
         // then
         match result {
@@ -252,8 +894,8 @@ mod tests {
         let mut mock_store = MockSemanticStore::new();
         mock_store
             .expect_put()
-            .with(eq(0u64), eq(embedding.clone()))
-            .return_once(|_, _| Ok(()));
+            .with(eq(PARTITION), eq(0u64), eq(embedding.clone()))
+            .return_once(|_, _, _| Ok(()));
 
         let response_store = ResponseStore::new();
 
@@ -262,10 +904,11 @@ mod tests {
             response_store,
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // when
-        let result = cache.insert(embedding, response.clone());
+        let result = cache.insert(PARTITION, embedding, response.clone(), None);
 
         // then
         assert!(result.is_ok());
@@ -274,6 +917,31 @@ mod tests {
         assert_eq!(stored.as_str(), response);
     }
 
+    #[test]
+    fn insert_should_roll_back_response_when_semantic_store_fails() {
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store
+            .expect_put()
+            .return_once(|_, _, _| Err(CacheError::FaissRetrievalError(Error::ParameterName)));
+
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(100),
+            None,
+        );
+
+        // when
+        let result = cache.insert(PARTITION, vec![0.1, 0.2, 0.3], String::from("orphan"), None);
+
+        // then
+        assert!(result.is_err());
+        assert_eq!(cache.response_store.len(), 0);
+        assert_eq!(cache.response_store.memory_usage_bytes(), 0);
+    }
+
     #[test]
     fn insert_should_evict_when_entry_limit_reached() {
         let embedding = vec![0.1_f32, 0.2, 0.3];
@@ -281,7 +949,7 @@ mod tests {
 
         // given
         let mut mock_store = MockSemanticStore::new();
-        mock_store.expect_put().times(3).returning(|_, _| Ok(()));
+        mock_store.expect_put().times(3).returning(|_, _, _| Ok(()));
         mock_store.expect_delete().times(2).returning(|_| Ok(()));
 
         let response_store = ResponseStore::new();
@@ -291,25 +959,231 @@ mod tests {
             response_store,
             0.9,
             EvictionPolicy::EntryLimit(2),
+            None,
         );
 
         // when - add first entry
-        cache.insert(embedding.clone(), response.clone()).unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), response.clone(), None)
+            .unwrap();
         assert_eq!(cache.response_store.len(), 1);
         assert!(!cache.is_full());
 
         // when - add second entry, this triggers eviction because after adding we have 2 items (which is >= limit)
-        cache.insert(embedding.clone(), response.clone()).unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), response.clone(), None)
+            .unwrap();
         assert_eq!(cache.response_store.len(), 1); // evicted back to 1
 
         // when - add third entry, again triggers eviction
-        cache.insert(embedding.clone(), response.clone()).unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), response.clone(), None)
+            .unwrap();
         assert_eq!(cache.response_store.len(), 1); // still 1
 
         // verify is_full returns false now since we have 1 item and limit is 2
         assert!(!cache.is_full());
     }
 
+    #[test]
+    fn insert_should_evict_down_to_low_watermark() {
+        let embedding = vec![0.1_f32, 0.2, 0.3];
+
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store.expect_put().times(9).returning(|_, _, _| Ok(()));
+        mock_store.expect_delete().times(4).returning(|_| Ok(()));
+        // the entry limit is the fuller one
+        mock_store.expect_memory_usage_bytes().returning(|| 0);
+
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::Combined(CombinedLimits {
+                max_entries: Some(10),
+                max_memory_mb: Some(1),
+                high_watermark: 0.8,
+                low_watermark: 0.5,
+            }),
+            None,
+        );
+
+        // when - the 8th entry reaches the high watermark
+        for _ in 0..8 {
+            cache
+                .insert(PARTITION, embedding.clone(), String::from("response"), None)
+                .unwrap();
+        }
+
+        // then - entries are evicted until less than 5 are left
+        assert_eq!(cache.response_store.len(), 4);
+
+        // when - the next insert stays below the high watermark
+        cache
+            .insert(PARTITION, embedding.clone(), String::from("response"), None)
+            .unwrap();
+
+        // then
+        assert_eq!(cache.response_store.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn insert_should_leave_eviction_to_background_task_when_signalled() {
+        let embedding = vec![0.1_f32, 0.2, 0.3];
+
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store.expect_put().times(2).returning(|_, _, _| Ok(()));
+        mock_store
+            .expect_delete()
+            .with(eq(0))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let signal = Arc::new(Notify::new());
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(2),
+            None,
+        )
+        .with_eviction_signal(signal.clone());
+
+        // when
+        cache
+            .insert(PARTITION, embedding.clone(), String::from("first"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), String::from("second"), None)
+            .unwrap();
+
+        // then - the insert which filled the cache only signalled
+        assert_eq!(cache.response_store.len(), 2);
+        tokio::time::timeout(Duration::from_secs(1), signal.notified())
+            .await
+            .expect("eviction should have been signalled");
+        assert_eq!(cache.evict().unwrap(), 1);
+        assert!(cache.response_store.contains(1));
+        assert_eq!(cache.evict().unwrap(), 0);
+    }
+
+    #[test]
+    fn validate_should_reject_invalid_combined_limits() {
+        let limits = CombinedLimits {
+            max_entries: Some(10),
+            max_memory_mb: None,
+            high_watermark: 0.9,
+            low_watermark: 0.5,
+        };
+
+        assert!(EvictionPolicy::Combined(limits.clone()).validate().is_ok());
+        assert!(
+            EvictionPolicy::Combined(CombinedLimits {
+                max_entries: None,
+                ..limits.clone()
+            })
+            .validate()
+            .is_err()
+        );
+        assert!(
+            EvictionPolicy::Combined(CombinedLimits {
+                low_watermark: 0.95,
+                ..limits
+            })
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn insert_should_evict_least_served_entry_with_lfu() {
+        let embedding = vec![0.1_f32, 0.2, 0.3];
+
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store.expect_put().times(3).returning(|_, _, _| Ok(()));
+        mock_store
+            .expect_delete()
+            .with(eq(1))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(3),
+            None,
+        )
+        .with_eviction_strategy(EvictionStrategy::Lfu);
+        cache
+            .insert(PARTITION, embedding.clone(), String::from("served"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), String::from("unserved"), None)
+            .unwrap();
+        cache.record_hit(0);
+
+        // when - the new entry was never served either, but isn't evicted right away
+        cache
+            .insert(PARTITION, embedding.clone(), String::from("new"), None)
+            .unwrap();
+
+        // then
+        assert!(cache.response_store.contains(0));
+        assert!(!cache.response_store.contains(1));
+        assert!(cache.response_store.contains(2));
+    }
+
+    #[test]
+    fn insert_should_only_admit_prompts_asked_for_more_often_than_victim() {
+        let popular = vec![0.1_f32, 0.2, 0.3];
+        let rare = vec![0.3_f32, 0.2, 0.1];
+
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store.expect_put().times(4).returning(|_, _, _| Ok(()));
+        // the two rejected entries, then the evicted popular one
+        mock_store.expect_delete().times(3).returning(|_| Ok(()));
+
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(2),
+            None,
+        )
+        .with_eviction_strategy(EvictionStrategy::TinyLfu);
+        cache
+            .insert(PARTITION, popular, String::from("popular"), None)
+            .unwrap();
+        cache.record_hit(0);
+        cache.record_hit(0);
+
+        // when - the rare prompt has missed less often than the popular entry was served
+        cache
+            .insert(PARTITION, rare.clone(), String::from("rare"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, rare.clone(), String::from("rare"), None)
+            .unwrap();
+
+        // then
+        assert_eq!(cache.response_store.len(), 1);
+        assert!(cache.response_store.contains(0));
+
+        // when - until it missed more often
+        cache
+            .insert(PARTITION, rare, String::from("rare"), None)
+            .unwrap();
+
+        // then
+        assert_eq!(cache.response_store.len(), 1);
+        assert!(cache.response_store.contains(3));
+    }
+
     #[test]
     fn insert_should_evict_when_memory_limit_reached() {
         use std::sync::Arc;
@@ -326,7 +1200,7 @@ mod tests {
         // given
         let mut mock_store = MockSemanticStore::new();
 
-        mock_store.expect_put().times(3).returning(move |_, _| {
+        mock_store.expect_put().times(3).returning(move |_, _, _| {
             entry_count_clone.fetch_add(1, Ordering::Relaxed);
             Ok(())
         });
@@ -346,19 +1220,26 @@ mod tests {
             response_store,
             0.9,
             EvictionPolicy::MemoryLimitMb(1),
+            None,
         );
 
         // when - add first entry
-        cache.insert(embedding.clone(), response.clone()).unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), response.clone(), None)
+            .unwrap();
         assert!(!cache.is_full()); // should have ~0.8MB which is under 1MB limit
 
         // when - add second entry, this should trigger eviction because 2 entries would be ~1.6MB
-        cache.insert(embedding.clone(), response.clone()).unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), response.clone(), None)
+            .unwrap();
         assert_eq!(cache.response_store.len(), 1); // evicted back to 1
         assert!(!cache.is_full()); // single entry is under limit
 
         // when - add third entry, again triggers eviction
-        cache.insert(embedding.clone(), response.clone()).unwrap();
+        cache
+            .insert(PARTITION, embedding.clone(), response.clone(), None)
+            .unwrap();
         assert_eq!(cache.response_store.len(), 1); // still 1
 
         // verify cache is not full after eviction
@@ -377,21 +1258,29 @@ mod tests {
         let mut mock_store = MockSemanticStore::new();
         mock_store
             .expect_get()
-            .with(eq(embedding.clone()), eq(1), eq(EXACT_MATCH_SIMILARITY))
-            .return_once(move |_, _, _| Ok(vec![existing_id]));
+            .with(
+                eq(PARTITION),
+                eq(embedding.clone()),
+                eq(1),
+                eq(EXACT_MATCH_SIMILARITY),
+            )
+            .return_once(move |_, _, _, _| Ok(vec![scored(existing_id)]));
 
         let response_store = ResponseStore::new();
-        response_store.put(existing_id, String::from("old_response"));
+        response_store.put(existing_id, String::from("old_response"), None);
 
         let cache = CacheImpl::new(
             Box::new(mock_store),
             response_store,
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // when
-        let result = cache.try_update(&embedding, response.clone()).unwrap();
+        let result = cache
+            .try_update(PARTITION, &embedding, response.clone(), None)
+            .unwrap();
 
         // then
         assert!(result);
@@ -410,8 +1299,13 @@ mod tests {
         let mut mock_store = MockSemanticStore::new();
         mock_store
             .expect_get()
-            .with(eq(embedding.clone()), eq(1), eq(EXACT_MATCH_SIMILARITY))
-            .return_once(move |_, _, _| Ok(vec![]));
+            .with(
+                eq(PARTITION),
+                eq(embedding.clone()),
+                eq(1),
+                eq(EXACT_MATCH_SIMILARITY),
+            )
+            .return_once(move |_, _, _, _| Ok(vec![]));
 
         let response_store = ResponseStore::new();
 
@@ -420,10 +1314,13 @@ mod tests {
             response_store,
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // when
-        let result = cache.try_update(&embedding, new_response.clone()).unwrap();
+        let result = cache
+            .try_update(PARTITION, &embedding, new_response.clone(), None)
+            .unwrap();
 
         // then
         assert!(!result);
@@ -436,35 +1333,385 @@ mod tests {
     fn cache_size_metric_tracks_correctly() {
         // Setup cache
         let mut mock_store = MockSemanticStore::new();
-        mock_store.expect_put().times(2).returning(|_, _| Ok(()));
+        mock_store.expect_put().times(2).returning(|_, _, _| Ok(()));
         mock_store
             .expect_get()
             .times(1)
-            .returning(|_, _, _| Ok(vec![0]));
+            .returning(|_, _, _, _| Ok(vec![scored(0)]));
 
         let cache = CacheImpl::new(
             Box::new(mock_store),
             ResponseStore::new(),
             0.9,
             EvictionPolicy::EntryLimit(100),
+            None,
         );
 
         // Insert first entry - should increment len
         cache
-            .insert(vec![0.1, 0.2, 0.3], "first response".to_string())
+            .insert(
+                PARTITION,
+                vec![0.1, 0.2, 0.3],
+                "first response".to_string(),
+                None,
+            )
             .unwrap();
         assert_eq!(cache.response_store.len(), 1);
 
         // Insert second entry - should increment len again
         cache
-            .insert(vec![0.4, 0.5, 0.6], "second response".to_string())
+            .insert(
+                PARTITION,
+                vec![0.4, 0.5, 0.6],
+                "second response".to_string(),
+                None,
+            )
             .unwrap();
         assert_eq!(cache.response_store.len(), 2);
 
         // Try update (overwrite) - should NOT change len
         cache
-            .try_update(&[0.1, 0.2, 0.3], "new response".to_string())
+            .try_update(
+                PARTITION,
+                &[0.1, 0.2, 0.3],
+                "new response".to_string(),
+                None,
+            )
             .unwrap();
         assert_eq!(cache.response_store.len(), 2);
     }
+
+    // TTL
+
+    #[test]
+    fn get_should_return_empty_when_entry_expired() {
+        let embedding = vec![0.1_f32, 0.2, 0.3];
+
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store.expect_put().returning(|_, _, _| Ok(()));
+        mock_store
+            .expect_get()
+            .with(eq(PARTITION), eq(embedding.clone()), eq(TOP_K), eq(0.9))
+            .returning(|_, _, _, _| Ok(vec![scored(0)]));
+
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(100),
+            Some(Duration::from_secs(60)),
+        );
+
+        // when
+        cache
+            .insert(
+                PARTITION,
+                embedding.clone(),
+                String::from("stale response"),
+                Some(Duration::ZERO),
+            )
+            .unwrap();
+
+        // then
+        assert_eq!(
+            cache.get_if_present(PARTITION, &embedding, None).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn remove_expired_should_delete_from_both_stores() {
+        // given
+        let mut mock_store = MockSemanticStore::new();
+        mock_store.expect_put().times(2).returning(|_, _, _| Ok(()));
+        mock_store
+            .expect_delete()
+            .with(eq(0u64))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let cache = CacheImpl::new(
+            Box::new(mock_store),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(100),
+            Some(Duration::from_secs(60)),
+        );
+        cache
+            .insert(
+                PARTITION,
+                vec![0.1, 0.2, 0.3],
+                String::from("expired"),
+                Some(Duration::ZERO),
+            )
+            .unwrap();
+        cache
+            .insert(PARTITION, vec![0.4, 0.5, 0.6], String::from("fresh"), None)
+            .unwrap();
+
+        // when
+        let removed = cache.remove_expired().unwrap();
+
+        // then
+        assert_eq!(removed, 1);
+        assert_eq!(cache.response_store.len(), 1);
+        assert_eq!(cache.response_store.get(1).unwrap(), "fresh");
+    }
+
+    // PERSISTENCE
+
+    #[test]
+    fn with_persistence_should_restore_entries_after_restart() {
+        // given
+        let dir = std::env::temp_dir().join(format!("semcache-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let new_cache = || {
+            CacheImpl::new(
+                Box::new(FlatIPFaissStore::new(3)),
+                ResponseStore::new(),
+                0.9,
+                EvictionPolicy::EntryLimit(100),
+                None,
+            )
+            .with_persistence(Persistence::open(&dir).unwrap())
+            .unwrap()
+        };
+        let first_run = new_cache();
+        first_run
+            .insert(PARTITION, vec![0.0, 1.0, 0.0], String::from("first"), None)
+            .unwrap();
+        first_run
+            .insert(PARTITION, vec![1.0, 0.0, 0.0], String::from("second"), None)
+            .unwrap();
+        first_run.record_hit(0);
+        first_run.snapshot().unwrap();
+        // only in the log
+        first_run
+            .insert(PARTITION, vec![0.0, 0.0, 1.0], String::from("third"), None)
+            .unwrap();
+        drop(first_run);
+
+        // when
+        let second_run = new_cache();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // then
+        let get = |embedding: &[f32]| {
+            second_run
+                .get_if_present(PARTITION, embedding, None)
+                .unwrap()
+                .map(|hit| hit.response)
+        };
+        assert_eq!(get(&[0.0, 1.0, 0.0]), Some(String::from("first")));
+        assert_eq!(get(&[1.0, 0.0, 0.0]), Some(String::from("second")));
+        assert_eq!(get(&[0.0, 0.0, 1.0]), Some(String::from("third")));
+        assert_eq!(second_run.id_generator.load(Ordering::Relaxed), 3);
+        let hits: Vec<(u64, u64)> = second_run
+            .response_store
+            .entries(std::time::SystemTime::now())
+            .into_iter()
+            .map(|entry| (entry.id, entry.stats.hits))
+            .collect();
+        assert!(hits.contains(&(0, 1)));
+        assert!(hits.contains(&(1, 0)));
+    }
+
+    #[test]
+    fn with_persistence_should_restore_lru_order() {
+        // given
+        let dir = std::env::temp_dir().join(format!("semcache-lru-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let new_cache = || {
+            CacheImpl::new(
+                Box::new(FlatIPFaissStore::new(3)),
+                ResponseStore::new(),
+                0.9,
+                EvictionPolicy::EntryLimit(100),
+                None,
+            )
+            .with_persistence(Persistence::open(&dir).unwrap())
+            .unwrap()
+        };
+        let first_run = new_cache();
+        first_run
+            .insert(PARTITION, vec![0.0, 1.0, 0.0], String::from("first"), None)
+            .unwrap();
+        first_run
+            .insert(PARTITION, vec![1.0, 0.0, 0.0], String::from("second"), None)
+            .unwrap();
+        // makes the second entry the least recently used
+        first_run
+            .get_if_present(PARTITION, &[0.0, 1.0, 0.0], None)
+            .unwrap();
+        first_run.snapshot().unwrap();
+        drop(first_run);
+
+        // when
+        let second_run = new_cache();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // then
+        assert_eq!(
+            second_run
+                .response_store
+                .evict(&EvictionStrategy::Lru, None),
+            Some(1)
+        );
+        assert_eq!(
+            second_run
+                .response_store
+                .evict(&EvictionStrategy::Lru, None),
+            Some(0)
+        );
+    }
+
+    // DELETE
+
+    fn flat_cache() -> CacheImpl<String> {
+        CacheImpl::new(
+            Box::new(FlatIPFaissStore::new(3)),
+            ResponseStore::new(),
+            0.9,
+            EvictionPolicy::EntryLimit(100),
+            None,
+        )
+    }
+
+    #[test]
+    fn delete_should_only_remove_exact_match() {
+        // given
+        let cache = flat_cache();
+        cache
+            .insert(PARTITION, vec![0.0, 1.0, 0.0], String::from("exact"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, vec![0.0, 1.0, 0.3], String::from("close"), None)
+            .unwrap();
+
+        // when
+        let deleted = cache.delete(PARTITION, &[0.0, 1.0, 0.0]).unwrap();
+
+        // then
+        assert!(deleted);
+        assert!(!cache.delete(PARTITION, &[0.0, 1.0, 0.0]).unwrap());
+        assert_eq!(cache.response_store.len(), 1);
+        assert_eq!(cache.semantic_store.ids(), vec![1]);
+    }
+
+    #[test]
+    fn delete_similar_should_remove_neighbourhood() {
+        // given
+        let cache = flat_cache();
+        cache
+            .insert(PARTITION, vec![0.0, 1.0, 0.0], String::from("first"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, vec![0.0, 1.0, 0.1], String::from("second"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, vec![1.0, 0.0, 0.0], String::from("far"), None)
+            .unwrap();
+        cache
+            .insert(
+                PartitionKey(1),
+                vec![0.0, 1.0, 0.0],
+                String::from("other"),
+                None,
+            )
+            .unwrap();
+
+        // when
+        let deleted = cache
+            .delete_similar(PARTITION, &[0.0, 1.0, 0.0], 0.9)
+            .unwrap();
+
+        // then
+        assert_eq!(deleted, 2);
+        assert_eq!(
+            cache
+                .get_if_present(PARTITION, &[1.0, 0.0, 0.0], None)
+                .unwrap()
+                .map(|hit| hit.response),
+            Some(String::from("far"))
+        );
+        assert_eq!(
+            cache
+                .get_if_present(PartitionKey(1), &[0.0, 1.0, 0.0], None)
+                .unwrap()
+                .map(|hit| hit.response),
+            Some(String::from("other"))
+        );
+    }
+
+    #[test]
+    fn delete_partition_and_flush_should_remove_entries() {
+        // given
+        let cache = flat_cache();
+        cache
+            .insert(PARTITION, vec![0.0, 1.0, 0.0], String::from("first"), None)
+            .unwrap();
+        cache
+            .insert(
+                PartitionKey(1),
+                vec![0.0, 1.0, 0.0],
+                String::from("second"),
+                None,
+            )
+            .unwrap();
+        cache
+            .insert(
+                PartitionKey(1),
+                vec![1.0, 0.0, 0.0],
+                String::from("third"),
+                None,
+            )
+            .unwrap();
+
+        // when / then
+        assert_eq!(cache.delete_partition(PartitionKey(1)).unwrap(), 2);
+        assert_eq!(cache.semantic_store.ids(), vec![0]);
+        assert_eq!(cache.flush().unwrap(), 1);
+        assert_eq!(cache.response_store.len(), 0);
+        assert!(cache.semantic_store.ids().is_empty());
+    }
+
+    // CONSISTENCY
+
+    #[test]
+    fn repair_orphans_should_remove_entries_left_in_one_store() {
+        // given
+        let cache = flat_cache();
+        cache
+            .insert(PARTITION, vec![0.0, 1.0, 0.0], String::from("first"), None)
+            .unwrap();
+        cache
+            .insert(PARTITION, vec![1.0, 0.0, 0.0], String::from("second"), None)
+            .unwrap();
+        cache.semantic_store.delete(0).unwrap();
+        cache.response_store.remove(1);
+
+        // when
+        let first_check = cache.repair_orphans().unwrap();
+        let second_check = cache.repair_orphans().unwrap();
+
+        // then
+        // responses inserted since the previous check could still be waiting for their vector
+        assert_eq!(
+            first_check,
+            Orphans {
+                responses: 0,
+                vectors: 1
+            }
+        );
+        assert_eq!(
+            second_check,
+            Orphans {
+                responses: 1,
+                vectors: 0
+            }
+        );
+        assert_eq!(cache.response_store.len(), 0);
+        assert!(cache.semantic_store.ids().is_empty());
+    }
 }
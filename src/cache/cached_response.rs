@@ -0,0 +1,375 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::header::{CONTENT_TYPE, SET_COOKIE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use serde_json::Value;
+
+use super::eviction::{CostMetric, EvictionCost};
+use super::persistence::codec::{read_bytes, read_u8, read_u64, write_bytes, write_u8, write_u64};
+use super::size::HeapSize;
+use crate::providers::ProviderType;
+use crate::utils::header_utils::HOP_HEADERS;
+
+// Encoded responses start with this and a version byte
+const MAGIC: &[u8; 3] = b"SCR";
+const VERSION: u8 = 1;
+
+// A response as it was received from upstream, replayed on cache hits
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    // without the headers that describe the connection or the encoding of the body
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    // None for entries written through the cache aside api
+    pub provider: Option<ProviderType>,
+    pub model: Option<String>,
+    pub created_at: SystemTime,
+    // the prompt the response was cached for, hits are verified against it
+    pub prompt: Option<String>,
+    // the request fields the partition key was derived from, as json
+    pub partition_fields: Option<String>,
+    // tokens upstream reported for the request, if it did
+    pub usage_tokens: Option<u64>,
+    // how long upstream took to respond, until the last chunk of a stream
+    pub upstream_latency: Option<Duration>,
+}
+
+impl CachedResponse {
+    pub fn from_upstream(
+        provider: ProviderType,
+        model: Option<String>,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: Vec<u8>,
+    ) -> Self {
+        Self {
+            status,
+            headers: cacheable_headers(headers),
+            usage_tokens: usage_tokens(&body),
+            body,
+            provider: Some(provider),
+            model,
+            created_at: SystemTime::now(),
+            prompt: None,
+            partition_fields: None,
+            upstream_latency: None,
+        }
+    }
+
+    pub fn from_body(body: Vec<u8>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body,
+            provider: None,
+            model: None,
+            created_at: SystemTime::now(),
+            prompt: None,
+            partition_fields: None,
+            usage_tokens: None,
+            upstream_latency: None,
+        }
+    }
+
+    pub fn with_upstream_latency(mut self, latency: Duration) -> Self {
+        self.upstream_latency = Some(latency);
+        self
+    }
+
+    pub fn with_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn with_partition_fields(mut self, fields: &Value) -> Self {
+        self.partition_fields = Some(fields.to_string());
+        self
+    }
+
+    // Seconds since the response was received from upstream
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.created_at)
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+// The content type depends on whether a hit is replayed as a stream, and upstream cookies belong
+// to the client which made the original request
+fn cacheable_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut cacheable = HeaderMap::new();
+    for (name, value) in headers {
+        let excluded = HOP_HEADERS.contains(name)
+            || name == CONTENT_TYPE
+            || name == SET_COOKIE
+            || name.as_str() == "x-cache-status"
+            || name.as_str().starts_with("x-semcache-");
+        if !excluded {
+            cacheable.append(name.clone(), value.clone());
+        }
+    }
+    cacheable
+}
+
+// OpenAI compatible APIs report `total_tokens`, Anthropic only input and output tokens
+fn usage_tokens(body: &[u8]) -> Option<u64> {
+    let body: Value = serde_json::from_slice(body).ok()?;
+    let usage = body.get("usage")?;
+    let count = |field: &str| usage.get(field).and_then(Value::as_u64);
+    count("total_tokens").or_else(|| Some(count("input_tokens")? + count("output_tokens")?))
+}
+
+fn provider_tag(provider: Option<ProviderType>) -> u8 {
+    match provider {
+        None => 0,
+        Some(ProviderType::OpenAI) => 1,
+        Some(ProviderType::Anthropic) => 2,
+        Some(ProviderType::Generic) => 3,
+    }
+}
+
+impl HeapSize for CachedResponse {
+    fn heap_size_bytes(&self) -> usize {
+        self.headers.heap_size_bytes()
+            + self.body.heap_size_bytes()
+            + self.model.heap_size_bytes()
+            + self.prompt.heap_size_bytes()
+            + self.partition_fields.heap_size_bytes()
+    }
+}
+
+// Responses cached before their usage and latency were stored count as free to recompute
+impl EvictionCost for CachedResponse {
+    fn cost(&self, metric: CostMetric) -> f64 {
+        match metric {
+            CostMetric::Tokens => self.usage_tokens.unwrap_or(0) as f64,
+            CostMetric::Latency => self
+                .upstream_latency
+                .map_or(0.0, |latency| latency.as_secs_f64()),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl CachedResponse {
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u8(writer, VERSION)?;
+        write_u64(writer, u64::from(self.status.as_u16()))?;
+        write_u64(writer, self.headers.len() as u64)?;
+        for (name, value) in &self.headers {
+            write_bytes(writer, name.as_str().as_bytes())?;
+            write_bytes(writer, value.as_bytes())?;
+        }
+        write_bytes(writer, &self.body)?;
+        write_u8(writer, provider_tag(self.provider))?;
+        write_optional_string(writer, self.model.as_deref())?;
+        let created_at = self
+            .created_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        write_u64(writer, created_at.as_millis() as u64)?;
+        write_optional_string(writer, self.prompt.as_deref())?;
+        write_optional_string(writer, self.partition_fields.as_deref())?;
+        write_optional_u64(writer, self.usage_tokens)?;
+        write_optional_u64(
+            writer,
+            self.upstream_latency
+                .map(|latency| latency.as_millis() as u64),
+        )
+    }
+
+    // Expects the magic and version to have been read already
+    fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let status = StatusCode::from_u16(read_u64(reader)? as u16)
+            .map_err(|err| invalid_data(err.to_string()))?;
+        let header_count = read_u64(reader)?;
+        let mut headers = HeaderMap::new();
+        for _ in 0..header_count {
+            let name = HeaderName::from_bytes(&read_bytes(reader)?)
+                .map_err(|err| invalid_data(err.to_string()))?;
+            let value = HeaderValue::from_bytes(&read_bytes(reader)?)
+                .map_err(|err| invalid_data(err.to_string()))?;
+            headers.append(name, value);
+        }
+        let body = read_bytes(reader)?;
+        let provider = match read_u8(reader)? {
+            0 => None,
+            1 => Some(ProviderType::OpenAI),
+            2 => Some(ProviderType::Anthropic),
+            3 => Some(ProviderType::Generic),
+            tag => return Err(invalid_data(format!("Invalid provider tag {tag}"))),
+        };
+        let model = read_optional_string(reader)?;
+        let created_at = UNIX_EPOCH + Duration::from_millis(read_u64(reader)?);
+        let prompt = read_optional_string(reader)?;
+        let partition_fields = read_optional_string(reader)?;
+        let usage_tokens = read_optional_u64(reader)?;
+        let upstream_latency = read_optional_u64(reader)?.map(Duration::from_millis);
+        Ok(Self {
+            status,
+            headers,
+            body,
+            provider,
+            model,
+            created_at,
+            prompt,
+            partition_fields,
+            usage_tokens,
+            upstream_latency,
+        })
+    }
+}
+
+fn write_optional_string(writer: &mut impl Write, string: Option<&str>) -> io::Result<()> {
+    match string {
+        None => write_u8(writer, 0),
+        Some(string) => {
+            write_u8(writer, 1)?;
+            write_bytes(writer, string.as_bytes())
+        }
+    }
+}
+
+fn write_optional_u64(writer: &mut impl Write, value: Option<u64>) -> io::Result<()> {
+    match value {
+        None => write_u8(writer, 0),
+        Some(value) => {
+            write_u8(writer, 1)?;
+            write_u64(writer, value)
+        }
+    }
+}
+
+fn read_optional_u64(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    match read_u8(reader)? {
+        0 => Ok(None),
+        _ => read_u64(reader).map(Some),
+    }
+}
+
+fn read_optional_string(reader: &mut impl Read) -> io::Result<Option<String>> {
+    match read_u8(reader)? {
+        0 => Ok(None),
+        _ => String::from_utf8(read_bytes(reader)?)
+            .map(Some)
+            .map_err(|err| invalid_data(err.to_string())),
+    }
+}
+
+impl From<CachedResponse> for Vec<u8> {
+    fn from(response: CachedResponse) -> Self {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 1 + response.heap_size_bytes() + 64);
+        response
+            .write_to(&mut bytes)
+            .expect("Writing to a vec can't fail");
+        bytes
+    }
+}
+
+impl TryFrom<Vec<u8>> for CachedResponse {
+    type Error = io::Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        match bytes.strip_prefix(MAGIC) {
+            Some([VERSION, encoded @ ..]) => Self::read_from(&mut &encoded[..]),
+            _ => Err(invalid_data(String::from("Unsupported response encoding"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::http::{HeaderMap, StatusCode};
+    use serde_json::json;
+
+    use super::CachedResponse;
+    use crate::providers::ProviderType;
+
+    #[test]
+    fn should_round_trip_through_bytes() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert("openai-processing-ms", "120".parse().unwrap());
+        headers.append("x-ratelimit-remaining-requests", "99".parse().unwrap());
+        let response = CachedResponse {
+            created_at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000),
+            ..CachedResponse::from_upstream(
+                ProviderType::OpenAI,
+                Some(String::from("gpt-4o")),
+                StatusCode::OK,
+                &headers,
+                br#"{"usage":{"total_tokens":42}}"#.to_vec(),
+            )
+            .with_prompt("What is semcache?")
+            .with_partition_fields(&json!({"model": "gpt-4o", "provider": "openai"}))
+            .with_upstream_latency(Duration::from_millis(850))
+        };
+
+        // when
+        let bytes: Vec<u8> = response.clone().into();
+        let decoded = CachedResponse::try_from(bytes).unwrap();
+
+        // then
+        assert_eq!(decoded, response);
+    }
+
+    #[test]
+    fn should_drop_headers_which_are_not_replayed() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", "req_1".parse().unwrap());
+        headers.insert("content-type", "text/event-stream".parse().unwrap());
+        headers.insert("content-length", "2".parse().unwrap());
+        headers.insert("set-cookie", "session=1".parse().unwrap());
+        headers.insert("transfer-encoding", "chunked".parse().unwrap());
+
+        // when
+        let response = CachedResponse::from_upstream(
+            ProviderType::Anthropic,
+            None,
+            StatusCode::OK,
+            &headers,
+            b"{}".to_vec(),
+        );
+
+        // then
+        assert_eq!(response.headers.len(), 1);
+        assert_eq!(response.headers.get("x-request-id").unwrap(), "req_1");
+    }
+
+    #[test]
+    fn should_read_token_usage_from_upstream_bodies() {
+        // given
+        let openai =
+            json!({"usage": {"prompt_tokens": 5, "completion_tokens": 7, "total_tokens": 12}});
+        let anthropic = json!({"usage": {"input_tokens": 3, "output_tokens": 4}});
+
+        // when
+        let from_openai = CachedResponse::from_upstream(
+            ProviderType::OpenAI,
+            None,
+            StatusCode::OK,
+            &HeaderMap::new(),
+            openai.to_string().into_bytes(),
+        );
+        let from_anthropic = CachedResponse::from_upstream(
+            ProviderType::Anthropic,
+            None,
+            StatusCode::OK,
+            &HeaderMap::new(),
+            anthropic.to_string().into_bytes(),
+        );
+
+        // then
+        assert_eq!(from_openai.usage_tokens, Some(12));
+        assert_eq!(from_anthropic.usage_tokens, Some(7));
+    }
+}
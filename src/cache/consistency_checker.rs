@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{MissedTickBehavior, interval};
+use tracing::warn;
+
+use crate::app_state::AppState;
+use crate::cache::background::run_blocking_per_namespace;
+
+// Periodically removes responses without a vector and vectors without a response. Inserts and
+// removals keep both stores in step, but a failure halfway through a removal, or an eviction racing
+// an insert, can leave an entry behind in one of them.
+pub fn spawn_consistency_checker(state: Arc<AppState>, check_interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = interval(check_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            run_blocking_per_namespace(&state, "consistency check", |namespace| {
+                if let Err(err) = namespace.cache.repair_orphans() {
+                    warn!(
+                        error = ?err,
+                        namespace = namespace.name,
+                        "Failed to check cache consistency"
+                    );
+                }
+            })
+            .await;
+        }
+    })
+}
@@ -4,4 +4,7 @@ use thiserror::Error;
 pub enum CacheError {
     #[error("Failed to search through Faiss in-memory store: {0}")]
     FaissRetrievalError(#[from] faiss::error::Error),
+
+    #[error("Failed to read or write persisted cache: {0}")]
+    PersistenceError(#[from] std::io::Error),
 }
@@ -0,0 +1,216 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use tracing::error;
+
+use super::partition::PartitionKey;
+
+// What a cost aware strategy considers expensive to recompute
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostMetric {
+    // tokens upstream reported for the request
+    #[default]
+    Tokens,
+    // time upstream took to respond
+    Latency,
+}
+
+// What recomputing a cached response costs, for the cost aware strategy
+pub trait EvictionCost {
+    fn cost(&self, metric: CostMetric) -> f64;
+}
+
+// Raw bodies don't record what they cost upstream, so they are all equally cheap
+impl EvictionCost for Vec<u8> {
+    fn cost(&self, _metric: CostMetric) -> f64 {
+        0.0
+    }
+}
+
+impl EvictionCost for String {
+    fn cost(&self, _metric: CostMetric) -> f64 {
+        0.0
+    }
+}
+
+// Picks which entry is evicted once the eviction policy's limit is reached. Configured as e.g.
+// `{type: cost_aware, metric: latency}`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EvictionStrategy {
+    // the least recently used entry
+    #[default]
+    Lru,
+    // the least often served entry, the least recently used one of those
+    Lfu,
+    // lru, but a new entry is only admitted if it was asked for more often than the entry it
+    // would evict
+    TinyLfu,
+    // the entry which is cheapest to recompute, weighted by how often it was served
+    CostAware {
+        #[serde(default)]
+        metric: CostMetric,
+    },
+    // the entry which expires first, entries without a time to live last
+    TtlFirst,
+}
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 1 << 14;
+// counters saturate at this, so a burst of requests can't pin an entry forever
+const MAX_COUNT: u8 = 15;
+// all counters are halved after this many additions, so old popularity fades
+const RESET_AFTER: usize = 10 * SKETCH_WIDTH;
+
+// Count-min sketch estimating how often a key was seen recently, in a fixed amount of memory
+struct FrequencySketch {
+    counters: Vec<[u8; SKETCH_WIDTH]>,
+    additions: usize,
+}
+
+impl FrequencySketch {
+    fn new() -> Self {
+        Self {
+            counters: vec![[0; SKETCH_WIDTH]; SKETCH_DEPTH],
+            additions: 0,
+        }
+    }
+
+    fn slot(key: u64, row: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        (key, row).hash(&mut hasher);
+        hasher.finish() as usize % SKETCH_WIDTH
+    }
+
+    fn increment(&mut self, key: u64) -> u8 {
+        for row in 0..SKETCH_DEPTH {
+            let counter = &mut self.counters[row][Self::slot(key, row)];
+            *counter = (*counter + 1).min(MAX_COUNT);
+        }
+        self.additions += 1;
+        if self.additions >= RESET_AFTER {
+            self.reset();
+        }
+        self.estimate(key)
+    }
+
+    fn estimate(&self, key: u64) -> u8 {
+        (0..SKETCH_DEPTH)
+            .map(|row| self.counters[row][Self::slot(key, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn reset(&mut self) {
+        for row in &mut self.counters {
+            row.iter_mut().for_each(|counter| *counter /= 2);
+        }
+        self.additions /= 2;
+    }
+}
+
+// TinyLFU admission: misses are counted by prompt, so a prompt which keeps missing builds up a
+// frequency before its response is inserted, and hits are counted by entry
+pub struct TinyLfuAdmission {
+    sketch: Mutex<FrequencySketch>,
+}
+
+impl Default for TinyLfuAdmission {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TinyLfuAdmission {
+    pub fn new() -> Self {
+        Self {
+            sketch: Mutex::new(FrequencySketch::new()),
+        }
+    }
+
+    fn with_sketch<R>(&self, f: impl FnOnce(&mut FrequencySketch) -> R) -> R {
+        let mut sketch = self.sketch.lock().unwrap_or_else(|err| {
+            error!(error = ?err, "Mutex poisoned");
+            panic!("Frequency sketch mutex poisoned")
+        });
+        f(&mut sketch)
+    }
+
+    // Counts a hit on a cached entry
+    pub fn record_hit(&self, entry_id: u64) {
+        self.with_sketch(|sketch| sketch.increment(entry_key(entry_id)));
+    }
+
+    // Counts a miss of the prompt about to be inserted, returns its estimated frequency
+    pub fn record_miss(&self, partition: PartitionKey, embedding: &[f32]) -> u8 {
+        self.with_sketch(|sketch| sketch.increment(prompt_key(partition, embedding)))
+    }
+
+    // Whether a new entry with the given frequency should replace the victim
+    pub fn admits(&self, frequency: u8, victim_id: u64) -> bool {
+        frequency > self.with_sketch(|sketch| sketch.estimate(entry_key(victim_id)))
+    }
+}
+
+fn entry_key(entry_id: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    entry_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Identical prompts embed identically, so the embedding's bits identify the prompt
+fn prompt_key(partition: PartitionKey, embedding: &[f32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    partition.hash(&mut hasher);
+    for value in embedding {
+        value.to_bits().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{CostMetric, EvictionStrategy, TinyLfuAdmission};
+    use crate::cache::partition::PartitionKey;
+
+    #[test]
+    fn admits_should_prefer_the_more_frequent_key() {
+        // given
+        let admission = TinyLfuAdmission::new();
+        let partition = PartitionKey::default();
+        admission.record_hit(1);
+        admission.record_hit(1);
+
+        // when
+        let first_miss = admission.record_miss(partition, &[0.1, 0.2]);
+        admission.record_miss(partition, &[0.1, 0.2]);
+        let third_miss = admission.record_miss(partition, &[0.1, 0.2]);
+
+        // then
+        assert!(!admission.admits(first_miss, 1));
+        assert!(admission.admits(third_miss, 1));
+        // an entry which was never served is always replaced by a prompt seen before
+        assert!(admission.admits(first_miss, 2));
+    }
+
+    #[test]
+    fn strategy_should_deserialize_from_config() {
+        // given
+        let config = json!({"type": "cost_aware", "metric": "latency"});
+
+        // when
+        let strategy: EvictionStrategy = serde_json::from_value(config).unwrap();
+
+        // then
+        assert_eq!(
+            strategy,
+            EvictionStrategy::CostAware {
+                metric: CostMetric::Latency
+            }
+        );
+    }
+}
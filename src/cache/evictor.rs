@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::app_state::AppState;
+use crate::cache::background::run_blocking_per_namespace;
+
+// Evicts entries whenever an insert signals that its cache reached the eviction policy's limit, so
+// that requests don't wait for eviction. Signals which arrive while evicting are coalesced into one
+// more run.
+pub fn spawn_evictor(state: Arc<AppState>, signal: Arc<Notify>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            signal.notified().await;
+            run_blocking_per_namespace(&state, "eviction", |namespace| {
+                match namespace.cache.evict() {
+                    Ok(0) => {}
+                    Ok(evicted) => debug!(
+                        namespace = namespace.name,
+                        "Evicted {evicted} cache entries"
+                    ),
+                    Err(err) => warn!(
+                        error = ?err,
+                        namespace = namespace.name,
+                        "Failed to evict cache entries"
+                    ),
+                }
+            })
+            .await;
+        }
+    })
+}
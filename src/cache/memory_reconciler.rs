@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{MissedTickBehavior, interval};
+use tracing::{debug, warn};
+
+use crate::app_state::AppState;
+use crate::metrics::metrics::{CACHE_MEMORY_ESTIMATE_BYTES, MEMORY_UNACCOUNTED_BYTES};
+use crate::utils::cgroup_utils::read_cgroup_v2_memory_bytes;
+
+// Periodically compares the memory the caches estimate they take up with the memory the cgroup
+// reports for the process. Memory limits are enforced on the estimate, the difference shows how far
+// off it is (besides the model, buffers and allocator overhead, which it doesn't include).
+pub fn spawn_memory_reconciler(
+    state: Arc<AppState>,
+    reconcile_interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = interval(reconcile_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let estimate: usize = state
+                .namespaces
+                .all()
+                .iter()
+                .map(|namespace| namespace.cache.memory_usage_bytes())
+                .sum();
+            reconcile(estimate as u64, read_cgroup_v2_memory_bytes());
+        }
+    })
+}
+
+// Reports the estimate and the memory it doesn't account for, which is None without a cgroup reading
+fn reconcile(estimate_bytes: u64, cgroup_bytes: Option<u64>) -> Option<i64> {
+    CACHE_MEMORY_ESTIMATE_BYTES.set(estimate_bytes as i64);
+    let Some(cgroup_bytes) = cgroup_bytes else {
+        debug!("could not read cgroup memory usage to reconcile the cache estimate with");
+        return None;
+    };
+    let unaccounted_bytes = cgroup_bytes as i64 - estimate_bytes as i64;
+    MEMORY_UNACCOUNTED_BYTES.set(unaccounted_bytes);
+    if unaccounted_bytes < 0 {
+        warn!(
+            estimate_bytes,
+            cgroup_bytes, "Cache memory estimate exceeds the memory used by the process"
+        );
+    }
+    Some(unaccounted_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reconcile;
+    use crate::metrics::metrics::MEMORY_UNACCOUNTED_BYTES;
+
+    #[test]
+    fn reconcile_should_report_memory_besides_the_estimate() {
+        // when
+        let unaccounted = reconcile(300, Some(1000));
+        let unknown = reconcile(300, None);
+
+        // then
+        assert_eq!(unaccounted, Some(700));
+        assert_eq!(unknown, None);
+        // the last reading is kept
+        assert_eq!(MEMORY_UNACCOUNTED_BYTES.get(), 700);
+    }
+}
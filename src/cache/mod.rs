@@ -1,5 +1,16 @@
+pub mod background;
 pub mod cache;
 pub mod cache_impl;
+pub mod cached_response;
+pub mod consistency_checker;
 pub mod error;
-pub(crate) mod response_store;
+pub mod eviction;
+pub mod evictor;
+pub mod memory_reconciler;
+pub mod partition;
+pub mod persistence;
+pub mod reaper;
+pub mod response_store;
 pub mod semantic_store;
+pub mod size;
+pub mod snapshotter;
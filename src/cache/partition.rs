@@ -0,0 +1,75 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+// Scopes semantic lookups, only entries inserted under the same partition key are eligible for a hit.
+// Derived from a stable hash so that keys stay the same across restarts and versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PartitionKey(pub u64);
+
+impl PartitionKey {
+    // Hashes the canonical json encoding of `fields`. serde_json sorts object keys, so the key
+    // doesn't depend on the order in which the fields appeared in the request.
+    pub fn from_fields(fields: &Value) -> Self {
+        let digest = Sha256::digest(fields.to_string().as_bytes());
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&digest[..8]);
+        Self(u64::from_be_bytes(prefix))
+    }
+}
+
+// Partition keys are exposed as 16 hex digits, which is how the API accepts them back
+impl fmt::Display for PartitionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for PartitionKey {
+    type Err = ParseIntError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(value, 16).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::PartitionKey;
+
+    #[test]
+    fn from_fields_should_ignore_key_order() {
+        let first = json!({"model": "gpt-4o", "temperature": 0.2});
+        let second = json!({"temperature": 0.2, "model": "gpt-4o"});
+
+        assert_eq!(
+            PartitionKey::from_fields(&first),
+            PartitionKey::from_fields(&second)
+        );
+    }
+
+    #[test]
+    fn should_round_trip_through_hex() {
+        let key = PartitionKey::from_fields(&json!({"model": "gpt-4o"}));
+
+        assert_eq!(key.to_string().len(), 16);
+        assert_eq!(key.to_string().parse::<PartitionKey>().unwrap(), key);
+        assert!("not hex".parse::<PartitionKey>().is_err());
+    }
+
+    #[test]
+    fn from_fields_should_differ_on_any_value() {
+        let first = json!({"model": "gpt-4o", "temperature": 0.2});
+        let second = json!({"model": "gpt-4o", "temperature": 0.7});
+
+        assert_ne!(
+            PartitionKey::from_fields(&first),
+            PartitionKey::from_fields(&second)
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Primitives shared by the log and snapshot formats, all integers are little endian
+
+pub fn write_u8(writer: &mut impl Write, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
+
+pub fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+pub fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u64(writer, bytes.len() as u64)?;
+    writer.write_all(bytes)
+}
+
+pub fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u64(reader)? as usize;
+    let mut bytes = Vec::new();
+    // read through `take` rather than allocating `len` upfront, so a corrupt length fails with
+    // an unexpected eof instead of an attempt to allocate an arbitrary amount of memory
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+pub fn write_floats(writer: &mut impl Write, floats: &[f32]) -> io::Result<()> {
+    write_u64(writer, floats.len() as u64)?;
+    for float in floats {
+        writer.write_all(&float.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_floats(reader: &mut impl Read) -> io::Result<Vec<f32>> {
+    let len = read_u64(reader)? as usize;
+    let mut floats = Vec::new();
+    let mut buf = [0u8; 4];
+    for _ in 0..len {
+        reader.read_exact(&mut buf)?;
+        floats.push(f32::from_le_bytes(buf));
+    }
+    Ok(floats)
+}
+
+// stored as a presence flag followed by milliseconds since the unix epoch
+pub fn write_optional_time(writer: &mut impl Write, time: Option<SystemTime>) -> io::Result<()> {
+    match time {
+        None => write_u8(writer, 0),
+        Some(time) => {
+            let millis = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_millis() as u64;
+            write_u8(writer, 1)?;
+            write_u64(writer, millis)
+        }
+    }
+}
+
+pub fn read_optional_time(reader: &mut impl Read) -> io::Result<Option<SystemTime>> {
+    match read_u8(reader)? {
+        0 => Ok(None),
+        1 => Ok(Some(UNIX_EPOCH + Duration::from_millis(read_u64(reader)?))),
+        flag => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid time flag {flag}"),
+        )),
+    }
+}
@@ -0,0 +1,313 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tracing::{info, warn};
+
+use self::record::Record;
+use self::snapshot::ResponseSnapshot;
+use crate::cache::error::CacheError;
+
+pub(crate) mod codec;
+pub mod record;
+pub mod snapshot;
+
+// Layout of the persistence directory:
+//   snapshot/          the latest complete snapshot, responses plus the vectors of the semantic store
+//   snapshot.tmp/      a snapshot which is still being written
+//   snapshot.old/      the previous snapshot, while it's being replaced
+//   log-<seq>.bin      changes since the snapshot, replayed in order of their sequence number
+const SNAPSHOT_DIR: &str = "snapshot";
+const SNAPSHOT_TMP_DIR: &str = "snapshot.tmp";
+const SNAPSHOT_OLD_DIR: &str = "snapshot.old";
+const RESPONSES_FILE: &str = "responses.bin";
+const VECTORS_DIR: &str = "vectors";
+const LOG_PREFIX: &str = "log-";
+const LOG_SUFFIX: &str = ".bin";
+
+const MUTEX_PANIC: &str = "Mutex poisoned, persisted cache might be incomplete, panicking";
+
+struct Log {
+    seq: u64,
+    writer: BufWriter<File>,
+}
+
+impl Log {
+    fn create(directory: &Path, seq: u64) -> Result<Self, CacheError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path(directory, seq))?;
+        Ok(Self {
+            seq,
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+// Keeps the cache on disk as periodic snapshots plus a log of the changes made since the last one.
+pub struct Persistence {
+    directory: PathBuf,
+    log: Mutex<Log>,
+    // held while writing a snapshot, so two snapshots never race on the same files
+    snapshot_lock: Mutex<()>,
+}
+
+impl Persistence {
+    // Opens (or creates) the persistence directory. Changes are appended to a new log, the
+    // existing logs are left in place for restore to replay.
+    pub fn open(directory: impl Into<PathBuf>) -> Result<Self, CacheError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        // a leftover temporary snapshot was never completed, and an old snapshot is only left
+        // behind if we stopped in the middle of replacing it
+        remove_dir_if_exists(&directory.join(SNAPSHOT_TMP_DIR))?;
+        let old_snapshot = directory.join(SNAPSHOT_OLD_DIR);
+        if old_snapshot.exists() {
+            if directory.join(SNAPSHOT_DIR).exists() {
+                fs::remove_dir_all(&old_snapshot)?;
+            } else {
+                fs::rename(&old_snapshot, directory.join(SNAPSHOT_DIR))?;
+            }
+        }
+
+        let seq = log_seqs(&directory)?.last().map_or(0, |seq| seq + 1);
+        let log = Log::create(&directory, seq)?;
+        Ok(Self {
+            directory,
+            log: Mutex::new(log),
+            snapshot_lock: Mutex::new(()),
+        })
+    }
+
+    // Records are flushed to the OS straight away, so they survive the process crashing
+    pub fn append(&self, record: &Record) -> Result<(), CacheError> {
+        let mut log = self.log.lock().expect(MUTEX_PANIC);
+        record.write_to(&mut log.writer)?;
+        log.writer.flush()?;
+        Ok(())
+    }
+
+    // Returns None if no snapshot has been written yet
+    pub fn read_snapshot(&self) -> Result<Option<ResponseSnapshot>, CacheError> {
+        let path = self.directory.join(SNAPSHOT_DIR).join(RESPONSES_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(File::open(path)?);
+        Ok(Some(ResponseSnapshot::read_from(&mut reader)?))
+    }
+
+    // Where the semantic store saved its vectors in the latest snapshot
+    pub fn vectors_dir(&self) -> PathBuf {
+        self.directory.join(SNAPSHOT_DIR).join(VECTORS_DIR)
+    }
+
+    // Replays every record of the logs starting at `from_seq`, oldest first. The log currently
+    // being appended to is never replayed.
+    pub fn replay<F>(&self, from_seq: u64, mut apply: F) -> Result<usize, CacheError>
+    where
+        F: FnMut(Record) -> Result<(), CacheError>,
+    {
+        let current_seq = self.log.lock().expect(MUTEX_PANIC).seq;
+        let mut replayed = 0;
+        for seq in log_seqs(&self.directory)? {
+            if seq < from_seq || seq >= current_seq {
+                continue;
+            }
+            let mut reader = BufReader::new(File::open(log_path(&self.directory, seq))?);
+            while let Some(record) = Record::read_from(&mut reader)? {
+                apply(record)?;
+                replayed += 1;
+            }
+        }
+        Ok(replayed)
+    }
+
+    // Writes a new snapshot and removes the logs it makes redundant. Appends are first switched
+    // over to a new log, whose sequence number is passed to `capture`. Anything changed in memory
+    // before that is part of the captured state, anything changed after is in the new log.
+    // `save_vectors` is given the directory the semantic store should save its vectors to.
+    pub fn write_snapshot<C, V>(&self, capture: C, save_vectors: V) -> Result<(), CacheError>
+    where
+        C: FnOnce(u64) -> ResponseSnapshot,
+        V: FnOnce(&Path) -> Result<(), CacheError>,
+    {
+        let _snapshot_guard = self.snapshot_lock.lock().expect(MUTEX_PANIC);
+
+        let log_seq = {
+            let mut log = self.log.lock().expect(MUTEX_PANIC);
+            let next = Log::create(&self.directory, log.seq + 1)?;
+            log.writer.flush()?;
+            *log = next;
+            log.seq
+        };
+        let snapshot = capture(log_seq);
+
+        let tmp_dir = self.directory.join(SNAPSHOT_TMP_DIR);
+        remove_dir_if_exists(&tmp_dir)?;
+        fs::create_dir_all(tmp_dir.join(VECTORS_DIR))?;
+        let mut writer = BufWriter::new(File::create(tmp_dir.join(RESPONSES_FILE))?);
+        snapshot.write_to(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        save_vectors(&tmp_dir.join(VECTORS_DIR))?;
+
+        // swap in the new snapshot, open picks up the old one if we stop in between
+        let snapshot_dir = self.directory.join(SNAPSHOT_DIR);
+        let old_dir = self.directory.join(SNAPSHOT_OLD_DIR);
+        if snapshot_dir.exists() {
+            fs::rename(&snapshot_dir, &old_dir)?;
+        }
+        fs::rename(&tmp_dir, &snapshot_dir)?;
+        remove_dir_if_exists(&old_dir)?;
+
+        for seq in log_seqs(&self.directory)?
+            .into_iter()
+            .filter(|seq| *seq < log_seq)
+        {
+            if let Err(err) = fs::remove_file(log_path(&self.directory, seq)) {
+                warn!(error = ?err, seq, "Failed to remove log contained in snapshot");
+            }
+        }
+        info!(
+            "Wrote snapshot of {} entries to {:?}",
+            snapshot.entries.len(),
+            snapshot_dir
+        );
+        Ok(())
+    }
+}
+
+fn log_path(directory: &Path, seq: u64) -> PathBuf {
+    directory.join(format!("{LOG_PREFIX}{seq:020}{LOG_SUFFIX}"))
+}
+
+// sequence numbers of all logs in `directory`, in ascending order
+fn log_seqs(directory: &Path) -> Result<Vec<u64>, CacheError> {
+    let mut seqs = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let file_name = entry?.file_name();
+        let seq = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(LOG_PREFIX))
+            .and_then(|name| name.strip_suffix(LOG_SUFFIX))
+            .and_then(|seq| seq.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            seqs.push(seq);
+        }
+    }
+    seqs.sort_unstable();
+    Ok(seqs)
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<(), CacheError> {
+    if path.exists() {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::Persistence;
+    use super::record::Record;
+    use super::snapshot::{ResponseSnapshot, SnapshotEntry};
+    use crate::cache::response_store::HitStats;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("semcache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn replay_all(persistence: &Persistence, from_seq: u64) -> Vec<Record> {
+        let mut records = Vec::new();
+        persistence
+            .replay(from_seq, |record| {
+                records.push(record);
+                Ok(())
+            })
+            .unwrap();
+        records
+    }
+
+    #[test]
+    fn replay_should_return_records_of_previous_runs() {
+        // given
+        let dir = temp_dir("replay");
+        let first_run = Persistence::open(&dir).unwrap();
+        first_run.append(&Record::Remove { id: 1 }).unwrap();
+        first_run.append(&Record::Remove { id: 2 }).unwrap();
+        drop(first_run);
+
+        // when
+        let second_run = Persistence::open(&dir).unwrap();
+        second_run.append(&Record::Remove { id: 3 }).unwrap();
+        let replayed = replay_all(&second_run, 0);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // then - the log of the current run isn't replayed
+        assert_eq!(
+            replayed,
+            vec![Record::Remove { id: 1 }, Record::Remove { id: 2 }]
+        );
+    }
+
+    #[test]
+    fn write_snapshot_should_only_leave_later_records_to_replay() {
+        // given
+        let dir = temp_dir("snapshot");
+        let first_run = Persistence::open(&dir).unwrap();
+        first_run.append(&Record::Remove { id: 1 }).unwrap();
+
+        // when
+        first_run
+            .write_snapshot(
+                |log_seq| ResponseSnapshot {
+                    next_id: 5,
+                    log_seq,
+                    entries: vec![SnapshotEntry {
+                        id: 4,
+                        response: b"Paris".to_vec(),
+                        expires_at: None,
+                        stats: HitStats {
+                            hits: 3,
+                            last_hit_at: Some(UNIX_EPOCH + Duration::from_millis(1_000)),
+                        },
+                    }],
+                },
+                |vectors_dir| {
+                    assert!(vectors_dir.is_dir());
+                    Ok(())
+                },
+            )
+            .unwrap();
+        first_run.append(&Record::Remove { id: 4 }).unwrap();
+        drop(first_run);
+
+        // then
+        let second_run = Persistence::open(&dir).unwrap();
+        let snapshot = second_run.read_snapshot().unwrap().unwrap();
+        let replayed = replay_all(&second_run, snapshot.log_seq);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(snapshot.next_id, 5);
+        assert_eq!(snapshot.entries.len(), 1);
+        assert_eq!(snapshot.entries[0].response, b"Paris".to_vec());
+        assert_eq!(snapshot.entries[0].stats.hits, 3);
+        assert_eq!(
+            snapshot.entries[0].stats.last_hit_at,
+            Some(UNIX_EPOCH + Duration::from_millis(1_000))
+        );
+        assert_eq!(replayed, vec![Record::Remove { id: 4 }]);
+    }
+}
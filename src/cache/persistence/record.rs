@@ -0,0 +1,176 @@
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
+
+use super::codec::{
+    read_bytes, read_floats, read_optional_time, read_u8, read_u64, write_bytes, write_floats,
+    write_optional_time, write_u8, write_u64,
+};
+use crate::cache::partition::PartitionKey;
+
+const INSERT_TAG: u8 = 1;
+const UPDATE_TAG: u8 = 2;
+const REMOVE_TAG: u8 = 3;
+
+// A change to the cache, appended to the log after it was applied in memory. Replaying a record
+// on top of a snapshot which already contains the change leaves the cache unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Insert {
+        id: u64,
+        partition: PartitionKey,
+        embedding: Vec<f32>,
+        response: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    },
+    Update {
+        id: u64,
+        response: Vec<u8>,
+        expires_at: Option<SystemTime>,
+    },
+    // the entry was evicted or expired
+    Remove {
+        id: u64,
+    },
+}
+
+impl Record {
+    pub fn id(&self) -> u64 {
+        match self {
+            Record::Insert { id, .. } | Record::Update { id, .. } | Record::Remove { id } => *id,
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Record::Insert {
+                id,
+                partition,
+                embedding,
+                response,
+                expires_at,
+            } => {
+                write_u8(writer, INSERT_TAG)?;
+                write_u64(writer, *id)?;
+                write_u64(writer, partition.0)?;
+                write_floats(writer, embedding)?;
+                write_bytes(writer, response)?;
+                write_optional_time(writer, *expires_at)
+            }
+            Record::Update {
+                id,
+                response,
+                expires_at,
+            } => {
+                write_u8(writer, UPDATE_TAG)?;
+                write_u64(writer, *id)?;
+                write_bytes(writer, response)?;
+                write_optional_time(writer, *expires_at)
+            }
+            Record::Remove { id } => {
+                write_u8(writer, REMOVE_TAG)?;
+                write_u64(writer, *id)
+            }
+        }
+    }
+
+    // Returns None at the end of the log. A record that was cut short by a crash while it was
+    // being written also ends the log, rather than failing the whole replay.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<Record>> {
+        let tag = match read_u8(reader) {
+            Ok(tag) => tag,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        match Self::read_body(tag, reader) {
+            Ok(record) => Ok(Some(record)),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_body(tag: u8, reader: &mut impl Read) -> io::Result<Record> {
+        match tag {
+            INSERT_TAG => Ok(Record::Insert {
+                id: read_u64(reader)?,
+                partition: PartitionKey(read_u64(reader)?),
+                embedding: read_floats(reader)?,
+                response: read_bytes(reader)?,
+                expires_at: read_optional_time(reader)?,
+            }),
+            UPDATE_TAG => Ok(Record::Update {
+                id: read_u64(reader)?,
+                response: read_bytes(reader)?,
+                expires_at: read_optional_time(reader)?,
+            }),
+            REMOVE_TAG => Ok(Record::Remove {
+                id: read_u64(reader)?,
+            }),
+            tag => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown log record tag {tag}"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::Record;
+    use crate::cache::partition::PartitionKey;
+
+    #[test]
+    fn records_should_survive_a_round_trip() {
+        let records = vec![
+            Record::Insert {
+                id: 7,
+                partition: PartitionKey(42),
+                embedding: vec![0.1, -0.2, 0.3],
+                response: b"Paris".to_vec(),
+                expires_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            },
+            Record::Update {
+                id: 7,
+                response: b"Paris, France".to_vec(),
+                expires_at: None,
+            },
+            Record::Remove { id: 7 },
+        ];
+        let mut log = Vec::new();
+        for record in &records {
+            record.write_to(&mut log).unwrap();
+        }
+
+        let mut reader = Cursor::new(log);
+        let mut replayed = Vec::new();
+        while let Some(record) = Record::read_from(&mut reader).unwrap() {
+            replayed.push(record);
+        }
+
+        assert_eq!(replayed, records);
+    }
+
+    #[test]
+    fn read_from_should_ignore_a_truncated_record() {
+        let mut log = Vec::new();
+        Record::Remove { id: 1 }.write_to(&mut log).unwrap();
+        Record::Update {
+            id: 2,
+            response: b"cut short".to_vec(),
+            expires_at: None,
+        }
+        .write_to(&mut log)
+        .unwrap();
+        log.truncate(log.len() - 4);
+
+        let mut reader = Cursor::new(log);
+
+        assert_eq!(
+            Record::read_from(&mut reader).unwrap(),
+            Some(Record::Remove { id: 1 })
+        );
+        assert_eq!(Record::read_from(&mut reader).unwrap(), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
+
+use super::codec::{
+    read_bytes, read_optional_time, read_u64, write_bytes, write_optional_time, write_u64,
+};
+use crate::cache::response_store::HitStats;
+
+const MAGIC: &[u8; 8] = b"SEMCACHE";
+const VERSION: u64 = 1;
+
+pub struct SnapshotEntry {
+    pub id: u64,
+    pub response: Vec<u8>,
+    pub expires_at: Option<SystemTime>,
+    pub stats: HitStats,
+}
+
+// The responses of the cache at the time of a snapshot. The vectors are saved next to it by the
+// semantic store.
+pub struct ResponseSnapshot {
+    // the next id the cache would have handed out
+    pub next_id: u64,
+    // the first log which isn't contained in the snapshot, and has to be replayed on top of it
+    pub log_seq: u64,
+    // ordered from least to most recently used, so inserting them in order restores the lru order
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl ResponseSnapshot {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u64(writer, VERSION)?;
+        write_u64(writer, self.next_id)?;
+        write_u64(writer, self.log_seq)?;
+        write_u64(writer, self.entries.len() as u64)?;
+        for entry in &self.entries {
+            write_u64(writer, entry.id)?;
+            write_bytes(writer, &entry.response)?;
+            write_optional_time(writer, entry.expires_at)?;
+            write_u64(writer, entry.stats.hits)?;
+            write_optional_time(writer, entry.stats.last_hit_at)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        let version = read_u64(reader)?;
+        if &magic != MAGIC || version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported snapshot format, version {version}"),
+            ));
+        }
+        let next_id = read_u64(reader)?;
+        let log_seq = read_u64(reader)?;
+        let len = read_u64(reader)?;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let id = read_u64(reader)?;
+            let response = read_bytes(reader)?;
+            let expires_at = read_optional_time(reader)?;
+            let stats = HitStats {
+                hits: read_u64(reader)?,
+                last_hit_at: read_optional_time(reader)?,
+            };
+            entries.push(SnapshotEntry {
+                id,
+                response,
+                expires_at,
+                stats,
+            });
+        }
+        Ok(Self {
+            next_id,
+            log_seq,
+            entries,
+        })
+    }
+}
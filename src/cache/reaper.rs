@@ -0,0 +1,36 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{MissedTickBehavior, interval};
+use tracing::{debug, warn};
+
+use crate::app_state::AppState;
+use crate::cache::background::run_blocking_per_namespace;
+
+// Periodically removes expired entries from the cache. Expired entries are already treated as misses
+// on lookup, this only frees the memory they (and their vectors) take up.
+pub fn spawn_expiry_reaper(state: Arc<AppState>, reap_interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = interval(reap_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            run_blocking_per_namespace(&state, "expiry reaper", |namespace| {
+                match namespace.cache.remove_expired() {
+                    Ok(0) => {}
+                    Ok(removed) => debug!(
+                        namespace = namespace.name,
+                        "Reaped {removed} expired cache entries"
+                    ),
+                    Err(err) => warn!(
+                        error = ?err,
+                        namespace = namespace.name,
+                        "Failed to reap expired cache entries"
+                    ),
+                }
+            })
+            .await;
+        }
+    })
+}
@@ -1,11 +1,72 @@
 use lru::LruCache;
+use ordered_float::OrderedFloat;
+use std::collections::BinaryHeap;
 use std::mem::size_of;
-use std::sync::atomic::AtomicUsize;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::error;
 
+use super::eviction::{EvictionCost, EvictionStrategy};
+use super::size::HeapSize;
+
+// Entries are spread over this many independently locked lru lists, by id
+const SHARD_COUNT: usize = 16;
+
+// How often an entry was served, kept across updates of its response and persisted with snapshots
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HitStats {
+    pub hits: u64,
+    // None if the entry was never served
+    pub last_hit_at: Option<SystemTime>,
+}
+
+// Lookups and hits only hold a shared lock on their shard, so everything they update is atomic
 struct EntryMetadata {
     size_bytes: usize,
+    // None if the entry never expires
+    expires_at: Option<SystemTime>,
+    hits: AtomicU64,
+    // nanoseconds since the epoch, 0 if the entry was never served
+    last_hit_nanos: AtomicU64,
+    // the recency tick of the entry's last put or lookup
+    last_used: AtomicU64,
+    // the recency tick the entry's position in the lru list reflects, lookups don't move entries
+    ordered_at: u64,
+}
+
+impl EntryMetadata {
+    fn new(size_bytes: usize, expires_at: Option<SystemTime>, stats: HitStats, tick: u64) -> Self {
+        let last_hit_nanos = stats.last_hit_at.map_or(0, |at| {
+            at.duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+        });
+        Self {
+            size_bytes,
+            expires_at,
+            hits: AtomicU64::new(stats.hits),
+            last_hit_nanos: AtomicU64::new(last_hit_nanos),
+            last_used: AtomicU64::new(tick),
+            ordered_at: tick,
+        }
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn stats(&self) -> HitStats {
+        let last_hit_nanos = self.last_hit_nanos.load(Ordering::Relaxed);
+        HitStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            last_hit_at: (last_hit_nanos > 0)
+                .then(|| UNIX_EPOCH + Duration::from_nanos(last_hit_nanos)),
+        }
+    }
+
+    fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
 }
 
 struct CacheEntry<T> {
@@ -13,144 +74,498 @@ struct CacheEntry<T> {
     metadata: EntryMetadata,
 }
 
+// An entry as copied out of the store
+pub struct StoredEntry<T> {
+    pub id: u64,
+    pub response: T,
+    pub expires_at: Option<SystemTime>,
+    pub stats: HitStats,
+}
+
+type Shard<T> = LruCache<u64, CacheEntry<T>>;
+
+// Recency is approximate: lookups only record when an entry was used, and entries are moved to the
+// front of their shard's list once they reach its back, when an entry has to be evicted. This keeps
+// lookups from serialising on an exclusive lock.
 pub struct ResponseStore<T> {
-    cache: Arc<Mutex<LruCache<u64, CacheEntry<T>>>>,
-    total_size_bytes: Arc<AtomicUsize>,
+    shards: Vec<RwLock<Shard<T>>>,
+    // source of recency ticks, advanced by every put and lookup
+    clock: AtomicU64,
+    total_size_bytes: AtomicUsize,
+}
+
+const LOCK_PANIC: &str = "RwLock poisoned, unrecoverable error in response_store";
+
+fn read_shard<T>(shard: &RwLock<Shard<T>>) -> RwLockReadGuard<'_, Shard<T>> {
+    shard.read().unwrap_or_else(|err| {
+        error!(error = ?err, "RwLock poisoned");
+        panic!("{}", LOCK_PANIC)
+    })
+}
+
+fn write_shard<T>(shard: &RwLock<Shard<T>>) -> RwLockWriteGuard<'_, Shard<T>> {
+    shard.write().unwrap_or_else(|err| {
+        error!(error = ?err, "RwLock poisoned");
+        panic!("{}", LOCK_PANIC)
+    })
 }
 
-const MUTEX_PANIC: &str = "Mutex attempted to get grabbed twice by the same thread, unrecoverable error in response_store";
+impl<T: Clone + HeapSize + EvictionCost + 'static> Default for ResponseStore<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl<T: Clone + 'static> ResponseStore<T> {
-    // Compile-time constant for base entry size
-    const BASE_ENTRY_SIZE: usize = size_of::<CacheEntry<T>>();
+impl<T: Clone + HeapSize + EvictionCost + 'static> ResponseStore<T> {
+    // An lru entry is a node holding the key, the entry and the two list pointers, plus a hash table
+    // slot with a reference to the key, a pointer to the node and a control byte
+    const BASE_ENTRY_SIZE: usize = size_of::<u64>()
+        + size_of::<CacheEntry<T>>()
+        + 2 * size_of::<usize>()
+        + 2 * size_of::<usize>()
+        + 1;
 
     // Creates a new ResponseStore for a generic response type. Does not automatically evict
     // items so those operations need to be performed by the orchestrator.
     pub fn new() -> Self {
         Self {
-            cache: Arc::new(Mutex::new(LruCache::unbounded())),
-            total_size_bytes: Arc::new(AtomicUsize::new(0)),
+            shards: (0..SHARD_COUNT)
+                .map(|_| RwLock::new(LruCache::unbounded()))
+                .collect(),
+            clock: AtomicU64::new(0),
+            total_size_bytes: AtomicUsize::new(0),
         }
     }
 
+    fn shard(&self, id: u64) -> &RwLock<Shard<T>> {
+        &self.shards[id as usize % SHARD_COUNT]
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    // Expired entries are treated as absent, but are left in place for remove_expired to clean up
     pub fn get(&self, id: u64) -> Option<T> {
-        let mut cache = self.cache.lock().unwrap_or_else(|err| {
-            error!(error = ?err, "Mutex poisoned");
-            panic!("{}", MUTEX_PANIC)
-        });
-        let response = &cache.get_mut(&id)?.response;
-        Some(response.clone())
+        let shard = read_shard(self.shard(id));
+        let entry = shard.peek(&id)?;
+        if entry.metadata.is_expired(SystemTime::now()) {
+            return None;
+        }
+        entry
+            .metadata
+            .last_used
+            .fetch_max(self.tick(), Ordering::Relaxed);
+        Some(entry.response.clone())
+    }
+
+    // `expires_at` is None for entries which should never expire. Replacing an entry keeps its
+    // hit stats.
+    pub fn put(&self, id: u64, response: T, expires_at: Option<SystemTime>) {
+        self.put_entry(id, response, expires_at, None, false);
+    }
+
+    // Like put, but only if the entry still exists, returns whether it did. Checking and replacing
+    // under one lock keeps a concurrent eviction from being undone.
+    pub fn replace(&self, id: u64, response: T, expires_at: Option<SystemTime>) -> bool {
+        self.put_entry(id, response, expires_at, None, true)
     }
 
-    pub fn put(&self, id: u64, response: T) {
+    // Puts an entry with the stats it had when it was persisted
+    pub fn put_with_stats(
+        &self,
+        id: u64,
+        response: T,
+        expires_at: Option<SystemTime>,
+        stats: HitStats,
+    ) {
+        self.put_entry(id, response, expires_at, Some(stats), false);
+    }
+
+    fn put_entry(
+        &self,
+        id: u64,
+        response: T,
+        expires_at: Option<SystemTime>,
+        stats: Option<HitStats>,
+        existing_only: bool,
+    ) -> bool {
         let size_bytes = self.calculate_entry_size(&response);
-        let entry = CacheEntry {
-            response,
-            metadata: EntryMetadata { size_bytes },
-        };
 
-        let mut cache = self.cache.lock().unwrap_or_else(|err| {
-            error!(error = ?err, "Mutex poisoned");
-            panic!("{}", MUTEX_PANIC)
-        });
+        let mut shard = write_shard(self.shard(id));
+        if existing_only && !shard.contains(&id) {
+            return false;
+        }
 
         // If the cache contains the id, we need to get its byte_size to maintain the total_size_bytes
-        let old_size = cache
+        let (old_size, old_stats) = shard
             .peek(&id)
-            .map(|entry| entry.metadata.size_bytes)
-            .unwrap_or(0);
+            .map(|entry| (entry.metadata.size_bytes, entry.metadata.stats()))
+            .unwrap_or_default();
 
-        cache.put(id, entry);
+        let entry = CacheEntry {
+            response,
+            metadata: EntryMetadata::new(
+                size_bytes,
+                expires_at,
+                stats.unwrap_or(old_stats),
+                self.tick(),
+            ),
+        };
+        shard.put(id, entry);
 
         let size_delta = size_bytes as i64 - old_size as i64;
 
         // Avoid casting negative i64 to usize which could wrap to huge number and cause fetch_add to overflow incorrectly
         if size_delta > 0 {
             self.total_size_bytes
-                .fetch_add(size_delta as usize, std::sync::atomic::Ordering::Relaxed);
+                .fetch_add(size_delta as usize, Ordering::Relaxed);
         } else if size_delta < 0 {
             self.total_size_bytes
-                .fetch_sub((-size_delta) as usize, std::sync::atomic::Ordering::Relaxed);
+                .fetch_sub((-size_delta) as usize, Ordering::Relaxed);
         }
+        true
     }
 
-    pub fn pop(&self) -> Option<u64> {
-        let mut cache = self.cache.lock().unwrap_or_else(|err| {
-            error!(error = ?err, "Mutex poisoned");
-            panic!("{}", MUTEX_PANIC)
-        });
-        if let Some((id, entry)) = cache.pop_lru() {
-            self.total_size_bytes.fetch_sub(
-                entry.metadata.size_bytes,
-                std::sync::atomic::Ordering::Relaxed,
-            );
-            Some(id)
+    // Counts a hit on the entry with the given id, returns whether it exists
+    pub fn record_hit(&self, id: u64, at: SystemTime) -> bool {
+        let shard = read_shard(self.shard(id));
+        let Some(entry) = shard.peek(&id) else {
+            return false;
+        };
+        let at_nanos = at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64);
+        entry.metadata.hits.fetch_add(1, Ordering::Relaxed);
+        entry
+            .metadata
+            .last_hit_nanos
+            .fetch_max(at_nanos, Ordering::Relaxed);
+        true
+    }
+
+    // The id of the entry the strategy would evict next, skipping `excluded`
+    pub fn peek_victim(&self, strategy: &EvictionStrategy, excluded: Option<u64>) -> Option<u64> {
+        match strategy {
+            EvictionStrategy::Lru | EvictionStrategy::TinyLfu => self.lru_victim(excluded),
+            _ => self
+                .ranked_victims(strategy, excluded, 1)
+                .into_iter()
+                .next(),
+        }
+    }
+
+    // Removes the entry the strategy picks, skipping `excluded`, and returns its id
+    pub fn evict(&self, strategy: &EvictionStrategy, excluded: Option<u64>) -> Option<u64> {
+        // the victim might be removed by someone else before it's evicted, then the next one is
+        loop {
+            let id = self.peek_victim(strategy, excluded)?;
+            if self.remove(id) {
+                return Some(id);
+            }
+        }
+    }
+
+    // Removes up to `count` entries the strategy picks, skipping `excluded`, and returns their ids.
+    // Ranked strategies pick the whole batch in one scan over the entries.
+    pub fn evict_batch(
+        &self,
+        strategy: &EvictionStrategy,
+        excluded: Option<u64>,
+        count: usize,
+    ) -> Vec<u64> {
+        if matches!(strategy, EvictionStrategy::Lru | EvictionStrategy::TinyLfu) {
+            return (0..count)
+                .map_while(|_| self.evict(strategy, excluded))
+                .collect();
+        }
+        let mut evicted = Vec::with_capacity(count);
+        // victims removed by someone else in the meantime are made up for by another scan
+        while evicted.len() < count {
+            let victims = self.ranked_victims(strategy, excluded, count - evicted.len());
+            if victims.is_empty() {
+                break;
+            }
+            evicted.extend(victims.into_iter().filter(|id| self.remove(*id)));
+        }
+        evicted
+    }
+
+    // The least recently used of the shards' last entries. A shard's last entry is only exact
+    // once entries which were looked up since they were ordered are moved to the front.
+    fn lru_victim(&self, excluded: Option<u64>) -> Option<u64> {
+        self.shards
+            .iter()
+            .filter_map(|shard| {
+                let mut shard = write_shard(shard);
+                Self::reorder_back(&mut shard);
+                shard
+                    .iter()
+                    .rev()
+                    .find(|(id, _)| Some(**id) != excluded)
+                    .map(|(id, entry)| (entry.metadata.last_used(), *id))
+            })
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    fn reorder_back(shard: &mut Shard<T>) {
+        while let Some((&id, entry)) = shard.peek_lru() {
+            let last_used = entry.metadata.last_used();
+            if last_used <= entry.metadata.ordered_at {
+                return;
+            }
+            // moves the entry to the front
+            if let Some(entry) = shard.get_mut(&id) {
+                entry.metadata.ordered_at = last_used;
+            }
+        }
+    }
+
+    // The `count` lowest ranked entries, the least recently used ones of those on a tie, lowest first
+    fn ranked_victims(
+        &self,
+        strategy: &EvictionStrategy,
+        excluded: Option<u64>,
+        count: usize,
+    ) -> Vec<u64> {
+        let rank = |entry: &CacheEntry<T>| -> f64 {
+            let hits = entry.metadata.hits.load(Ordering::Relaxed);
+            match strategy {
+                EvictionStrategy::Lfu => hits as f64,
+                EvictionStrategy::CostAware { metric } => {
+                    entry.response.cost(*metric) * (hits + 1) as f64
+                }
+                EvictionStrategy::TtlFirst => {
+                    entry.metadata.expires_at.map_or(f64::INFINITY, |at| {
+                        at.duration_since(UNIX_EPOCH)
+                            .unwrap_or(Duration::ZERO)
+                            .as_secs_f64()
+                    })
+                }
+                EvictionStrategy::Lru | EvictionStrategy::TinyLfu => 0.0,
+            }
+        };
+        // a max heap of the lowest ranked entries seen so far, the highest of which is replaced
+        let mut lowest = BinaryHeap::with_capacity(count + 1);
+        for shard in &self.shards {
+            let shard = read_shard(shard);
+            for (id, entry) in shard.iter().filter(|(id, _)| Some(**id) != excluded) {
+                lowest.push((OrderedFloat(rank(entry)), entry.metadata.last_used(), *id));
+                if lowest.len() > count {
+                    lowest.pop();
+                }
+            }
+        }
+        lowest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(_, _, id)| id)
+            .collect()
+    }
+
+    // Removes all entries which expired at `now`, and returns their ids
+    pub fn remove_expired(&self, now: SystemTime) -> Vec<u64> {
+        let mut expired_ids = Vec::new();
+        for shard in &self.shards {
+            let mut shard = write_shard(shard);
+            let shard_expired: Vec<u64> = shard
+                .iter()
+                .filter(|(_, entry)| entry.metadata.is_expired(now))
+                .map(|(id, _)| *id)
+                .collect();
+            for id in shard_expired {
+                if let Some(entry) = shard.pop(&id) {
+                    self.total_size_bytes
+                        .fetch_sub(entry.metadata.size_bytes, Ordering::Relaxed);
+                    expired_ids.push(id);
+                }
+            }
+        }
+        expired_ids
+    }
+
+    // Removes the entry with the given id, returns whether it existed
+    pub fn remove(&self, id: u64) -> bool {
+        let mut shard = write_shard(self.shard(id));
+        if let Some(entry) = shard.pop(&id) {
+            self.total_size_bytes
+                .fetch_sub(entry.metadata.size_bytes, Ordering::Relaxed);
+            true
         } else {
-            None
+            false
+        }
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        read_shard(self.shard(id)).contains(&id)
+    }
+
+    // ids of all entries including expired ones, in no particular order
+    pub fn ids(&self) -> Vec<u64> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                read_shard(shard)
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    // Copies out all entries which haven't expired at `now`, ordered from least to most recently
+    // used so putting them back in order restores the lru order
+    pub fn entries(&self, now: SystemTime) -> Vec<StoredEntry<T>> {
+        let mut entries: Vec<(u64, StoredEntry<T>)> = Vec::new();
+        for shard in &self.shards {
+            let shard = read_shard(shard);
+            entries.extend(
+                shard
+                    .iter()
+                    .filter(|(_, entry)| !entry.metadata.is_expired(now))
+                    .map(|(id, entry)| {
+                        let stored = StoredEntry {
+                            id: *id,
+                            response: entry.response.clone(),
+                            expires_at: entry.metadata.expires_at,
+                            stats: entry.metadata.stats(),
+                        };
+                        (entry.metadata.last_used(), stored)
+                    }),
+            );
         }
+        entries.sort_by_key(|(last_used, _)| *last_used);
+        entries.into_iter().map(|(_, entry)| entry).collect()
     }
 
     pub fn len(&self) -> usize {
-        let cache = self.cache.lock().unwrap_or_else(|err| {
-            error!(error = ?err, "Mutex poisoned");
-            panic!("{}", MUTEX_PANIC)
-        });
-        cache.len()
+        self.shards
+            .iter()
+            .map(|shard| read_shard(shard).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn memory_usage_bytes(&self) -> usize {
-        self.total_size_bytes
-            .load(std::sync::atomic::Ordering::Relaxed)
+        self.total_size_bytes.load(Ordering::Relaxed)
     }
 
     fn calculate_entry_size(&self, response: &T) -> usize {
-        use std::any::Any;
-
-        let response_size =
-            if let Some(bytes_response) = (response as &dyn Any).downcast_ref::<Vec<u8>>() {
-                bytes_response.len() // actual byte vector content size
-            } else {
-                error!("Response type not supported");
-                size_of::<T>() // fallback to type size for other types
-            };
-
-        Self::BASE_ENTRY_SIZE + response_size
+        Self::BASE_ENTRY_SIZE + response.heap_size_bytes()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ResponseStore;
+    use std::time::{Duration, SystemTime};
+
+    use super::{HitStats, ResponseStore};
+    use crate::cache::cached_response::CachedResponse;
+    use crate::cache::eviction::{CostMetric, EvictionStrategy};
 
     #[test]
     fn put_and_get() {
         let cache = ResponseStore::new();
         let answer = b"The capital of France is Paris.".to_vec();
-        cache.put(1, answer.clone());
+        cache.put(1, answer.clone(), None);
 
         let response = cache.get(1).unwrap();
         assert_eq!(answer, response);
     }
 
     #[test]
-    fn pop_removes_lru_entry() {
+    fn evict_removes_lru_entry() {
         let cache = ResponseStore::new();
-        cache.put(1, b"first".to_vec());
-        cache.put(2, b"second".to_vec());
-        cache.put(3, b"third".to_vec());
+        cache.put(1, b"first".to_vec(), None);
+        cache.put(2, b"second".to_vec(), None);
+        cache.put(3, b"third".to_vec(), None);
 
         cache.get(1);
         cache.get(3);
 
-        let popped = cache.pop().unwrap();
-        assert_eq!(popped, 2);
+        let evicted = cache.evict(&EvictionStrategy::Lru, None).unwrap();
+        assert_eq!(evicted, 2);
         assert!(cache.get(2).is_none());
     }
 
     #[test]
-    fn pop_returns_none_when_empty() {
+    fn evict_returns_none_when_empty() {
         let cache: ResponseStore<Vec<u8>> = ResponseStore::new();
-        assert_eq!(cache.pop(), None);
+        assert_eq!(cache.evict(&EvictionStrategy::Lru, None), None);
+    }
+
+    #[test]
+    fn evict_skips_excluded_entry() {
+        let cache = ResponseStore::new();
+        cache.put(1, b"only".to_vec(), None);
+
+        assert_eq!(cache.evict(&EvictionStrategy::Lfu, Some(1)), None);
+        assert_eq!(cache.evict(&EvictionStrategy::Lru, Some(1)), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn lfu_evicts_least_served_entry() {
+        let cache = ResponseStore::new();
+        let now = SystemTime::now();
+        cache.put(1, b"first".to_vec(), None);
+        cache.put(2, b"second".to_vec(), None);
+        cache.put(3, b"third".to_vec(), None);
+        cache.record_hit(1, now);
+        cache.record_hit(3, now);
+        // the most recently used, but never served
+        cache.get(2);
+
+        assert_eq!(cache.peek_victim(&EvictionStrategy::Lfu, None), Some(2));
+        assert_eq!(cache.evict(&EvictionStrategy::Lfu, None), Some(2));
+        // ties go to the least recently used entry
+        assert_eq!(cache.evict(&EvictionStrategy::Lfu, None), Some(1));
+    }
+
+    #[test]
+    fn cost_aware_evicts_cheapest_entry() {
+        let cache = ResponseStore::new();
+        let response = |tokens: u64, latency_ms: u64| CachedResponse {
+            usage_tokens: Some(tokens),
+            ..CachedResponse::from_body(b"{}".to_vec())
+                .with_upstream_latency(Duration::from_millis(latency_ms))
+        };
+        cache.put(1, response(1000, 100), None);
+        cache.put(2, response(50, 2000), None);
+        cache.put(3, response(400, 500), None);
+
+        let tokens = EvictionStrategy::CostAware {
+            metric: CostMetric::Tokens,
+        };
+        let latency = EvictionStrategy::CostAware {
+            metric: CostMetric::Latency,
+        };
+        assert_eq!(cache.peek_victim(&tokens, None), Some(2));
+        assert_eq!(cache.peek_victim(&latency, None), Some(1));
+
+        // served often enough, a cheap entry outweighs an expensive one
+        let now = SystemTime::now();
+        (0..10).for_each(|_| {
+            cache.record_hit(2, now);
+        });
+        assert_eq!(cache.peek_victim(&tokens, None), Some(3));
+    }
+
+    #[test]
+    fn ttl_first_evicts_entry_expiring_first() {
+        let cache = ResponseStore::new();
+        let now = SystemTime::now();
+        cache.put(1, b"forever".to_vec(), None);
+        cache.put(2, b"later".to_vec(), Some(now + Duration::from_secs(600)));
+        cache.put(3, b"sooner".to_vec(), Some(now + Duration::from_secs(60)));
+
+        assert_eq!(cache.evict(&EvictionStrategy::TtlFirst, None), Some(3));
+        assert_eq!(cache.evict(&EvictionStrategy::TtlFirst, None), Some(2));
+        assert_eq!(cache.evict(&EvictionStrategy::TtlFirst, None), Some(1));
     }
 
     #[test]
@@ -158,14 +573,14 @@ mod tests {
         let cache = ResponseStore::new();
         assert_eq!(cache.len(), 0);
 
-        cache.put(1, b"one".to_vec());
+        cache.put(1, b"one".to_vec(), None);
         assert_eq!(cache.len(), 1);
 
-        cache.put(2, b"two".to_vec());
-        cache.put(3, b"three".to_vec());
+        cache.put(2, b"two".to_vec(), None);
+        cache.put(3, b"three".to_vec(), None);
         assert_eq!(cache.len(), 3);
 
-        cache.pop();
+        cache.evict(&EvictionStrategy::Lru, None);
         assert_eq!(cache.len(), 2);
     }
 
@@ -174,26 +589,26 @@ mod tests {
         let cache = ResponseStore::new();
         let initial_memory = cache.memory_usage_bytes();
 
-        cache.put(1, vec![b'A'; 100]);
+        cache.put(1, vec![b'A'; 100], None);
         let after_one = cache.memory_usage_bytes();
         assert!(after_one > initial_memory);
 
-        cache.put(2, vec![b'B'; 200]);
+        cache.put(2, vec![b'B'; 200], None);
         let after_two = cache.memory_usage_bytes();
         assert!(after_two > after_one);
     }
 
     #[test]
-    fn memory_usage_decreases_after_pop() {
+    fn memory_usage_decreases_after_evict() {
         let cache = ResponseStore::new();
-        cache.put(1, vec![b'A'; 1000]);
-        cache.put(2, vec![b'B'; 1000]);
+        cache.put(1, vec![b'A'; 1000], None);
+        cache.put(2, vec![b'B'; 1000], None);
 
-        let before_pop = cache.memory_usage_bytes();
-        cache.pop();
-        let after_pop = cache.memory_usage_bytes();
+        let before_evict = cache.memory_usage_bytes();
+        cache.evict(&EvictionStrategy::Lru, None);
+        let after_evict = cache.memory_usage_bytes();
 
-        assert!(after_pop < before_pop);
+        assert!(after_evict < before_evict);
     }
 
     #[test]
@@ -201,20 +616,165 @@ mod tests {
         let cache = ResponseStore::new();
 
         // Put initial entry with small size
-        cache.put(1, b"small".to_vec());
+        cache.put(1, b"small".to_vec(), None);
         let after_small = cache.memory_usage_bytes();
         assert_eq!(cache.len(), 1);
 
         // Put same key with larger size - should replace, not add
-        cache.put(1, "much_larger_string".repeat(100).into_bytes());
+        cache.put(1, "much_larger_string".repeat(100).into_bytes(), None);
         let after_large = cache.memory_usage_bytes();
         assert_eq!(cache.len(), 1); // Length should stay the same
         assert!(after_large > after_small); // Memory should increase
 
         // Put same key with smaller size - should decrease memory
-        cache.put(1, b"tiny".to_vec());
+        cache.put(1, b"tiny".to_vec(), None);
         let after_tiny = cache.memory_usage_bytes();
         assert_eq!(cache.len(), 1); // Length should stay the same
         assert!(after_tiny < after_large); // Memory should decrease from large
     }
+
+    #[test]
+    fn get_should_treat_expired_entries_as_absent() {
+        let cache = ResponseStore::new();
+        let now = SystemTime::now();
+        cache.put(1, b"stale".to_vec(), Some(now - Duration::from_secs(1)));
+        cache.put(2, b"fresh".to_vec(), Some(now + Duration::from_secs(60)));
+
+        assert!(cache.get(1).is_none());
+        assert_eq!(cache.get(2).unwrap(), b"fresh".to_vec());
+        // expired entries are only dropped by remove_expired
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn remove_expired_should_only_remove_expired_entries() {
+        let cache = ResponseStore::new();
+        let now = SystemTime::now();
+        cache.put(1, vec![b'A'; 100], Some(now - Duration::from_secs(1)));
+        cache.put(2, vec![b'B'; 100], Some(now + Duration::from_secs(60)));
+        cache.put(3, vec![b'C'; 100], None);
+        let before = cache.memory_usage_bytes();
+
+        let removed = cache.remove_expired(now);
+
+        assert_eq!(removed, vec![1]);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.memory_usage_bytes() < before);
+        assert!(cache.get(2).is_some());
+        assert!(cache.get(3).is_some());
+    }
+
+    #[test]
+    fn entries_should_be_ordered_from_least_recently_used() {
+        let cache = ResponseStore::new();
+        let now = SystemTime::now();
+        cache.put(1, b"first".to_vec(), None);
+        cache.put(2, b"second".to_vec(), Some(now - Duration::from_secs(1)));
+        cache.put(3, b"third".to_vec(), Some(now + Duration::from_secs(60)));
+        cache.get(1);
+
+        let ids: Vec<u64> = cache
+            .entries(now)
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+
+        // the expired entry is left out
+        assert_eq!(ids, vec![3, 1]);
+    }
+
+    #[test]
+    fn record_hit_should_count_hits_across_updates() {
+        let cache = ResponseStore::new();
+        let first_hit = SystemTime::now();
+        let second_hit = first_hit + Duration::from_secs(1);
+        cache.put(1, b"first".to_vec(), None);
+
+        assert!(cache.record_hit(1, first_hit));
+        cache.put(1, b"updated".to_vec(), None);
+        assert!(cache.record_hit(1, second_hit));
+        assert!(!cache.record_hit(2, second_hit));
+
+        let entries = cache.entries(second_hit);
+        assert_eq!(entries[0].response, b"updated".to_vec());
+        assert_eq!(
+            entries[0].stats,
+            HitStats {
+                hits: 2,
+                last_hit_at: Some(second_hit),
+            }
+        );
+    }
+
+    #[test]
+    fn evict_should_account_for_lookups_within_a_shard() {
+        // ids 0, 16 and 32 share a shard, so the lookup has to reorder its list
+        let cache = ResponseStore::new();
+        cache.put(0, b"first".to_vec(), None);
+        cache.put(16, b"second".to_vec(), None);
+        cache.put(32, b"third".to_vec(), None);
+
+        cache.get(0);
+
+        assert_eq!(cache.evict(&EvictionStrategy::Lru, None), Some(16));
+        assert_eq!(cache.evict(&EvictionStrategy::Lru, None), Some(32));
+        assert_eq!(cache.evict(&EvictionStrategy::Lru, None), Some(0));
+    }
+
+    #[test]
+    fn concurrent_puts_and_gets_should_keep_entries_consistent() {
+        let cache = ResponseStore::new();
+
+        std::thread::scope(|scope| {
+            for thread in 0..4_u64 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for id in (thread * 100)..(thread * 100 + 100) {
+                        cache.put(id, id.to_le_bytes().to_vec(), None);
+                        assert_eq!(cache.get(id), Some(id.to_le_bytes().to_vec()));
+                    }
+                });
+            }
+        });
+
+        assert_eq!(cache.len(), 400);
+        for _ in 0..400 {
+            assert!(cache.evict(&EvictionStrategy::Lru, None).is_some());
+        }
+        assert!(cache.is_empty());
+        assert_eq!(cache.memory_usage_bytes(), 0);
+    }
+
+    #[test]
+    fn replace_should_only_update_existing_entries() {
+        let cache = ResponseStore::new();
+        cache.put(1, b"first".to_vec(), None);
+
+        assert!(cache.replace(1, b"updated".to_vec(), None));
+        assert!(!cache.replace(2, b"second".to_vec(), None));
+
+        assert_eq!(cache.get(1), Some(b"updated".to_vec()));
+        assert!(!cache.contains(2));
+        assert_eq!(cache.ids(), vec![1]);
+    }
+
+    #[test]
+    fn evict_batch_should_remove_lowest_ranked_entries_in_order() {
+        let cache = ResponseStore::new();
+        let now = SystemTime::now();
+        for id in 0..5 {
+            cache.put(id, b"entry".to_vec(), None);
+        }
+        for id in [0, 1, 1, 3, 3, 3, 4, 4, 4, 4] {
+            cache.record_hit(id, now);
+        }
+
+        let evicted = cache.evict_batch(&EvictionStrategy::Lfu, Some(2), 3);
+
+        // entry 2 was never served, but is excluded
+        assert_eq!(evicted, vec![0, 1, 3]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evict_batch(&EvictionStrategy::Lru, None, 5).len(), 2);
+        assert!(cache.is_empty());
+    }
 }
@@ -0,0 +1,677 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, mpsc};
+use std::thread;
+
+use faiss::index::IndexImpl;
+use faiss::index::autotune::ParameterSpace;
+use faiss::{Idx, Index, MetricType, index_factory};
+use tracing::{error, info, warn};
+
+use super::flat_ip_faiss_store::{find_nearest, into_cosine_similarity};
+use super::index_type::{INDEX_OVERHEAD_BYTES, IndexType};
+use super::semantic_store::{ScoredMatch, SemanticStore};
+use crate::cache::error::CacheError;
+use crate::cache::partition::PartitionKey;
+use crate::cache::persistence::codec::{read_floats, read_u64, write_floats, write_u64};
+use crate::cache::size::hash_map_heap_size;
+use crate::utils::linear_algebra::normalize;
+
+const RW_LOCK_ERROR: &str = "RwLock poisoned, faiss store might be corrupted, panicking";
+const MUTEX_ERROR: &str = "Mutex poisoned, faiss index might be corrupted, panicking";
+const VECTORS_FILE_EXTENSION: &str = "vectors";
+// Deleted vectors stay in the index until it's rebuilt, and may take up places in the search
+// results. Searches fetch up to this many extra results to make up for them, once there are more
+// deleted vectors than this (and more than a quarter of the live ones) the index is rebuilt.
+const MAX_DEAD_OVERFETCH: usize = 64;
+
+// Approximate indexes don't support removing vectors (HNSW) or need retraining as they grow (IVF).
+// Deletes are therefore only applied to the id mappings, and each partition keeps a copy of its
+// vectors so the index can be rebuilt without the deleted ones, or retrained on all of them.
+struct PartitionIndex {
+    index: IndexImpl,
+    // the id and normalized vector of every live entry, by the label it has in the index
+    entries: HashMap<u64, (u64, Vec<f32>)>,
+    // vectors in the index whose entries have been deleted
+    dead: usize,
+    // number of vectors the index was built from
+    built_from: usize,
+    // set while a new index is built outside the lock
+    rebuilding: bool,
+}
+
+impl PartitionIndex {
+    fn needs_rebuild(&self, index_type: &IndexType) -> bool {
+        if self.rebuilding {
+            return false;
+        }
+        let live = self.entries.len();
+        let too_many_dead = self.dead > MAX_DEAD_OVERFETCH && self.dead > live / 4;
+        // train once there are enough vectors, and retrain every time the partition doubled
+        let needs_training = index_type.min_training_size().is_some_and(|min| {
+            live >= min && (self.built_from < min || live >= 2 * self.built_from)
+        });
+        too_many_dead || needs_training
+    }
+}
+
+#[derive(Default)]
+struct Partitions {
+    // searches need mutable access to an index, so every partition gets its own lock
+    indexes: HashMap<PartitionKey, Mutex<PartitionIndex>>,
+    // the partition and label of every live entry
+    ids: HashMap<u64, (PartitionKey, u64)>,
+    next_label: u64,
+}
+
+// The partitions, shared with the rebuild worker
+struct AnnIndexes {
+    faiss_store: RwLock<Partitions>,
+    dimensionality: u32,
+    index_type: IndexType,
+}
+
+// A partition to rebuild, with a copy of its entries as of when the rebuild started
+type RebuildJob = (PartitionKey, Vec<(u64, Vec<f32>)>);
+
+pub struct AnnFaissStore {
+    shared: Arc<AnnIndexes>,
+    // rebuilds and retrains run on a worker thread, so the put or delete which triggers one
+    // doesn't wait for it
+    rebuilds: mpsc::Sender<RebuildJob>,
+}
+
+impl AnnFaissStore {
+    pub fn new(dimensionality: u32, index_type: IndexType) -> Self {
+        // fail fast on unusable parameters rather than on the first insert or training
+        if let IndexType::IvfPq { pq_m, .. } = index_type {
+            assert!(
+                dimensionality.is_multiple_of(pq_m),
+                "pq_m must divide the embedding dimensionality {dimensionality}"
+            );
+        }
+        let largest = index_type.min_training_size().unwrap_or(0);
+        if let Err(err) = index_factory(
+            dimensionality,
+            index_type.description(largest),
+            MetricType::InnerProduct,
+        ) {
+            error!(error = ?err);
+            panic!("failed to init faiss index {:?}", index_type)
+        }
+        let shared = Arc::new(AnnIndexes {
+            faiss_store: RwLock::new(Partitions::default()),
+            dimensionality,
+            index_type,
+        });
+        let (rebuilds, receiver) = mpsc::channel::<RebuildJob>();
+        let worker_shared = shared.clone();
+        // the worker stops once the store, and with it the sender, has been dropped
+        if let Err(err) = thread::Builder::new()
+            .name(String::from("faiss-rebuild"))
+            .spawn(move || {
+                for (partition, snapshot) in receiver {
+                    worker_shared.rebuild(partition, snapshot);
+                }
+            })
+        {
+            error!(error = ?err);
+            panic!("failed to start faiss rebuild worker")
+        }
+        AnnFaissStore { shared, rebuilds }
+    }
+
+    // Hands a rebuild to the worker, the partition is served from its current index until the
+    // new one is swapped in
+    fn request_rebuild(&self, partition: PartitionKey, snapshot: Vec<(u64, Vec<f32>)>) {
+        if let Err(mpsc::SendError((partition, snapshot))) =
+            self.rebuilds.send((partition, snapshot))
+        {
+            // the worker only stops if it panicked
+            warn!("Faiss rebuild worker stopped, rebuilding inline");
+            self.shared.rebuild(partition, snapshot);
+        }
+    }
+
+    // Blocks until no partition is being rebuilt
+    #[cfg(test)]
+    fn wait_for_rebuilds(&self) {
+        loop {
+            let rebuilding = {
+                let read_guard = self.shared.faiss_store.read().expect(RW_LOCK_ERROR);
+                read_guard
+                    .indexes
+                    .values()
+                    .any(|partition_index| partition_index.lock().expect(MUTEX_ERROR).rebuilding)
+            };
+            if !rebuilding {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+}
+
+impl AnnIndexes {
+    // Builds an index holding `vectors`, training it first if required
+    fn build_index(&self, vectors: &[(u64, &[f32])]) -> Result<IndexImpl, CacheError> {
+        let mut index = index_factory(
+            self.dimensionality,
+            self.index_type.description(vectors.len()),
+            MetricType::InnerProduct,
+        )?;
+        if let Some((name, value)) = self.index_type.search_parameter(vectors.len()) {
+            ParameterSpace::new()?.set_index_parameter(&index, name, value)?;
+        }
+        if vectors.is_empty() {
+            return Ok(index);
+        }
+        let labels: Vec<Idx> = vectors.iter().map(|(label, _)| Idx::new(*label)).collect();
+        let flattened: Vec<f32> = vectors
+            .iter()
+            .flat_map(|(_, vector)| vector.iter().copied())
+            .collect();
+        if !index.is_trained() {
+            index.train(&flattened)?;
+        }
+        index.add_with_ids(&flattened, &labels)?;
+        Ok(index)
+    }
+
+    fn new_partition(
+        &self,
+        entries: HashMap<u64, (u64, Vec<f32>)>,
+    ) -> Result<PartitionIndex, CacheError> {
+        let vectors: Vec<(u64, &[f32])> = entries
+            .iter()
+            .map(|(label, (_, vector))| (*label, vector.as_slice()))
+            .collect();
+        let index = self.build_index(&vectors)?;
+        Ok(PartitionIndex {
+            index,
+            built_from: entries.len(),
+            entries,
+            dead: 0,
+            rebuilding: false,
+        })
+    }
+
+    // Replaces the index of `partition` with one built from `snapshot`, a copy of its entries taken
+    // when the rebuild started. Runs on the rebuild worker without holding any lock while the index
+    // is built, changes made to the partition in the meantime are applied to the new index before
+    // it's swapped in.
+    fn rebuild(&self, partition: PartitionKey, snapshot: Vec<(u64, Vec<f32>)>) {
+        let vectors: Vec<(u64, &[f32])> = snapshot
+            .iter()
+            .map(|(label, vector)| (*label, vector.as_slice()))
+            .collect();
+        let built = self.build_index(&vectors);
+
+        let mut write_guard = self.faiss_store.write().expect(RW_LOCK_ERROR);
+        let Some(partition_index) = write_guard.indexes.get_mut(&partition) else {
+            return;
+        };
+        let partition_index = partition_index.get_mut().expect(MUTEX_ERROR);
+        // the partition was replaced by a load since the rebuild started
+        if !partition_index.rebuilding {
+            return;
+        }
+        partition_index.rebuilding = false;
+        let mut index = match built {
+            Ok(index) => index,
+            Err(err) => {
+                warn!(error = ?err, "Failed to rebuild faiss index, keeping the current one");
+                return;
+            }
+        };
+
+        let snapshot_labels: HashSet<u64> = snapshot.iter().map(|(label, _)| *label).collect();
+        for (label, (_, vector)) in &partition_index.entries {
+            if snapshot_labels.contains(label) {
+                continue;
+            }
+            if let Err(err) = index.add_with_ids(vector, &[Idx::new(*label)]) {
+                warn!(error = ?err, "Failed to rebuild faiss index, keeping the current one");
+                return;
+            }
+        }
+        let dead = snapshot_labels
+            .iter()
+            .filter(|label| !partition_index.entries.contains_key(label))
+            .count();
+        info!("Rebuilt faiss index from {} vectors", snapshot.len());
+        partition_index.index = index;
+        partition_index.dead = dead;
+        partition_index.built_from = snapshot.len();
+        if partition_index.entries.is_empty() {
+            write_guard.indexes.remove(&partition);
+        }
+    }
+
+    // Starts a rebuild of the partition if it needs one, returning a copy of its entries to
+    // rebuild from. Must be called with the partition locked.
+    fn start_rebuild(&self, partition_index: &mut PartitionIndex) -> Option<Vec<(u64, Vec<f32>)>> {
+        if !partition_index.needs_rebuild(&self.index_type) {
+            return None;
+        }
+        partition_index.rebuilding = true;
+        Some(
+            partition_index
+                .entries
+                .iter()
+                .map(|(label, (_, vector))| (*label, vector.clone()))
+                .collect(),
+        )
+    }
+}
+
+impl SemanticStore for AnnFaissStore {
+    fn get(
+        &self,
+        partition: PartitionKey,
+        vec: &[f32],
+        top_k: usize,
+        similarity_threshold: f32,
+    ) -> Result<Vec<ScoredMatch>, CacheError> {
+        let similarity_threshold = into_cosine_similarity(similarity_threshold);
+        let vec = normalize(vec);
+
+        let read_guard = self.shared.faiss_store.read().expect(RW_LOCK_ERROR);
+        let Some(partition_index) = read_guard.indexes.get(&partition) else {
+            return Ok(vec![]);
+        };
+        let mut partition_index = partition_index.lock().expect(MUTEX_ERROR);
+
+        // faiss will return nonsense from a search if it's empty
+        if partition_index.entries.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let k = top_k + partition_index.dead.min(MAX_DEAD_OVERFETCH);
+        let search_result = partition_index.index.search(&vec, k)?;
+        let result = find_nearest(search_result, similarity_threshold)
+            .into_iter()
+            // labels without an entry belong to deleted vectors
+            .filter_map(|found| {
+                partition_index
+                    .entries
+                    .get(&found.id)
+                    .map(|(id, _)| ScoredMatch { id: *id, ..found })
+            })
+            .take(top_k)
+            .collect();
+        Ok(result)
+    }
+
+    fn put(&self, partition: PartitionKey, id: u64, vec: Vec<f32>) -> Result<(), CacheError> {
+        let vec = normalize(&vec);
+        // putting an existing id replaces its vector
+        self.delete(id)?;
+
+        let rebuild = {
+            let mut write_guard = self.shared.faiss_store.write().expect(RW_LOCK_ERROR);
+            let Partitions {
+                indexes,
+                ids,
+                next_label,
+            } = &mut *write_guard;
+            let label = *next_label;
+            let partition_index = match indexes.entry(partition) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(Mutex::new(self.shared.new_partition(HashMap::new())?))
+                }
+            }
+            .get_mut()
+            .expect(MUTEX_ERROR);
+            partition_index
+                .index
+                .add_with_ids(&vec, &[Idx::new(label)])?;
+            partition_index.entries.insert(label, (id, vec));
+            *next_label += 1;
+            ids.insert(id, (partition, label));
+            self.shared.start_rebuild(partition_index)
+        };
+
+        if let Some(snapshot) = rebuild {
+            self.request_rebuild(partition, snapshot);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, id: u64) -> Result<(), CacheError> {
+        let rebuild = {
+            let mut write_guard = self.shared.faiss_store.write().expect(RW_LOCK_ERROR);
+            let Some((partition, label)) = write_guard.ids.remove(&id) else {
+                return Ok(());
+            };
+            let Some(partition_index) = write_guard.indexes.get_mut(&partition) else {
+                return Ok(());
+            };
+            let partition_index = partition_index.get_mut().expect(MUTEX_ERROR);
+            partition_index.entries.remove(&label);
+            partition_index.dead += 1;
+            if partition_index.entries.is_empty() && !partition_index.rebuilding {
+                write_guard.indexes.remove(&partition);
+                return Ok(());
+            }
+            self.shared
+                .start_rebuild(partition_index)
+                .map(|snapshot| (partition, snapshot))
+        };
+
+        if let Some((partition, snapshot)) = rebuild {
+            self.request_rebuild(partition, snapshot);
+        }
+        Ok(())
+    }
+
+    fn memory_usage_bytes(&self) -> usize {
+        let read_guard = self.shared.faiss_store.read().expect(RW_LOCK_ERROR);
+
+        let vector_size = self.shared.dimensionality as usize * size_of::<f32>();
+        let index_vector_size = self
+            .shared
+            .index_type
+            .bytes_per_vector(self.shared.dimensionality);
+        let partitions_size: usize = read_guard
+            .indexes
+            .values()
+            .map(|partition_index| {
+                let partition_index = partition_index.lock().expect(MUTEX_ERROR);
+                let live = partition_index.entries.len();
+                // the index, and the copy of the live vectors it's rebuilt from
+                INDEX_OVERHEAD_BYTES
+                    + (live + partition_index.dead) * index_vector_size
+                    + hash_map_heap_size(&partition_index.entries)
+                    + live * vector_size
+            })
+            .sum();
+        // the partition -> index map, and the id -> partition and label lookup
+        let lookups_size =
+            hash_map_heap_size(&read_guard.indexes) + hash_map_heap_size(&read_guard.ids);
+
+        partitions_size + lookups_size
+    }
+
+    fn ids(&self) -> Vec<u64> {
+        let read_guard = self.shared.faiss_store.read().expect(RW_LOCK_ERROR);
+        read_guard.ids.keys().copied().collect()
+    }
+
+    fn partition_ids(&self, partition: PartitionKey) -> Vec<u64> {
+        let read_guard = self.shared.faiss_store.read().expect(RW_LOCK_ERROR);
+        read_guard
+            .ids
+            .iter()
+            .filter(|(_, (id_partition, _))| *id_partition == partition)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    // The indexes themselves aren't saved, every partition is saved as a list of its ids and
+    // vectors and rebuilt on load. This also allows switching between approximate index types across restarts.
+    fn save(&self, dir: &Path) -> Result<(), CacheError> {
+        let read_guard = self.shared.faiss_store.read().expect(RW_LOCK_ERROR);
+        for (partition, partition_index) in &read_guard.indexes {
+            let partition_index = partition_index.lock().expect(MUTEX_ERROR);
+            let mut writer = BufWriter::new(File::create(vectors_path(dir, *partition))?);
+            write_u64(&mut writer, partition_index.entries.len() as u64)?;
+            for (id, vector) in partition_index.entries.values() {
+                write_u64(&mut writer, *id)?;
+                write_floats(&mut writer, vector)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn load(&self, dir: &Path) -> Result<(), CacheError> {
+        let mut partitions = Partitions::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(partition) = partition_of(&path) else {
+                continue;
+            };
+            let mut reader = BufReader::new(File::open(&path)?);
+            let len = read_u64(&mut reader)?;
+            let mut entries = HashMap::new();
+            for _ in 0..len {
+                let id = read_u64(&mut reader)?;
+                let vector = read_floats(&mut reader)?;
+                // vectors of a different embedding model can't be searched with our queries
+                if vector.len() != self.shared.dimensionality as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Saved vectors have dimensionality {}, expected {}",
+                            vector.len(),
+                            self.shared.dimensionality
+                        ),
+                    )
+                    .into());
+                }
+                let label = partitions.next_label;
+                partitions.next_label += 1;
+                partitions.ids.insert(id, (partition, label));
+                entries.insert(label, (id, vector));
+            }
+            if !entries.is_empty() {
+                let partition_index = self.shared.new_partition(entries)?;
+                partitions
+                    .indexes
+                    .insert(partition, Mutex::new(partition_index));
+            }
+        }
+        *self.shared.faiss_store.write().expect(RW_LOCK_ERROR) = partitions;
+        Ok(())
+    }
+}
+
+fn vectors_path(dir: &Path, partition: PartitionKey) -> PathBuf {
+    dir.join(format!("{:016x}.{VECTORS_FILE_EXTENSION}", partition.0))
+}
+
+// the partition key of a file written by save, None for any other file
+fn partition_of(path: &Path) -> Option<PartitionKey> {
+    if path.extension()?.to_str()? != VECTORS_FILE_EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    u64::from_str_radix(stem, 16).ok().map(PartitionKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::partition::PartitionKey;
+    use crate::cache::semantic_store::ann_faiss_store::AnnFaissStore;
+    use crate::cache::semantic_store::index_type::IndexType;
+
+    use super::{ScoredMatch, SemanticStore};
+
+    const PARTITION: PartitionKey = PartitionKey(0);
+    const HNSW: IndexType = IndexType::Hnsw {
+        m: 16,
+        ef_search: 32,
+    };
+
+    // distinct unit vectors spread over the sphere, deterministic so failures can be reproduced
+    fn found_ids(found: &[ScoredMatch]) -> Vec<u64> {
+        found.iter().map(|found| found.id).collect()
+    }
+
+    fn spread_vectors(count: usize) -> Vec<Vec<f32>> {
+        (0..count)
+            .map(|i| {
+                let i = i as f32;
+                vec![(i * 0.7).sin(), (i * 1.3).cos(), (i * 0.3).sin(), 1.0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn get_should_find_exact_match() {
+        // given
+        let store = AnnFaissStore::new(4, HNSW);
+        for (id, vector) in spread_vectors(100).into_iter().enumerate() {
+            store.put(PARTITION, id as u64, vector).unwrap();
+        }
+
+        // when
+        let found = store
+            .get(PARTITION, &spread_vectors(100)[42], 1, 1.0)
+            .unwrap();
+
+        // then
+        assert_eq!(found_ids(&found), vec![42]);
+        assert!(found[0].similarity > 0.99);
+    }
+
+    #[test]
+    fn delete_should_remove_vector_from_results() {
+        // given
+        let store = AnnFaissStore::new(4, HNSW);
+        store.put(PARTITION, 1, vec![0.0, 1.0, 0.0, 0.0]).unwrap();
+        store.put(PARTITION, 2, vec![0.0, 1.0, 0.1, 0.0]).unwrap();
+
+        // when
+        store.delete(1).unwrap();
+        let found = store.get(PARTITION, &[0.0, 1.0, 0.0, 0.0], 1, 0.9).unwrap();
+
+        // then
+        assert_eq!(found_ids(&found), vec![2]);
+        assert_eq!(store.ids(), vec![2]);
+    }
+
+    #[test]
+    fn delete_should_drop_empty_partitions() {
+        // given
+        let store = AnnFaissStore::new(4, HNSW);
+        store.put(PARTITION, 1, vec![0.0, 1.0, 0.0, 0.0]).unwrap();
+
+        // when
+        store.delete(1).unwrap();
+        // deleting an unknown id is a no-op
+        store.delete(7).unwrap();
+
+        // then
+        assert!(store.ids().is_empty());
+        assert_eq!(store.memory_usage_bytes(), 0);
+        assert!(
+            store
+                .get(PARTITION, &[0.0, 1.0, 0.0, 0.0], 1, 0.0)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn put_should_replace_vector_of_existing_id() {
+        // given
+        let store = AnnFaissStore::new(4, HNSW);
+        store.put(PARTITION, 1, vec![0.0, 1.0, 0.0, 0.0]).unwrap();
+
+        // when
+        store.put(PARTITION, 1, vec![1.0, 0.0, 0.0, 0.0]).unwrap();
+
+        // then
+        assert!(
+            store
+                .get(PARTITION, &[0.0, 1.0, 0.0, 0.0], 1, 0.9)
+                .unwrap()
+                .is_empty()
+        );
+        let found = store.get(PARTITION, &[1.0, 0.0, 0.0, 0.0], 1, 0.9).unwrap();
+        assert_eq!(found_ids(&found), vec![1]);
+    }
+
+    #[test]
+    fn deleted_vectors_should_not_crowd_out_live_ones() {
+        // given
+        let store = AnnFaissStore::new(4, HNSW);
+        for id in 0..200 {
+            store.put(PARTITION, id, vec![0.0, 1.0, 0.0, 0.0]).unwrap();
+        }
+        store
+            .put(PARTITION, 200, vec![0.0, 1.0, 0.05, 0.0])
+            .unwrap();
+
+        // when
+        // enough deletes to trigger rebuilds along the way
+        for id in 0..200 {
+            store.delete(id).unwrap();
+        }
+        store.wait_for_rebuilds();
+        let found = store.get(PARTITION, &[0.0, 1.0, 0.0, 0.0], 1, 0.9).unwrap();
+
+        // then
+        assert_eq!(found_ids(&found), vec![200]);
+    }
+
+    #[test]
+    fn ivf_pq_should_train_once_partition_is_large_enough() {
+        // given
+        let index_type = IndexType::IvfPq {
+            nlist: 4,
+            pq_m: 2,
+            nprobe: 4,
+        };
+        let store = AnnFaissStore::new(4, index_type);
+        let vectors = spread_vectors(index_type.min_training_size().unwrap() + 1);
+        let flat_memory = {
+            for (id, vector) in vectors.iter().take(100).enumerate() {
+                store.put(PARTITION, id as u64, vector.clone()).unwrap();
+            }
+            store.memory_usage_bytes()
+        };
+
+        // when
+        for (id, vector) in vectors.iter().enumerate().skip(100) {
+            store.put(PARTITION, id as u64, vector.clone()).unwrap();
+        }
+        store.wait_for_rebuilds();
+
+        // then
+        let built_from = {
+            let read_guard = store.shared.faiss_store.read().unwrap();
+            read_guard.indexes[&PARTITION].lock().unwrap().built_from
+        };
+        // trained on the vectors it had when it reached the training size
+        assert_eq!(built_from, index_type.min_training_size().unwrap());
+        assert_eq!(store.ids().len(), vectors.len());
+        assert!(store.memory_usage_bytes() > flat_memory);
+        let found = store.get(PARTITION, &vectors[7], 10, 0.9).unwrap();
+        assert!(!found.is_empty());
+    }
+
+    #[test]
+    fn load_should_restore_saved_vectors() {
+        // given
+        let dir = std::env::temp_dir().join(format!("semcache-ann-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let saved = AnnFaissStore::new(4, HNSW);
+        saved.put(PARTITION, 1, vec![0.0, 1.0, 0.0, 0.0]).unwrap();
+        saved
+            .put(PartitionKey(9), 2, vec![1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        saved.save(&dir).unwrap();
+
+        // when
+        let loaded = AnnFaissStore::new(4, HNSW);
+        loaded.load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // then
+        let mut ids = loaded.ids();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        let found = loaded
+            .get(PartitionKey(9), &[1.0, 0.0, 0.0, 0.0], 1, 0.9)
+            .unwrap();
+        assert_eq!(found_ids(&found), vec![2]);
+    }
+}
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
 use crate::utils::linear_algebra::normalize;
@@ -5,100 +9,220 @@ use faiss::selector::IdSelector;
 use faiss::{
     ConcurrentIndex, IdMap, Idx, Index,
     index::{SearchResult, flat::FlatIndexImpl},
+    read_index, write_index,
 };
 use ordered_float::OrderedFloat;
 use tracing::error;
 
 use crate::cache::error::CacheError;
-
-use super::semantic_store::SemanticStore;
+use crate::cache::partition::PartitionKey;
+use crate::cache::size::hash_map_heap_size;
+
+use super::index_type::{INDEX_OVERHEAD_BYTES, IndexType};
+use super::semantic_store::{ScoredMatch, SemanticStore};
+
+// Every partition gets its own flat index, so a search never has to look at (or filter out)
+// vectors from other partitions. Indexes are created on first insert and dropped once empty.
+#[derive(Default)]
+struct Partitions {
+    indexes: HashMap<PartitionKey, IdMap<FlatIndexImpl>>,
+    id_to_partition: HashMap<u64, PartitionKey>,
+}
 
 pub struct FlatIPFaissStore {
-    faiss_store: RwLock<IdMap<FlatIndexImpl>>,
+    faiss_store: RwLock<Partitions>,
     dimensionality: u32,
 }
 
 const RW_LOCK_ERROR: &str = "RwLock poisoned, faiss store might be corrupted, panicking";
+const INDEX_FILE_EXTENSION: &str = "faiss";
 
 impl FlatIPFaissStore {
     pub fn new(dimensionality: u32) -> Self {
-        let faiss_index = FlatIndexImpl::new_ip(dimensionality).unwrap_or_else(|err| {
-            error!(error = ?err);
-            panic!("failed to init faiss index")
-        });
-        let id_map = IdMap::new(faiss_index).unwrap_or_else(|err| {
-            error!(error = ?err);
-            panic!("failed to init faiss index")
-        });
-        let faiss_store = RwLock::new(id_map);
+        // fail fast on an unusable dimensionality rather than on the first insert
+        new_index(dimensionality);
         FlatIPFaissStore {
-            faiss_store,
+            faiss_store: RwLock::new(Partitions::default()),
             dimensionality,
         }
     }
 }
 
+fn new_index(dimensionality: u32) -> IdMap<FlatIndexImpl> {
+    let faiss_index = FlatIndexImpl::new_ip(dimensionality).unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("failed to init faiss index")
+    });
+    IdMap::new(faiss_index).unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("failed to init faiss index")
+    })
+}
+
 impl SemanticStore for FlatIPFaissStore {
     fn get(
         &self,
+        partition: PartitionKey,
         vec: &[f32],
         top_k: usize,
         similarity_threshold: f32,
-    ) -> Result<Vec<u64>, CacheError> {
+    ) -> Result<Vec<ScoredMatch>, CacheError> {
         let similarity_threshold = into_cosine_similarity(similarity_threshold);
         let vec = normalize(vec);
 
         let read_guard = self.faiss_store.read().expect(RW_LOCK_ERROR);
+        let Some(index) = read_guard.indexes.get(&partition) else {
+            return Ok(vec![]);
+        };
 
         // faiss will return nonsense from a search if it's empty
-        if read_guard.ntotal() == 0 {
+        if index.ntotal() == 0 {
             return Ok(vec![]);
         }
 
-        let search_result = ConcurrentIndex::search(&*read_guard, &vec, top_k)?;
-        let result = find_nearest_ids(search_result, similarity_threshold);
+        let search_result = ConcurrentIndex::search(index, &vec, top_k)?;
+        let result = find_nearest(search_result, similarity_threshold);
         Ok(result)
     }
 
-    fn put(&self, id: u64, vec: Vec<f32>) -> Result<(), CacheError> {
+    fn put(&self, partition: PartitionKey, id: u64, vec: Vec<f32>) -> Result<(), CacheError> {
         let vec = normalize(&vec);
-        let id = Idx::new(id);
         let mut write_guard = self.faiss_store.write().expect(RW_LOCK_ERROR);
-        write_guard.add_with_ids(&vec, &[id])?;
+        let dimensionality = self.dimensionality;
+        let index = write_guard
+            .indexes
+            .entry(partition)
+            .or_insert_with(|| new_index(dimensionality));
+        index.add_with_ids(&vec, &[Idx::new(id)])?;
+        write_guard.id_to_partition.insert(id, partition);
         Ok(())
     }
 
     fn delete(&self, id: u64) -> Result<(), CacheError> {
-        let id = Idx::new(id);
-        let mut write_guard = self
-            .faiss_store
-            .write()
-            .expect("RwLock poisoned, faiss store might be corrupted, panicking");
-        let id_sel = IdSelector::batch(&[id])?;
-        write_guard.remove_ids(&id_sel)?;
+        let mut write_guard = self.faiss_store.write().expect(RW_LOCK_ERROR);
+        let Some(partition) = write_guard.id_to_partition.remove(&id) else {
+            return Ok(());
+        };
+        if let Some(index) = write_guard.indexes.get_mut(&partition) {
+            let id_sel = IdSelector::batch(&[Idx::new(id)])?;
+            index.remove_ids(&id_sel)?;
+            if index.ntotal() == 0 {
+                write_guard.indexes.remove(&partition);
+            }
+        }
         Ok(())
     }
 
-    // TODO: improve this to be more accurate
     fn memory_usage_bytes(&self) -> usize {
         let read_guard = self.faiss_store.read().expect(RW_LOCK_ERROR);
 
-        // Each vector takes dimensionality * 4 bytes (f32)
-        let vector_size = self.dimensionality as usize * 4;
-        let num_vectors = read_guard.ntotal() as usize;
-        let raw_vectors_size = num_vectors * vector_size;
+        let vector_size = IndexType::Flat.bytes_per_vector(self.dimensionality);
+        let indexes_size: usize = read_guard
+            .indexes
+            .values()
+            .map(|index| INDEX_OVERHEAD_BYTES + index.ntotal() as usize * vector_size)
+            .sum();
+        // the partition -> index map, and the id -> partition lookup used for deletes
+        let lookups_size = hash_map_heap_size(&read_guard.indexes)
+            + hash_map_heap_size(&read_guard.id_to_partition);
+
+        indexes_size + lookups_size
+    }
+
+    fn ids(&self) -> Vec<u64> {
+        let read_guard = self.faiss_store.read().expect(RW_LOCK_ERROR);
+        read_guard.id_to_partition.keys().copied().collect()
+    }
 
-        // Add 20% overhead for FAISS index metadata and structures
-        (raw_vectors_size as f64 * 1.2) as usize
+    fn partition_ids(&self, partition: PartitionKey) -> Vec<u64> {
+        let read_guard = self.faiss_store.read().expect(RW_LOCK_ERROR);
+        read_guard
+            .id_to_partition
+            .iter()
+            .filter(|(_, id_partition)| **id_partition == partition)
+            .map(|(id, _)| *id)
+            .collect()
     }
+
+    // every partition is saved as a separate faiss index file, named after its partition key
+    fn save(&self, dir: &Path) -> Result<(), CacheError> {
+        let read_guard = self.faiss_store.read().expect(RW_LOCK_ERROR);
+        for (partition, index) in &read_guard.indexes {
+            write_index(index, path_str(&index_path(dir, *partition))?)?;
+        }
+        Ok(())
+    }
+
+    fn load(&self, dir: &Path) -> Result<(), CacheError> {
+        let mut partitions = Partitions::default();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(partition) = partition_of(&path) else {
+                continue;
+            };
+            let index = read_index(path_str(&path)?)?
+                .into_id_map()?
+                .try_cast_inner_index::<FlatIndexImpl>()?;
+            // vectors of a different embedding model can't be searched with our queries
+            if index.d() != self.dimensionality {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Saved vectors have dimensionality {}, expected {}",
+                        index.d(),
+                        self.dimensionality
+                    ),
+                )
+                .into());
+            }
+            for id in index.id_map().iter().filter_map(|idx| idx.get()) {
+                partitions.id_to_partition.insert(id, partition);
+            }
+            partitions.indexes.insert(partition, index);
+        }
+        *self.faiss_store.write().expect(RW_LOCK_ERROR) = partitions;
+        Ok(())
+    }
+}
+
+fn index_path(dir: &Path, partition: PartitionKey) -> PathBuf {
+    dir.join(format!("{:016x}.{INDEX_FILE_EXTENSION}", partition.0))
+}
+
+// the partition key of an index file written by save, None for any other file
+fn partition_of(path: &Path) -> Option<PartitionKey> {
+    if path.extension()?.to_str()? != INDEX_FILE_EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    u64::from_str_radix(stem, 16).ok().map(PartitionKey)
+}
+
+// faiss only accepts file names which are valid utf-8
+fn path_str(path: &Path) -> Result<&str, CacheError> {
+    path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Path {path:?} is not valid utf-8"),
+        )
+        .into()
+    })
 }
 
 // takes an internal representation of similarity which is of [0, 1], and rescales it linearly to cosine similarity
-fn into_cosine_similarity(similarity_threshold: f32) -> f32 {
+pub(crate) fn into_cosine_similarity(similarity_threshold: f32) -> f32 {
     similarity_threshold * 2.0 - 1.0
 }
 
-fn find_nearest_ids(search_result: SearchResult, similarity_threshold: f32) -> Vec<u64> {
+// the inverse of into_cosine_similarity, so scores are reported on the same scale as the threshold
+pub(crate) fn from_cosine_similarity(cosine_similarity: f32) -> f32 {
+    ((cosine_similarity + 1.0) / 2.0).clamp(0.0, 1.0)
+}
+
+pub(super) fn find_nearest(
+    search_result: SearchResult,
+    similarity_threshold: f32,
+) -> Vec<ScoredMatch> {
     let mut distances_and_ids: Vec<(f32, u64)> = search_result
         .distances
         .into_iter()
@@ -115,18 +239,27 @@ fn find_nearest_ids(search_result: SearchResult, similarity_threshold: f32) -> V
     // ensure our found vectors are sorted in order of closest match first
     distances_and_ids.sort_by_key(|(distance, _)| std::cmp::Reverse(OrderedFloat(*distance)));
 
-    // extract ids of matching entries
     distances_and_ids
         .into_iter()
-        .map(|distance_and_id| distance_and_id.1)
+        .map(|(distance, id)| ScoredMatch {
+            id,
+            similarity: from_cosine_similarity(distance),
+        })
         .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::cache::partition::PartitionKey;
     use crate::cache::semantic_store::flat_ip_faiss_store::FlatIPFaissStore;
 
-    use super::SemanticStore;
+    use super::{ScoredMatch, SemanticStore};
+
+    const PARTITION: PartitionKey = PartitionKey(0);
+
+    fn found_ids(found: &[ScoredMatch]) -> Vec<u64> {
+        found.iter().map(|found| found.id).collect()
+    }
 
     #[test]
     fn get_should_normalize_vectors() {
@@ -134,19 +267,21 @@ mod tests {
         let faiss_store = FlatIPFaissStore::new(3);
         let normalized = vec![0.0, 1.0, 0.0];
         let normalized_id = 1;
-        faiss_store.put(normalized_id, normalized).unwrap();
+        faiss_store
+            .put(PARTITION, normalized_id, normalized)
+            .unwrap();
 
         // when
         // expect vector to get normalized before search
         let query = vec![0.0, 3.0, 0.0];
         // set similarity threshold to 1 so that only an exact match is returned
         let found = faiss_store
-            .get(&query, 1, 1.0)
+            .get(PARTITION, &query, 1, 1.0)
             .expect("error in faiss store");
 
         // then
         assert_eq!(found.len(), 1);
-        assert_eq!(found[0], normalized_id);
+        assert_eq!(found[0].id, normalized_id);
     }
 
     #[test]
@@ -156,18 +291,20 @@ mod tests {
         // expect the vector to be normalized before adding
         let unnormalized = vec![0.0, 3.0, 0.0];
         let unnormalized_id = 1;
-        faiss_store.put(unnormalized_id, unnormalized).unwrap();
+        faiss_store
+            .put(PARTITION, unnormalized_id, unnormalized)
+            .unwrap();
 
         // when
         let query = vec![0.0, 1.0, 0.0];
         // set similarity threshold to 1 so that only an exact match is returned
         let found = faiss_store
-            .get(&query, 1, 1.0)
+            .get(PARTITION, &query, 1, 1.0)
             .expect("error in faiss store");
 
         // then
         assert_eq!(found.len(), 1);
-        assert_eq!(found[0], unnormalized_id);
+        assert_eq!(found[0].id, unnormalized_id);
     }
 
     #[test]
@@ -178,7 +315,7 @@ mod tests {
         // when
         let query = vec![0_f32, 0.99, 0.0];
         let found = faiss_store
-            .get(&query, 1, 0.9)
+            .get(PARTITION, &query, 1, 0.9)
             .expect("error in faiss store");
 
         // then
@@ -191,16 +328,22 @@ mod tests {
         let faiss_store = FlatIPFaissStore::new(3);
         let vec1 = vec![0_f32, 1.0, 0.0];
         let vec2 = vec![0_f32, 0.0, 1.0];
-        faiss_store.put(1, vec1).expect("failed to insert vectors");
-        faiss_store.put(2, vec2).expect("failed to insert vectors");
+        faiss_store
+            .put(PARTITION, 1, vec1)
+            .expect("failed to insert vectors");
+        faiss_store
+            .put(PARTITION, 2, vec2)
+            .expect("failed to insert vectors");
 
         // when
         let query = vec![0_f32, 0.99, 0.0];
-        let found = faiss_store.get(&query, 1, 0.9).expect("No vector found");
+        let found = faiss_store
+            .get(PARTITION, &query, 1, 0.9)
+            .expect("No vector found");
 
         // then
         assert_eq!(found.len(), 1);
-        assert_eq!(found[0], 1);
+        assert_eq!(found[0].id, 1);
     }
 
     #[test]
@@ -208,17 +351,24 @@ mod tests {
         // given
         let cache = FlatIPFaissStore::new(3);
         let vec1 = vec![0_f32, 0.99, 0.0];
-        cache.put(1, vec1).expect("failed to insert vectors");
+        cache
+            .put(PARTITION, 1, vec1)
+            .expect("failed to insert vectors");
         let vec2 = vec![0_f32, 1.0, 0.0];
-        cache.put(2, vec2).expect("failed to insert vectors");
+        cache
+            .put(PARTITION, 2, vec2)
+            .expect("failed to insert vectors");
 
         // when
         let query = vec![0_f32, 1.0, 0.0];
-        let found = cache.get(&query, 2, 0.9).expect("No vector found");
+        let found = cache
+            .get(PARTITION, &query, 2, 0.9)
+            .expect("No vector found");
 
         // then
         assert_eq!(found.len(), 2);
-        assert_eq!(found, vec!(2, 1));
+        assert_eq!(found_ids(&found), vec!(2, 1));
+        assert!(found[0].similarity > found[1].similarity);
     }
 
     #[test]
@@ -226,11 +376,15 @@ mod tests {
         // given
         let cache = FlatIPFaissStore::new(3);
         let vec = vec![0_f32, 1.0, 0.0];
-        cache.put(1, vec).expect("failed to insert vectors");
+        cache
+            .put(PARTITION, 1, vec)
+            .expect("failed to insert vectors");
 
         // when
         let query = vec![0_f32, 0.0, 0.0];
-        let found = cache.get(&query, 2, 0.9).expect("No vector found");
+        let found = cache
+            .get(PARTITION, &query, 2, 0.9)
+            .expect("No vector found");
 
         // then
         assert_eq!(found.len(), 0);
@@ -241,15 +395,82 @@ mod tests {
         let cache = FlatIPFaissStore::new(3);
         let vec1 = vec![0_f32, 1.0, 0.0];
         let id = 1;
-        cache.put(id, vec1).expect("");
+        cache.put(PARTITION, id, vec1).expect("");
 
         let query = vec![0_f32, 0.99, 0.0];
 
-        let found = cache.get(&query, 1, 0.9).expect("");
+        let found = cache.get(PARTITION, &query, 1, 0.9).expect("");
         assert_eq!(found.len(), 1);
 
         cache.delete(id).expect("");
-        let after_delete = cache.get(&query, 1, 0.9).expect("");
+        let after_delete = cache.get(PARTITION, &query, 1, 0.9).expect("");
         assert_eq!(after_delete.len(), 0);
     }
+
+    #[test]
+    fn get_should_only_return_vectors_from_same_partition() {
+        // given
+        let cache = FlatIPFaissStore::new(3);
+        let other_partition = PartitionKey(1);
+        cache.put(PARTITION, 1, vec![0_f32, 1.0, 0.0]).expect("");
+        cache
+            .put(other_partition, 2, vec![0_f32, 1.0, 0.0])
+            .expect("");
+
+        // when
+        let query = vec![0_f32, 1.0, 0.0];
+        let found = cache.get(PARTITION, &query, 2, 0.9).expect("");
+        let found_in_other = cache.get(other_partition, &query, 2, 0.9).expect("");
+        let found_in_unknown = cache.get(PartitionKey(2), &query, 2, 0.9).expect("");
+
+        // then
+        assert_eq!(found_ids(&found), vec![1]);
+        assert_eq!(found_ids(&found_in_other), vec![2]);
+        assert!(found_in_unknown.is_empty());
+    }
+
+    #[test]
+    fn delete_should_drop_empty_partitions() {
+        // given
+        let cache = FlatIPFaissStore::new(3);
+        cache.put(PARTITION, 1, vec![0_f32, 1.0, 0.0]).expect("");
+
+        // when
+        cache.delete(1).expect("");
+
+        // then
+        let read_guard = cache.faiss_store.read().unwrap();
+        assert!(read_guard.indexes.is_empty());
+        assert!(read_guard.id_to_partition.is_empty());
+    }
+
+    #[test]
+    fn load_should_restore_saved_vectors_and_partitions() {
+        // given
+        let dir = std::env::temp_dir().join(format!("semcache-faiss-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let other_partition = PartitionKey(u64::MAX);
+        let saved = FlatIPFaissStore::new(3);
+        saved.put(PARTITION, 1, vec![0_f32, 1.0, 0.0]).expect("");
+        saved
+            .put(other_partition, 2, vec![1_f32, 0.0, 0.0])
+            .expect("");
+        saved.save(&dir).expect("");
+
+        // when
+        let loaded = FlatIPFaissStore::new(3);
+        loaded.load(&dir).expect("");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // then
+        let mut ids = loaded.ids();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        let found = loaded.get(PARTITION, &[0_f32, 1.0, 0.0], 2, 0.9).expect("");
+        assert_eq!(found_ids(&found), vec![1]);
+        let found_in_other = loaded
+            .get(other_partition, &[1_f32, 0.0, 0.0], 2, 0.9)
+            .expect("");
+        assert_eq!(found_ids(&found_in_other), vec![2]);
+    }
 }
@@ -0,0 +1,168 @@
+use serde::Deserialize;
+
+use super::ann_faiss_store::AnnFaissStore;
+use super::flat_ip_faiss_store::FlatIPFaissStore;
+use super::semantic_store::SemanticStore;
+
+// faiss warns when training with fewer points per centroid than this
+const TRAINING_POINTS_PER_CENTROID: usize = 39;
+// centroids per sub-quantizer, for 8 bit codes
+const PQ_CENTROIDS: usize = 256;
+// the faiss index and id map objects of a partition, besides the vectors they hold
+pub const INDEX_OVERHEAD_BYTES: usize = 512;
+
+// The kind of faiss index used to search for similar prompts
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(tag = "index_type", rename_all = "snake_case")]
+pub enum IndexType {
+    // exhaustive search, exact but its latency grows linearly with the number of entries
+    #[default]
+    Flat,
+    // graph based search, `m` neighbours per vector and `ef_search` candidates explored per lookup
+    Hnsw {
+        m: u32,
+        ef_search: u32,
+    },
+    // vectors are clustered into `nlist` lists of which `nprobe` are searched per lookup, and stored
+    // compressed into `pq_m` bytes. Partitions are searched exhaustively until there are enough
+    // vectors to train on.
+    IvfPq {
+        nlist: u32,
+        pq_m: u32,
+        nprobe: u32,
+    },
+}
+
+impl IndexType {
+    pub fn new_store(self, dimensionality: u32) -> Box<dyn SemanticStore> {
+        match self {
+            IndexType::Flat => Box::new(FlatIPFaissStore::new(dimensionality)),
+            _ => Box::new(AnnFaissStore::new(dimensionality, self)),
+        }
+    }
+
+    pub fn validate(self) -> Result<Self, String> {
+        let valid = match self {
+            IndexType::Flat => true,
+            IndexType::Hnsw { m, ef_search } => m > 0 && ef_search > 0,
+            IndexType::IvfPq {
+                nlist,
+                pq_m,
+                nprobe,
+            } => nlist > 0 && pq_m > 0 && nprobe > 0,
+        };
+        if valid {
+            Ok(self)
+        } else {
+            Err(format!("Index parameters must be positive: {:?}", self))
+        }
+    }
+
+    // Checks the parameters which depend on the embedding model, only known once it's loaded
+    pub fn validate_for(self, dimensionality: u32) -> Result<Self, String> {
+        match self {
+            IndexType::IvfPq { pq_m, .. } if !dimensionality.is_multiple_of(pq_m) => Err(format!(
+                "pq_m must divide the embedding dimensionality {dimensionality}: {:?}",
+                self
+            )),
+            _ => Ok(self),
+        }
+    }
+
+    // Number of vectors a partition needs before its index can be trained, None if the index
+    // doesn't need training
+    pub fn min_training_size(&self) -> Option<usize> {
+        match self {
+            IndexType::IvfPq { nlist, .. } => {
+                Some(TRAINING_POINTS_PER_CENTROID * (*nlist as usize).max(PQ_CENTROIDS))
+            }
+            _ => None,
+        }
+    }
+
+    // The faiss index factory description of an index holding `num_vectors` vectors. Labels are
+    // assigned by the store, so every index is wrapped in an id map.
+    pub fn description(&self, num_vectors: usize) -> String {
+        match self {
+            IndexType::Hnsw { m, .. } => format!("IDMap,HNSW{m},Flat"),
+            IndexType::IvfPq { nlist, pq_m, .. }
+                if self
+                    .min_training_size()
+                    .is_some_and(|min| num_vectors >= min) =>
+            {
+                format!("IDMap,IVF{nlist},PQ{pq_m}")
+            }
+            _ => String::from("IDMap,Flat"),
+        }
+    }
+
+    // The faiss search parameter of an index built by `description`, if it has one
+    pub fn search_parameter(&self, num_vectors: usize) -> Option<(&'static str, u32)> {
+        match self {
+            IndexType::Hnsw { ef_search, .. } => Some(("efSearch", *ef_search)),
+            IndexType::IvfPq { nprobe, .. }
+                if self
+                    .min_training_size()
+                    .is_some_and(|min| num_vectors >= min) =>
+            {
+                Some(("nprobe", *nprobe))
+            }
+            _ => None,
+        }
+    }
+
+    // Approximate size of a single vector inside the index
+    pub fn bytes_per_vector(&self, dimensionality: u32) -> usize {
+        // every index keeps a label per vector in its id map
+        let label_size = size_of::<i64>();
+        let vector_size = dimensionality as usize * size_of::<f32>();
+        match self {
+            IndexType::Flat => vector_size + label_size,
+            // level 0 of the graph links every vector to 2 * m neighbours
+            IndexType::Hnsw { m, .. } => {
+                vector_size + 2 * *m as usize * size_of::<i32>() + label_size
+            }
+            // the compressed code, and the label stored next to it in the inverted list
+            IndexType::IvfPq { pq_m, .. } => *pq_m as usize + 2 * label_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexType;
+
+    const IVF_PQ: IndexType = IndexType::IvfPq {
+        nlist: 100,
+        pq_m: 48,
+        nprobe: 8,
+    };
+
+    #[test]
+    fn ivf_pq_should_be_searched_exhaustively_until_trainable() {
+        let min = IVF_PQ.min_training_size().unwrap();
+
+        assert_eq!(IVF_PQ.description(min - 1), "IDMap,Flat");
+        assert_eq!(IVF_PQ.search_parameter(min - 1), None);
+        assert_eq!(IVF_PQ.description(min), "IDMap,IVF100,PQ48");
+        assert_eq!(IVF_PQ.search_parameter(min), Some(("nprobe", 8)));
+    }
+
+    #[test]
+    fn validate_should_reject_zero_parameters() {
+        let index_type = IndexType::Hnsw {
+            m: 0,
+            ef_search: 64,
+        };
+
+        assert!(index_type.validate().is_err());
+        assert!(IVF_PQ.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_for_should_reject_pq_m_not_dividing_dimensionality() {
+        assert!(IVF_PQ.validate_for(384).is_ok());
+        assert!(IVF_PQ.validate_for(100).is_err());
+        assert!(IndexType::Flat.validate_for(100).is_ok());
+    }
+}
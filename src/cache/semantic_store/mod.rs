@@ -1,2 +1,4 @@
+pub mod ann_faiss_store;
 pub mod flat_ip_faiss_store;
+pub mod index_type;
 pub mod semantic_store;
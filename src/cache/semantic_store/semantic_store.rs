@@ -1,20 +1,41 @@
+use std::path::Path;
+
 use mockall::automock;
 
 use crate::cache::error::CacheError;
+use crate::cache::partition::PartitionKey;
+
+// A vector found by a search, with its similarity to the query on the same [0, 1] scale as the threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoredMatch {
+    pub id: u64,
+    pub similarity: f32,
+}
 
 #[automock]
 pub trait SemanticStore: Send + Sync {
-    // Will return a list of sorted id's matching the query vector
-    // the id's will be sorted in descending order w.r.t. similarity, most similar id first
+    // Will return a list of sorted matches for the query vector
+    // the matches will be sorted in descending order w.r.t. similarity, most similar first
     // similarity is [0, 1] where 0 is least similar, and 1 is most similar
     // may return fewer than top_k vectors if not enough matching the similarity threshold are found in the db
+    // only vectors put under the same partition are searched
     fn get(
         &self,
+        partition: PartitionKey,
         vec: &[f32],
         top_k: usize,
         similarity_threshold: f32,
-    ) -> Result<Vec<u64>, CacheError>;
-    fn put(&self, id: u64, vec: Vec<f32>) -> Result<(), CacheError>;
+    ) -> Result<Vec<ScoredMatch>, CacheError>;
+    fn put(&self, partition: PartitionKey, id: u64, vec: Vec<f32>) -> Result<(), CacheError>;
     fn delete(&self, id: u64) -> Result<(), CacheError>;
+    // estimate of the memory taken up by the vectors, their indexes and the id lookups
     fn memory_usage_bytes(&self) -> usize;
+    // ids of all vectors in the store, in no particular order
+    fn ids(&self) -> Vec<u64>;
+    // ids of all vectors put under `partition`, in no particular order
+    fn partition_ids(&self, partition: PartitionKey) -> Vec<u64>;
+    // writes all vectors into the (existing, empty) directory `dir`
+    fn save(&self, dir: &Path) -> Result<(), CacheError>;
+    // replaces the contents of the store with the vectors saved to `dir`
+    fn load(&self, dir: &Path) -> Result<(), CacheError>;
 }
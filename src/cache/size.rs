@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+// Memory a value owns on the heap, on top of the `size_of` the value itself. Implemented by every
+// type the cache stores responses as, so that memory limits account for what entries take up.
+pub trait HeapSize {
+    fn heap_size_bytes(&self) -> usize;
+}
+
+impl HeapSize for Vec<u8> {
+    fn heap_size_bytes(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for String {
+    fn heap_size_bytes(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size_bytes(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size_bytes)
+    }
+}
+
+// Every header is stored as a bucket in the map's entries, with an index (and hash) pointing to it.
+// Standard header names aren't allocated, but custom ones and all values are.
+impl HeapSize for HeaderMap {
+    fn heap_size_bytes(&self) -> usize {
+        let bucket_size = size_of::<(HeaderName, HeaderValue)>() + 2 * size_of::<u16>();
+        self.iter()
+            .map(|(name, value)| bucket_size + name.as_str().len() + value.len())
+            .sum()
+    }
+}
+
+// Hash maps store their entries in buckets with a control byte each, and keep at least an eighth of
+// their buckets empty. Capacity left over from removed entries isn't counted, it's reused by the
+// next inserts.
+pub fn hash_map_heap_size<K, V>(map: &HashMap<K, V>) -> usize {
+    let buckets = map.len() * 8 / 7;
+    buckets * (size_of::<(K, V)>() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::http::HeaderMap;
+
+    use super::{HeapSize, hash_map_heap_size};
+
+    #[test]
+    fn heap_size_should_count_allocated_capacity() {
+        // given
+        let mut body = Vec::with_capacity(100);
+        body.extend_from_slice(b"{}");
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", "req_1".parse().unwrap());
+
+        // then
+        assert_eq!(body.heap_size_bytes(), 100);
+        assert_eq!(None::<String>.heap_size_bytes(), 0);
+        assert!(headers.heap_size_bytes() > "x-request-idreq_1".len());
+        assert_eq!(HeaderMap::new().heap_size_bytes(), 0);
+    }
+
+    #[test]
+    fn hash_map_heap_size_should_grow_with_entries() {
+        // given
+        let mut map: HashMap<u64, u64> = HashMap::new();
+        let empty = hash_map_heap_size(&map);
+
+        // when
+        map.extend((0..100_u64).map(|id| (id, id)));
+
+        // then
+        assert_eq!(empty, 0);
+        assert!(hash_map_heap_size(&map) >= 100 * (2 * size_of::<u64>() + 1));
+    }
+}
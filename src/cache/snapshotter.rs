@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::{MissedTickBehavior, interval_at};
+use tracing::warn;
+
+use crate::app_state::AppState;
+
+// Periodically writes a snapshot of the cache to disk. Every change is also logged as it happens,
+// snapshots keep the log (and the time it takes to replay it on startup) short.
+pub fn spawn_snapshotter(state: Arc<AppState>, snapshot_interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // the first tick of an interval completes immediately, there is nothing new to snapshot yet
+        let start = tokio::time::Instant::now() + snapshot_interval;
+        let mut ticker = interval_at(start, snapshot_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            // snapshots do blocking disk io
+            let state = state.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                for namespace in state.namespaces.all() {
+                    if let Err(err) = namespace.cache.snapshot() {
+                        warn!(error = ?err, namespace = namespace.name, "Failed to snapshot cache");
+                    }
+                }
+            })
+            .await;
+            if let Err(err) = result {
+                warn!(error = ?err, "Snapshot task failed");
+            }
+        }
+    })
+}
@@ -1,5 +1,7 @@
 use async_trait::async_trait;
+use axum::body::Bytes;
 use axum::http::HeaderMap;
+use futures::stream::BoxStream;
 use reqwest::StatusCode;
 use serde_json::Value;
 
@@ -15,6 +17,14 @@ pub trait Client: Send + Sync {
         provider: ProviderType,
         request_body: Value,
     ) -> Result<UpstreamResponse, CompletionError>;
+
+    // Same as post_http_request, but hands back the response body as it arrives instead of buffering it
+    async fn post_http_request_stream(
+        &self,
+        header_map: HeaderMap,
+        provider: ProviderType,
+        request_body: Value,
+    ) -> Result<UpstreamStreamResponse, CompletionError>;
 }
 
 pub struct UpstreamResponse {
@@ -22,3 +32,9 @@ pub struct UpstreamResponse {
     pub header_map: HeaderMap,
     pub response_body: Vec<u8>,
 }
+
+pub struct UpstreamStreamResponse {
+    pub status_code: StatusCode,
+    pub header_map: HeaderMap,
+    pub body_stream: BoxStream<'static, Result<Bytes, reqwest::Error>>,
+}
@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use axum::http::HeaderMap;
-use reqwest::{Error, Response};
+use futures::StreamExt;
+use reqwest::{Error, RequestBuilder, Response};
 use serde_json::Value;
 
 use crate::{
@@ -12,7 +13,7 @@ use crate::{
     },
 };
 
-use super::client::{Client, UpstreamResponse};
+use super::client::{Client, UpstreamResponse, UpstreamStreamResponse};
 
 pub struct HttpClient {
     reqwest_client: reqwest::Client,
@@ -26,21 +27,32 @@ impl Client for HttpClient {
         provider: ProviderType,
         request_body: Value,
     ) -> Result<UpstreamResponse, CompletionError> {
-        let upstream_url = provider.url(
-            headers.get(&PROXY_UPSTREAM_HEADER),
-            headers.get(&PROXY_UPSTREAM_HOST_HEADER),
-        )?;
-        let upstream_headers = prepare_upstream_headers(headers);
         let reqwest_response = self
-            .reqwest_client
-            .post(upstream_url)
-            .headers(upstream_headers)
-            .json(&request_body)
+            .build_request(headers, provider, request_body)?
             .send()
             .await?;
         let response = UpstreamResponse::try_from(reqwest_response).await?;
         Ok(response)
     }
+
+    async fn post_http_request_stream(
+        &self,
+        headers: HeaderMap,
+        provider: ProviderType,
+        request_body: Value,
+    ) -> Result<UpstreamStreamResponse, CompletionError> {
+        let reqwest_response = self
+            .build_request(headers, provider, request_body)?
+            .send()
+            .await?;
+        Ok(UpstreamStreamResponse::from(reqwest_response))
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HttpClient {
@@ -48,6 +60,24 @@ impl HttpClient {
         let reqwest_client = reqwest::Client::new();
         Self { reqwest_client }
     }
+
+    fn build_request(
+        &self,
+        headers: HeaderMap,
+        provider: ProviderType,
+        request_body: Value,
+    ) -> Result<RequestBuilder, CompletionError> {
+        let upstream_url = provider.url(
+            headers.get(&PROXY_UPSTREAM_HEADER),
+            headers.get(&PROXY_UPSTREAM_HOST_HEADER),
+        )?;
+        let upstream_headers = prepare_upstream_headers(headers);
+        Ok(self
+            .reqwest_client
+            .post(upstream_url)
+            .headers(upstream_headers)
+            .json(&request_body))
+    }
 }
 
 impl UpstreamResponse {
@@ -66,3 +96,18 @@ impl UpstreamResponse {
         })
     }
 }
+
+impl From<Response> for UpstreamStreamResponse {
+    fn from(response: Response) -> Self {
+        let status = response.status();
+
+        let mut response_headers = response.headers().clone();
+        remove_hop_headers(&mut response_headers);
+
+        Self {
+            status_code: status,
+            header_map: response_headers,
+            body_stream: response.bytes_stream().boxed(),
+        }
+    }
+}
@@ -2,18 +2,131 @@ use config::{Config, ConfigError};
 use serde::Deserialize;
 use tracing::{error, warn};
 
+use crate::auth::config::AuthConfig;
 use crate::cache::cache_impl::EvictionPolicy;
+use crate::cache::eviction::EvictionStrategy;
+use crate::cache::semantic_store::index_type::IndexType;
+use crate::embedding::config::EmbeddingConfig;
+use crate::endpoints::chat::coalescing::CoalescingConfig;
+use crate::endpoints::chat::partition::PartitionField;
+use crate::namespaces::config::NamespaceConfig;
+use crate::providers::context::{ContextConfig, NonTextContent};
+use crate::rerank::config::RerankConfig;
+use crate::rerank::guard::GuardConfig;
 
 const LOG_LEVEL_KEY: &'static str = "log_level";
 const PORT_KEY: &'static str = "port";
 const SIMILARITY_THRESHOLD_KEY: &'static str = "similarity_threshold";
 const EVICTION_POLICY_KEY: &'static str = "eviction_policy";
+const EVICTION_STRATEGY_KEY: &str = "eviction_strategy";
+const PARTITION_KEY_KEY: &str = "partition_key";
+const CONTEXT_STRATEGY_KEY: &str = "context_strategy";
+const NON_TEXT_CONTENT_KEY: &str = "non_text_content";
+const TTL_KEY: &str = "ttl";
+const PERSISTENCE_KEY: &str = "persistence";
+const SEMANTIC_INDEX_KEY: &str = "semantic_index";
+const EMBEDDING_KEY: &str = "embedding";
+const COALESCING_KEY: &str = "coalescing";
+const NAMESPACES_KEY: &str = "namespaces";
+const AUTH_KEY: &str = "auth";
+const RERANK_KEY: &str = "rerank";
+const GUARD_KEY: &str = "guard";
+const MAINTENANCE_KEY: &str = "maintenance";
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
-struct EvictionPolicyConfig {
-    policy_type: String,
-    value: usize,
+pub struct TtlConfig {
+    // entries never expire if unset, unless a ttl is given on insert
+    #[serde(default)]
+    pub default_seconds: Option<u64>,
+    #[serde(default = "default_reap_interval_seconds")]
+    pub reap_interval_seconds: u64,
+}
+
+fn default_reap_interval_seconds() -> u64 {
+    60
+}
+
+impl Default for TtlConfig {
+    fn default() -> Self {
+        Self {
+            default_seconds: None,
+            reap_interval_seconds: default_reap_interval_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PersistenceConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_persistence_directory")]
+    pub directory: String,
+    #[serde(default = "default_snapshot_interval_seconds")]
+    pub snapshot_interval_seconds: u64,
+}
+
+fn default_persistence_directory() -> String {
+    String::from("./data")
+}
+
+fn default_snapshot_interval_seconds() -> u64 {
+    300
+}
+
+// the cache only lives in memory unless persistence is enabled
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: default_persistence_directory(),
+            snapshot_interval_seconds: default_snapshot_interval_seconds(),
+        }
+    }
+}
+
+// Intervals of the background tasks which keep the caches in check
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct MaintenanceConfig {
+    #[serde(default = "default_consistency_check_interval_seconds")]
+    pub consistency_check_interval_seconds: u64,
+    #[serde(default = "default_memory_reconcile_interval_seconds")]
+    pub memory_reconcile_interval_seconds: u64,
+}
+
+fn default_consistency_check_interval_seconds() -> u64 {
+    60
+}
+
+fn default_memory_reconcile_interval_seconds() -> u64 {
+    30
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            consistency_check_interval_seconds: default_consistency_check_interval_seconds(),
+            memory_reconcile_interval_seconds: default_memory_reconcile_interval_seconds(),
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    fn validate(self) -> Result<Self, String> {
+        if self.consistency_check_interval_seconds == 0 {
+            return Err(String::from(
+                "consistency_check_interval_seconds must be greater than 0",
+            ));
+        }
+        if self.memory_reconcile_interval_seconds == 0 {
+            return Err(String::from(
+                "memory_reconcile_interval_seconds must be greater than 0",
+            ));
+        }
+        Ok(self)
+    }
 }
 
 pub fn from_file(config_file_name: &str) -> Config {
@@ -43,21 +156,141 @@ pub fn get_similarity_threshold(conf: &Config) -> Result<f64, ConfigError> {
 }
 
 pub fn get_eviction_policy(conf: &Config) -> Result<EvictionPolicy, ConfigError> {
-    let policy: EvictionPolicyConfig = with_log(
-        || conf.get::<EvictionPolicyConfig>(EVICTION_POLICY_KEY),
+    let eviction_policy = with_log(
+        || conf.get::<EvictionPolicy>(EVICTION_POLICY_KEY),
         EVICTION_POLICY_KEY,
     )?;
+    eviction_policy.validate().map_err(ConfigError::Message)
+}
 
-    match policy.policy_type.as_str() {
-        "entry_limit" => Ok(EvictionPolicy::EntryLimit(policy.value)),
-        "memory_limit_mb" => Ok(EvictionPolicy::MemoryLimitMb(policy.value)),
-        other => {
-            warn!(ty = other, "Unknown eviction policy type");
-            Err(ConfigError::Message(format!(
-                "Unknown eviction policy type: {}",
-                other
-            )))
-        }
+pub fn get_eviction_strategy(conf: &Config) -> Result<EvictionStrategy, ConfigError> {
+    with_log(
+        || or_default_if_missing(conf.get::<EvictionStrategy>(EVICTION_STRATEGY_KEY)),
+        EVICTION_STRATEGY_KEY,
+    )
+}
+
+pub fn get_partition_fields(conf: &Config) -> Result<Vec<PartitionField>, ConfigError> {
+    // requests are partitioned on everything unless the fields are narrowed down
+    with_log(
+        || match conf.get::<Vec<PartitionField>>(PARTITION_KEY_KEY) {
+            Err(ConfigError::NotFound(_)) => Ok(PartitionField::all()),
+            result => result,
+        },
+        PARTITION_KEY_KEY,
+    )
+}
+
+pub fn get_context_config(conf: &Config) -> Result<ContextConfig, ConfigError> {
+    let context_config = with_log(
+        || or_default_if_missing(conf.get::<ContextConfig>(CONTEXT_STRATEGY_KEY)),
+        CONTEXT_STRATEGY_KEY,
+    )?;
+    context_config
+        .validate()
+        .map_err(|err| ConfigError::Message(err.to_string()))
+}
+
+pub fn get_non_text_content(conf: &Config) -> Result<NonTextContent, ConfigError> {
+    with_log(
+        || or_default_if_missing(conf.get::<NonTextContent>(NON_TEXT_CONTENT_KEY)),
+        NON_TEXT_CONTENT_KEY,
+    )
+}
+
+pub fn get_ttl_config(conf: &Config) -> Result<TtlConfig, ConfigError> {
+    with_log(
+        || or_default_if_missing(conf.get::<TtlConfig>(TTL_KEY)),
+        TTL_KEY,
+    )
+}
+
+pub fn get_persistence_config(conf: &Config) -> Result<PersistenceConfig, ConfigError> {
+    with_log(
+        || or_default_if_missing(conf.get::<PersistenceConfig>(PERSISTENCE_KEY)),
+        PERSISTENCE_KEY,
+    )
+}
+
+pub fn get_index_type(conf: &Config) -> Result<IndexType, ConfigError> {
+    let index_type = with_log(
+        || or_default_if_missing(conf.get::<IndexType>(SEMANTIC_INDEX_KEY)),
+        SEMANTIC_INDEX_KEY,
+    )?;
+    index_type.validate().map_err(ConfigError::Message)
+}
+
+pub fn get_embedding_config(conf: &Config) -> Result<EmbeddingConfig, ConfigError> {
+    let embedding_config = with_log(
+        || or_default_if_missing(conf.get::<EmbeddingConfig>(EMBEDDING_KEY)),
+        EMBEDDING_KEY,
+    )?;
+    embedding_config
+        .validate()
+        .map_err(|err| ConfigError::Message(err.to_string()))
+}
+
+pub fn get_coalescing_config(conf: &Config) -> Result<CoalescingConfig, ConfigError> {
+    with_log(
+        || or_default_if_missing(conf.get::<CoalescingConfig>(COALESCING_KEY)),
+        COALESCING_KEY,
+    )
+}
+
+pub fn get_namespace_config(conf: &Config) -> Result<NamespaceConfig, ConfigError> {
+    let namespace_config = with_log(
+        || or_default_if_missing(conf.get::<NamespaceConfig>(NAMESPACES_KEY)),
+        NAMESPACES_KEY,
+    )?;
+    namespace_config
+        .validate()
+        .map_err(|err| ConfigError::Message(err.to_string()))
+}
+
+pub fn get_auth_config(conf: &Config) -> Result<AuthConfig, ConfigError> {
+    let auth_config = with_log(
+        || or_default_if_missing(conf.get::<AuthConfig>(AUTH_KEY)),
+        AUTH_KEY,
+    )?;
+    auth_config
+        .load_keys()
+        .map_err(|err| ConfigError::Message(err.to_string()))
+}
+
+pub fn get_rerank_config(conf: &Config) -> Result<RerankConfig, ConfigError> {
+    let rerank_config = with_log(
+        || or_default_if_missing(conf.get::<RerankConfig>(RERANK_KEY)),
+        RERANK_KEY,
+    )?;
+    rerank_config
+        .validate()
+        .map_err(|err| ConfigError::Message(err.to_string()))
+}
+
+pub fn get_guard_config(conf: &Config) -> Result<GuardConfig, ConfigError> {
+    let guard_config = with_log(
+        || or_default_if_missing(conf.get::<GuardConfig>(GUARD_KEY)),
+        GUARD_KEY,
+    )?;
+    guard_config
+        .validate()
+        .map_err(|err| ConfigError::Message(err.to_string()))
+}
+
+pub fn get_maintenance_config(conf: &Config) -> Result<MaintenanceConfig, ConfigError> {
+    let maintenance_config = with_log(
+        || or_default_if_missing(conf.get::<MaintenanceConfig>(MAINTENANCE_KEY)),
+        MAINTENANCE_KEY,
+    )?;
+    maintenance_config.validate().map_err(ConfigError::Message)
+}
+
+// Sections which weren't part of the original config fall back to their default when missing, so
+// existing config files keep working. A section which is present but malformed is still an error.
+fn or_default_if_missing<T: Default>(result: Result<T, ConfigError>) -> Result<T, ConfigError> {
+    match result {
+        Err(ConfigError::NotFound(_)) => Ok(T::default()),
+        result => result,
     }
 }
 
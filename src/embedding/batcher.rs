@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::embedding::error::EmbeddingError;
+use crate::metrics::metrics::{EMBEDDING_BATCH_SIZE, EMBEDDING_QUEUE_DEPTH};
+
+// prompts waiting to be batched, embed calls wait for room once it's full
+const QUEUE_CAPACITY: usize = 1024;
+
+// A prompt waiting to be embedded, and where to send its embedding
+pub struct PendingEmbedding {
+    pub text: String,
+    respond_to: oneshot::Sender<Result<Vec<f32>, EmbeddingError>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    // most prompts embedded together
+    pub max_batch_size: usize,
+    // how long to wait for more prompts once one arrives, zero only batches prompts which are
+    // already waiting
+    pub batch_window: Duration,
+}
+
+// The sending half of a queue of prompts, which embedding workers take batches from
+pub struct EmbeddingQueue {
+    sender: mpsc::Sender<PendingEmbedding>,
+}
+
+impl EmbeddingQueue {
+    pub fn new() -> (Self, mpsc::Receiver<PendingEmbedding>) {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        (Self { sender }, receiver)
+    }
+
+    // Queues the prompt and waits for a worker to embed it
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
+        let (respond_to, response) = oneshot::channel();
+        let pending = PendingEmbedding {
+            text: text.to_string(),
+            respond_to,
+        };
+        // counted before sending, a worker might take it off the queue straight away
+        EMBEDDING_QUEUE_DEPTH.inc();
+        self.sender.send(pending).await.map_err(|_| {
+            EMBEDDING_QUEUE_DEPTH.dec();
+            EmbeddingError::GenerationError(String::from("Embedding workers have stopped"))
+        })?;
+        response.await.map_err(|_| {
+            EmbeddingError::GenerationError(String::from("Embedding request was dropped"))
+        })?
+    }
+}
+
+// Waits for a prompt, then takes up to a full batch of prompts from the queue. None once every
+// sender has been dropped.
+pub async fn next_batch(
+    receiver: &mut mpsc::Receiver<PendingEmbedding>,
+    config: &BatchConfig,
+) -> Option<Vec<PendingEmbedding>> {
+    let first = receiver.recv().await?;
+    if !config.batch_window.is_zero() {
+        tokio::time::sleep(config.batch_window).await;
+    }
+    Some(fill_batch(first, receiver, config))
+}
+
+// Same as next_batch, for embedding workers running on their own threads
+pub fn blocking_next_batch(
+    receiver: &mut mpsc::Receiver<PendingEmbedding>,
+    config: &BatchConfig,
+) -> Option<Vec<PendingEmbedding>> {
+    let first = receiver.blocking_recv()?;
+    if !config.batch_window.is_zero() {
+        std::thread::sleep(config.batch_window);
+    }
+    Some(fill_batch(first, receiver, config))
+}
+
+fn fill_batch(
+    first: PendingEmbedding,
+    receiver: &mut mpsc::Receiver<PendingEmbedding>,
+    config: &BatchConfig,
+) -> Vec<PendingEmbedding> {
+    let mut batch = vec![first];
+    while batch.len() < config.max_batch_size {
+        match receiver.try_recv() {
+            Ok(pending) => batch.push(pending),
+            Err(_) => break,
+        }
+    }
+    EMBEDDING_QUEUE_DEPTH.sub(batch.len() as i64);
+    EMBEDDING_BATCH_SIZE.observe(batch.len() as f64);
+    batch
+}
+
+// Hands every prompt of the batch its embedding, in order, or the error if embedding failed
+pub fn respond(batch: Vec<PendingEmbedding>, result: Result<Vec<Vec<f32>>, String>) {
+    match result {
+        Ok(embeddings) => {
+            for (pending, embedding) in batch.into_iter().zip(embeddings) {
+                // the caller might have given up waiting
+                let _ = pending.respond_to.send(Ok(embedding));
+            }
+        }
+        Err(message) => {
+            for pending in batch {
+                let _ = pending
+                    .respond_to
+                    .send(Err(EmbeddingError::GenerationError(message.clone())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BatchConfig, EmbeddingQueue, next_batch, respond};
+
+    const CONFIG: BatchConfig = BatchConfig {
+        max_batch_size: 2,
+        batch_window: Duration::ZERO,
+    };
+
+    #[tokio::test]
+    async fn next_batch_should_take_waiting_prompts_up_to_max_batch_size() {
+        // given
+        let (queue, mut receiver) = EmbeddingQueue::new();
+        let worker = tokio::spawn(async move {
+            let mut sizes = vec![];
+            while let Some(batch) = next_batch(&mut receiver, &CONFIG).await {
+                sizes.push(batch.len());
+                let embeddings = batch
+                    .iter()
+                    .map(|pending| vec![pending.text.len() as f32])
+                    .collect();
+                respond(batch, Ok(embeddings));
+            }
+            sizes
+        });
+
+        // when
+        let (first, second, third) =
+            tokio::join!(queue.embed("a"), queue.embed("bb"), queue.embed("ccc"));
+        drop(queue);
+
+        // then
+        assert_eq!(first.unwrap(), vec![1.0]);
+        assert_eq!(second.unwrap(), vec![2.0]);
+        assert_eq!(third.unwrap(), vec![3.0]);
+        let sizes = worker.await.unwrap();
+        assert_eq!(sizes.iter().sum::<usize>(), 3);
+        assert!(sizes.iter().all(|size| *size <= CONFIG.max_batch_size));
+    }
+
+    #[tokio::test]
+    async fn respond_should_fail_whole_batch_on_error() {
+        // given
+        let (queue, mut receiver) = EmbeddingQueue::new();
+        tokio::spawn(async move {
+            while let Some(batch) = next_batch(&mut receiver, &CONFIG).await {
+                respond(batch, Err(String::from("model unavailable")));
+            }
+        });
+
+        // when
+        let (first, second) = tokio::join!(queue.embed("a"), queue.embed("b"));
+
+        // then
+        assert!(first.is_err());
+        assert!(second.is_err());
+    }
+}
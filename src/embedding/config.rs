@@ -0,0 +1,38 @@
+use serde::Deserialize;
+
+use crate::embedding::error::EmbeddingError;
+use crate::embedding::fastembed::{FastEmbedConfig, FastEmbedService};
+use crate::embedding::http::{HttpEmbeddingConfig, HttpEmbeddingService};
+use crate::embedding::service::EmbeddingService;
+
+// Where prompts get embedded
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum EmbeddingConfig {
+    // in process, with a fastembed ONNX model
+    Fastembed(FastEmbedConfig),
+    // by an OpenAI compatible embeddings API
+    Http(HttpEmbeddingConfig),
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        EmbeddingConfig::Fastembed(FastEmbedConfig::default())
+    }
+}
+
+impl EmbeddingConfig {
+    pub fn validate(self) -> Result<Self, EmbeddingError> {
+        match self {
+            EmbeddingConfig::Fastembed(config) => config.validate().map(EmbeddingConfig::Fastembed),
+            EmbeddingConfig::Http(config) => config.validate().map(EmbeddingConfig::Http),
+        }
+    }
+
+    pub fn new_service(self) -> Box<dyn EmbeddingService> {
+        match self {
+            EmbeddingConfig::Fastembed(config) => Box::new(FastEmbedService::new(&config)),
+            EmbeddingConfig::Http(config) => Box::new(HttpEmbeddingService::new(config)),
+        }
+    }
+}
@@ -1,46 +1,265 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::embedding::batcher::{
+    BatchConfig, EmbeddingQueue, PendingEmbedding, blocking_next_batch, respond,
+};
 use crate::embedding::error::EmbeddingError;
 use crate::embedding::service::EmbeddingService;
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
-use tracing::error;
+use fastembed::{
+    EmbeddingModel, InitOptions, InitOptionsUserDefined, TextEmbedding, TokenizerFiles,
+    UserDefinedEmbeddingModel,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info};
 
-pub struct FastEmbedService {
-    text_embedding: TextEmbedding,
-    model_name: EmbeddingModel,
+const DEFAULT_MODEL: &str = "AllMiniLML6V2";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FastEmbedConfig {
+    // named as in fastembed's EmbeddingModel, e.g. BGESmallENV15 or MultilingualE5Small
+    #[serde(default = "default_model")]
+    pub model: String,
+    // directory holding the model's files, for hosts which can't download them
+    pub model_directory: Option<String>,
+    // threads running the model, each embeds one batch at a time
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    // most prompts embedded together
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    // how long a worker waits for more prompts before embedding a batch, 0 only batches prompts
+    // which are already waiting
+    #[serde(default)]
+    pub batch_window_ms: u64,
 }
 
-impl FastEmbedService {
-    pub fn new() -> Self {
-        let text_embedding = TextEmbedding::try_new(
-            InitOptions::new(EmbeddingModel::AllMiniLML6V2).with_show_download_progress(true),
-        );
+fn default_model() -> String {
+    String::from(DEFAULT_MODEL)
+}
 
-        Self {
-            text_embedding: text_embedding.unwrap_or_else(|err| {
-                error!(error = ?err);
-                panic!("failed to init text_embedding")
-            }),
-            model_name: EmbeddingModel::AllMiniLML6V2,
+fn default_workers() -> usize {
+    1
+}
+
+fn default_max_batch_size() -> usize {
+    32
+}
+
+impl Default for FastEmbedConfig {
+    fn default() -> Self {
+        FastEmbedConfig {
+            model: default_model(),
+            model_directory: None,
+            workers: default_workers(),
+            max_batch_size: default_max_batch_size(),
+            batch_window_ms: 0,
         }
     }
+}
+
+impl FastEmbedConfig {
+    pub fn validate(self) -> Result<Self, EmbeddingError> {
+        if self.workers == 0 || self.max_batch_size == 0 {
+            return Err(EmbeddingError::SetupError(String::from(
+                "workers and max_batch_size must be positive",
+            )));
+        }
+        self.embedding_model()?;
+        Ok(self)
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig {
+            max_batch_size: self.max_batch_size,
+            batch_window: Duration::from_millis(self.batch_window_ms),
+        }
+    }
+
+    fn embedding_model(&self) -> Result<EmbeddingModel, EmbeddingError> {
+        // the variant name is preferred, model codes are shared by quantized and full models
+        let models = TextEmbedding::list_supported_models();
+        models
+            .iter()
+            .find(|info| format!("{:?}", info.model).eq_ignore_ascii_case(&self.model))
+            .or_else(|| {
+                models
+                    .iter()
+                    .find(|info| info.model_code.eq_ignore_ascii_case(&self.model))
+            })
+            .map(|info| info.model.clone())
+            .ok_or_else(|| {
+                EmbeddingError::SetupError(format!("Unknown embedding model '{}'", self.model))
+            })
+    }
+}
+
+// Runs the model on a pool of dedicated worker threads, so embedding doesn't block the async
+// runtime. Workers embed concurrently queued prompts together in a single call.
+pub struct FastEmbedService {
+    queue: EmbeddingQueue,
+    dimensionality: u32,
+}
+
+impl FastEmbedService {
+    pub fn new(config: &FastEmbedConfig) -> Self {
+        Self::try_new(config).unwrap_or_else(|err| {
+            error!(error = ?err);
+            panic!("failed to init text_embedding")
+        })
+    }
 
-    pub fn get_dimensionality(&self) -> u32 {
-        match &self.model_name {
-            EmbeddingModel::AllMiniLML6V2 => 384,
-            _ => panic!(
-                "{}",
-                EmbeddingError::SetupError(String::from("Embedding model with unknown size",))
+    fn try_new(config: &FastEmbedConfig) -> Result<Self, EmbeddingError> {
+        let model = config.embedding_model()?;
+        let dimensionality = TextEmbedding::get_model_info(&model)
+            .map_err(|err| EmbeddingError::SetupError(err.to_string()))?
+            .dim as u32;
+
+        let text_embedding = match &config.model_directory {
+            Some(directory) => {
+                info!("Loading embedding model {:?} from {}", model, directory);
+                TextEmbedding::try_new_from_user_defined(
+                    load_local_model(&model, Path::new(directory))?,
+                    InitOptionsUserDefined::new(),
+                )
+            }
+            None => TextEmbedding::try_new(
+                InitOptions::new(model.clone()).with_show_download_progress(true),
             ),
         }
+        .map_err(|err| EmbeddingError::SetupError(err.to_string()))?;
+
+        let text_embedding = Arc::new(text_embedding);
+        let (queue, receiver) = EmbeddingQueue::new();
+        // workers take turns collecting a batch from the queue
+        let receiver = Arc::new(Mutex::new(receiver));
+        for worker in 0..config.workers {
+            let text_embedding = text_embedding.clone();
+            let receiver = receiver.clone();
+            let batch_config = config.batch_config();
+            thread::Builder::new()
+                .name(format!("embedding-worker-{worker}"))
+                .spawn(move || run_worker(&text_embedding, &receiver, &batch_config))
+                .map_err(|err| EmbeddingError::SetupError(err.to_string()))?;
+        }
+
+        Ok(Self {
+            queue,
+            dimensionality,
+        })
+    }
+}
+
+// Embeds batches of queued prompts until the service has been dropped
+fn run_worker(
+    text_embedding: &TextEmbedding,
+    receiver: &Mutex<mpsc::Receiver<PendingEmbedding>>,
+    batch_config: &BatchConfig,
+) {
+    loop {
+        let batch = {
+            let mut receiver = receiver.lock().unwrap_or_else(|err| {
+                error!(error = ?err, "Mutex poisoned");
+                panic!("Embedding queue mutex poisoned")
+            });
+            blocking_next_batch(&mut receiver, batch_config)
+        };
+        let Some(batch) = batch else {
+            return;
+        };
+        debug!("Embedding batch of {} prompts", batch.len());
+        let texts: Vec<&str> = batch.iter().map(|pending| pending.text.as_str()).collect();
+        let result = text_embedding
+            .embed(texts, Some(batch.len()))
+            .map_err(|err| err.to_string());
+        respond(batch, result);
     }
 }
 
+// Reads the files of `model` from `directory`, laid out as in the model's huggingface repository
+fn load_local_model(
+    model: &EmbeddingModel,
+    directory: &Path,
+) -> Result<UserDefinedEmbeddingModel, EmbeddingError> {
+    let read = |file_name: &str| {
+        let path = directory.join(file_name);
+        fs::read(&path).map_err(|err| {
+            EmbeddingError::SetupError(format!("Failed to read {}: {err}", path.display()))
+        })
+    };
+    let model_info = TextEmbedding::get_model_info(model)
+        .map_err(|err| EmbeddingError::SetupError(err.to_string()))?;
+
+    let tokenizer_files = TokenizerFiles {
+        tokenizer_file: read("tokenizer.json")?,
+        config_file: read("config.json")?,
+        special_tokens_map_file: read("special_tokens_map.json")?,
+        tokenizer_config_file: read("tokenizer_config.json")?,
+    };
+    let mut local_model =
+        UserDefinedEmbeddingModel::new(read(&model_info.model_file)?, tokenizer_files)
+            .with_quantization(TextEmbedding::get_quantization_mode(model));
+    // pooling has to match what fastembed uses when it downloads the model itself
+    if let Some(pooling) = TextEmbedding::get_default_pooling_method(model) {
+        local_model = local_model.with_pooling(pooling);
+    }
+    Ok(local_model)
+}
+
+#[async_trait]
 impl EmbeddingService for FastEmbedService {
-    fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
-        let embeddings = self
-            .text_embedding
-            .embed(vec![text], None)
-            .map_err(|e| EmbeddingError::GenerationError(e.to_string()))?;
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
+        self.queue.embed(text).await
+    }
+
+    fn get_dimensionality(&self) -> u32 {
+        self.dimensionality
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastembed::EmbeddingModel;
+
+    use super::FastEmbedConfig;
+
+    fn config(model: &str) -> FastEmbedConfig {
+        FastEmbedConfig {
+            model: String::from(model),
+            ..FastEmbedConfig::default()
+        }
+    }
+
+    #[test]
+    fn should_resolve_model_by_variant_name() {
+        assert_eq!(
+            FastEmbedConfig::default().embedding_model().unwrap(),
+            EmbeddingModel::AllMiniLML6V2
+        );
+        assert_eq!(
+            config("bgesmallenv15q").embedding_model().unwrap(),
+            EmbeddingModel::BGESmallENV15Q
+        );
+    }
+
+    #[test]
+    fn should_resolve_model_by_model_code() {
+        assert_eq!(
+            config("intfloat/multilingual-e5-small")
+                .embedding_model()
+                .unwrap(),
+            EmbeddingModel::MultilingualE5Small
+        );
+    }
 
-        Ok(embeddings.into_iter().next().unwrap())
+    #[test]
+    fn should_reject_unknown_model() {
+        assert!(config("word2vec").validate().is_err());
     }
 }
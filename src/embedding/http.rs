@@ -0,0 +1,311 @@
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, error, warn};
+
+use crate::embedding::batcher::{
+    BatchConfig, EmbeddingQueue, PendingEmbedding, next_batch, respond,
+};
+use crate::embedding::error::EmbeddingError;
+use crate::embedding::service::EmbeddingService;
+
+#[derive(Clone, Deserialize)]
+pub struct HttpEmbeddingConfig {
+    // full url of an OpenAI compatible embeddings endpoint, e.g. http://localhost:11434/v1/embeddings
+    pub url: String,
+    pub model: String,
+    // sent as a bearer token if set
+    pub api_key: Option<String>,
+    // length of the vectors returned by the model
+    pub dimensionality: u32,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    // most prompts sent in a single request
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    // how long to wait for more prompts before sending a request, 0 only batches prompts which
+    // are already waiting
+    #[serde(default)]
+    pub batch_window_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_max_batch_size() -> usize {
+    32
+}
+
+// keeps the api key out of the logs
+impl fmt::Debug for HttpEmbeddingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpEmbeddingConfig")
+            .field("url", &self.url)
+            .field("model", &self.model)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("dimensionality", &self.dimensionality)
+            .field("timeout_ms", &self.timeout_ms)
+            .field("max_batch_size", &self.max_batch_size)
+            .field("batch_window_ms", &self.batch_window_ms)
+            .finish()
+    }
+}
+
+impl HttpEmbeddingConfig {
+    pub fn validate(self) -> Result<Self, EmbeddingError> {
+        if self.dimensionality == 0 || self.max_batch_size == 0 {
+            return Err(EmbeddingError::SetupError(String::from(
+                "dimensionality and max_batch_size must be positive",
+            )));
+        }
+        reqwest::Url::parse(&self.url).map_err(|err| {
+            EmbeddingError::SetupError(format!("Invalid embedding url '{}': {err}", self.url))
+        })?;
+        Ok(self)
+    }
+
+    fn batch_config(&self) -> BatchConfig {
+        BatchConfig {
+            max_batch_size: self.max_batch_size,
+            batch_window: Duration::from_millis(self.batch_window_ms),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    index: usize,
+}
+
+// Embeds prompts with an OpenAI compatible embeddings API (OpenAI, Ollama, TEI, vLLM...).
+// Prompts embedded concurrently are sent together, in batches of up to max_batch_size.
+pub struct HttpEmbeddingService {
+    queue: EmbeddingQueue,
+    dimensionality: u32,
+}
+
+impl HttpEmbeddingService {
+    // Must be called from within a tokio runtime, which runs the batching task
+    pub fn new(config: HttpEmbeddingConfig) -> Self {
+        let reqwest_client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .unwrap_or_else(|err| {
+                error!(error = ?err);
+                panic!("failed to init embedding http client")
+            });
+        let (queue, receiver) = EmbeddingQueue::new();
+        let dimensionality = config.dimensionality;
+        let client = EmbeddingsClient {
+            reqwest_client,
+            config,
+        };
+        tokio::spawn(client.run(receiver));
+        Self {
+            queue,
+            dimensionality,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingService for HttpEmbeddingService {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError> {
+        self.queue.embed(text).await
+    }
+
+    fn get_dimensionality(&self) -> u32 {
+        self.dimensionality
+    }
+}
+
+#[derive(Clone)]
+struct EmbeddingsClient {
+    reqwest_client: reqwest::Client,
+    config: HttpEmbeddingConfig,
+}
+
+impl EmbeddingsClient {
+    // Sends batches of waiting prompts until the service has been dropped
+    async fn run(self, mut receiver: mpsc::Receiver<PendingEmbedding>) {
+        let batch_config = self.config.batch_config();
+        while let Some(batch) = next_batch(&mut receiver, &batch_config).await {
+            // batches are sent concurrently, so a slow request doesn't hold up the ones behind it
+            tokio::spawn(self.clone().send_batch(batch));
+        }
+    }
+
+    async fn send_batch(self, batch: Vec<PendingEmbedding>) {
+        debug!("Sending batch of {} prompts to embed", batch.len());
+        let texts: Vec<&str> = batch.iter().map(|pending| pending.text.as_str()).collect();
+        let result = self.request_embeddings(texts).await;
+        if let Err(message) = &result {
+            warn!("Embedding request failed: {}", message);
+        }
+        respond(batch, result);
+    }
+
+    async fn request_embeddings(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>, String> {
+        let expected = texts.len();
+        let mut request = self
+            .reqwest_client
+            .post(&self.config.url)
+            .json(&EmbeddingsRequest {
+                model: &self.config.model,
+                input: texts,
+            });
+        if let Some(api_key) = &self.config.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?;
+        let response: EmbeddingsResponse = response.json().await.map_err(|err| err.to_string())?;
+        into_embeddings(response, expected, self.config.dimensionality)
+    }
+}
+
+// Orders the returned embeddings like the prompts they were requested for
+fn into_embeddings(
+    response: EmbeddingsResponse,
+    expected: usize,
+    dimensionality: u32,
+) -> Result<Vec<Vec<f32>>, String> {
+    let mut data = response.data;
+    if data.len() != expected {
+        return Err(format!(
+            "Expected {expected} embeddings, received {}",
+            data.len()
+        ));
+    }
+    data.sort_by_key(|embedding| embedding.index);
+    if data
+        .iter()
+        .enumerate()
+        .any(|(i, embedding)| embedding.index != i)
+    {
+        return Err(String::from("Received embeddings with invalid indices"));
+    }
+    if let Some(embedding) = data
+        .iter()
+        .find(|embedding| embedding.embedding.len() != dimensionality as usize)
+    {
+        return Err(format!(
+            "Expected embeddings of dimensionality {dimensionality}, received {}",
+            embedding.embedding.len()
+        ));
+    }
+    Ok(data
+        .into_iter()
+        .map(|embedding| embedding.embedding)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{Json, Router, extract::State, routing::post};
+    use serde_json::{Value, json};
+
+    use super::{EmbeddingsResponse, HttpEmbeddingConfig, HttpEmbeddingService, into_embeddings};
+    use crate::embedding::service::EmbeddingService;
+
+    fn response(data: Value) -> EmbeddingsResponse {
+        serde_json::from_value(json!({ "object": "list", "data": data })).unwrap()
+    }
+
+    #[test]
+    fn into_embeddings_should_order_by_index() {
+        let data = json!([
+            {"object": "embedding", "embedding": [0.0, 1.0], "index": 1},
+            {"object": "embedding", "embedding": [1.0, 0.0], "index": 0}
+        ]);
+
+        let embeddings = into_embeddings(response(data), 2, 2).unwrap();
+
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+    }
+
+    #[test]
+    fn into_embeddings_should_reject_unexpected_responses() {
+        let data = json!([{"embedding": [1.0, 0.0], "index": 0}]);
+        // missing embeddings
+        assert!(into_embeddings(response(data.clone()), 2, 2).is_err());
+        // wrong dimensionality
+        assert!(into_embeddings(response(data), 1, 3).is_err());
+    }
+
+    #[tokio::test]
+    async fn concurrent_embeds_should_be_sent_in_one_batch() {
+        // given
+        // an embeddings endpoint returning each prompt's length as its embedding
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/v1/embeddings",
+                post(
+                    |State(requests): State<Arc<AtomicUsize>>, Json(body): Json<Value>| async move {
+                        requests.fetch_add(1, Ordering::Relaxed);
+                        let data: Vec<Value> = body["input"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .enumerate()
+                            .map(|(index, input)| {
+                                let len = input.as_str().unwrap().len() as f32;
+                                json!({"embedding": [len], "index": index})
+                            })
+                            .collect();
+                        Json(json!({"data": data}))
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let service = HttpEmbeddingService::new(HttpEmbeddingConfig {
+            url: format!("http://{address}/v1/embeddings"),
+            model: String::from("test"),
+            api_key: None,
+            dimensionality: 1,
+            timeout_ms: 5000,
+            max_batch_size: 8,
+            batch_window_ms: 50,
+        });
+
+        // when
+        let (first, second, third) = tokio::join!(
+            service.embed("a"),
+            service.embed("bb"),
+            service.embed("ccc")
+        );
+
+        // then
+        assert_eq!(first.unwrap(), vec![1.0]);
+        assert_eq!(second.unwrap(), vec![2.0]);
+        assert_eq!(third.unwrap(), vec![3.0]);
+        assert_eq!(requests.load(Ordering::Relaxed), 1);
+    }
+}
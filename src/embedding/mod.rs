@@ -1,3 +1,6 @@
+pub mod batcher;
+pub mod config;
 pub mod error;
 pub mod fastembed;
+pub mod http;
 pub mod service;
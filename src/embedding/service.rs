@@ -1,8 +1,13 @@
+use async_trait::async_trait;
 use mockall::automock;
 
 use crate::embedding::error::EmbeddingError;
 
 #[automock]
+#[async_trait]
 pub trait EmbeddingService: Send + Sync {
-    fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError>;
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, EmbeddingError>;
+
+    // Length of the vectors returned by embed
+    fn get_dimensionality(&self) -> u32;
 }
@@ -1,13 +1,21 @@
 use axum::response::{IntoResponse, Response};
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{debug, error};
 
-use axum::{Json, extract::State};
+use axum::{Json, extract::State, http::HeaderMap};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{app_state::AppState, cache::error::CacheError, embedding::error::EmbeddingError};
+use crate::{
+    app_state::AppState,
+    cache::{cached_response::CachedResponse, error::CacheError, partition::PartitionKey},
+    embedding::error::EmbeddingError,
+    namespaces::error::NamespaceError,
+    rerank::error::RerankError,
+    utils::header_utils::{cache_hit_headers, cached_entry_headers},
+};
 
 #[derive(Debug, Error)]
 pub enum CacheAsideError {
@@ -15,6 +23,10 @@ pub enum CacheAsideError {
     InternalEmbedding(#[from] EmbeddingError),
     #[error("Error in caching layer: {0}")]
     InternalCache(#[from] CacheError),
+    #[error("Error verifying cache hit: {0}")]
+    InternalRerank(#[from] RerankError),
+    #[error("Namespace error: {0}")]
+    Namespace(#[from] NamespaceError),
 }
 
 impl IntoResponse for CacheAsideError {
@@ -28,6 +40,11 @@ impl IntoResponse for CacheAsideError {
                 error!(?err, "returning internal error to user");
                 (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
             }
+            Self::InternalRerank(err) => {
+                error!(?err, "returning internal error to user");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+            }
+            Self::Namespace(err) => err.into_response(),
         }
     }
 }
@@ -41,17 +58,29 @@ pub struct GetRequest {
 pub struct PutRequest {
     pub key: String,
     pub data: String,
+    // overrides the default time to live of the cache
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
 }
 
 pub async fn get(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(request): Json<GetRequest>,
 ) -> Result<Response, CacheAsideError> {
     debug!("cache_aside::GET request received");
-    let embedding = state.embedding_service.embed(&request.key)?;
-    let saved_response = state.cache.get_if_present(&embedding)?;
+    let namespace = state.namespaces.resolve(&headers)?;
+    let embedding = state.embedding_service.embed(&request.key).await?;
+    let saved_response = namespace
+        .find_hit::<CacheAsideError>(&request.key, PartitionKey::default(), &embedding, None)
+        .await?;
     let http_response = match saved_response {
-        Some(response_bytes) => (StatusCode::OK, response_bytes).into_response(),
+        Some(hit) => {
+            namespace.cache.record_hit(hit.entry_id);
+            let mut response_headers = cache_hit_headers(&hit);
+            response_headers.extend(cached_entry_headers(&hit.response));
+            (StatusCode::OK, response_headers, hit.response.body).into_response()
+        }
         None => (StatusCode::NOT_FOUND).into_response(),
     };
     Ok(http_response)
@@ -59,34 +88,53 @@ pub async fn get(
 
 pub async fn put(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(request): Json<PutRequest>,
 ) -> Result<Response, CacheAsideError> {
     debug!("cache_aside::PUT request received");
-    let body: Vec<u8> = request.data.into_bytes();
-    let embedding = state.embedding_service.embed(&request.key)?;
+    let namespace = state.namespaces.resolve(&headers)?;
+    let body = CachedResponse::from_body(request.data.into_bytes()).with_prompt(&request.key);
+    let ttl = request.ttl_seconds.map(Duration::from_secs);
+    let embedding = state.embedding_service.embed(&request.key).await?;
     // if we already have an entry associated with the prompt, update it
-    let updated_existing_entry = state.cache.try_update(&embedding, body.clone())?;
+    let updated_existing_entry =
+        namespace
+            .cache
+            .try_update(PartitionKey::default(), &embedding, body.clone(), ttl)?;
     if !updated_existing_entry {
-        state.cache.insert(embedding, body)?;
+        namespace
+            .cache
+            .insert(PartitionKey::default(), embedding, body, ttl)?;
     }
     Ok((StatusCode::OK).into_response())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{sync::Arc, usize};
+    use std::{sync::Arc, time::Duration, usize};
 
-    use axum::{body, extract::State};
-    use mockall::predicate::eq;
+    use axum::{body, extract::State, http::HeaderMap};
+    use mockall::predicate::{self, eq};
     use reqwest::StatusCode;
 
     use crate::{
         app_state::AppState,
-        cache::{cache::MockCache, error::CacheError},
+        cache::{
+            cache::{CacheHit, MockCache},
+            cached_response::CachedResponse,
+            error::CacheError,
+            partition::PartitionKey,
+        },
         embedding::{error::EmbeddingError, service::MockEmbeddingService},
         endpoints::cache_aside::handler::{CacheAsideError, GetRequest, PutRequest, get, put},
+        namespaces::registry::Namespaces,
+        providers::context::{ContextConfig, NonTextContent},
     };
 
+    fn has_body(body: Vec<u8>) -> impl mockall::Predicate<CachedResponse> {
+        predicate::function(move |response: &CachedResponse| response.body == body)
+    }
+
     #[tokio::test]
     async fn get_should_return_error_on_cache_failure() {
         // given
@@ -101,8 +149,8 @@ mod tests {
             .returning(move |_| Ok(embedding.clone()));
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
-        mock_cache.expect_get_if_present().returning(|_| {
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache.expect_get_if_present().returning(|_, _, _| {
             Err(CacheError::FaissRetrievalError(
                 faiss::error::Error::IndexDescription,
             ))
@@ -115,7 +163,10 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -124,7 +175,7 @@ mod tests {
         };
 
         // when
-        let result = get(State(app_state), axum::Json(request_body)).await;
+        let result = get(State(app_state), HeaderMap::new(), axum::Json(request_body)).await;
 
         // then
         match result {
@@ -157,7 +208,10 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -166,7 +220,7 @@ mod tests {
         };
 
         // when
-        let result = get(State(app_state), axum::Json(request_body)).await;
+        let result = get(State(app_state), HeaderMap::new(), axum::Json(request_body)).await;
 
         // then
         match result {
@@ -190,14 +244,26 @@ mod tests {
         });
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
         mock_cache
             .expect_get_if_present()
-            .with(eq(embedding))
+            .with(eq(PartitionKey::default()), eq(embedding), eq(None))
             .returning({
                 let response_clone = response.clone();
-                move |_| Ok(Some(response_clone.clone()))
+                move |_, _, _| {
+                    Ok(Some(CacheHit {
+                        response: CachedResponse::from_body(response_clone.clone()),
+                        entry_id: 3,
+                        similarity: 0.925,
+                        similarity_threshold: 0.9,
+                    }))
+                }
             });
+        mock_cache
+            .expect_record_hit()
+            .with(eq(3))
+            .times(1)
+            .return_const(());
 
         // set up client mock and assert we don't reach it
         let mut mock_client = crate::clients::client::MockClient::new();
@@ -206,7 +272,10 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -215,15 +284,20 @@ mod tests {
         };
 
         // when
-        let result = get(State(app_state), axum::Json(request_body))
+        let result = get(State(app_state), HeaderMap::new(), axum::Json(request_body))
             .await
             .unwrap();
+        let headers = result.headers().clone();
         let response_bytes = body::to_bytes(result.into_body(), usize::MAX)
             .await
             .unwrap();
 
         // then
         assert_eq!(response, response_bytes);
+        assert_eq!(headers.get("x-semcache-entry-id").unwrap(), "3");
+        assert_eq!(headers.get("x-semcache-similarity").unwrap(), "0.9250");
+        assert_eq!(headers.get("x-semcache-threshold").unwrap(), "0.9000");
+        assert!(headers.contains_key("x-semcache-age"));
     }
 
     #[tokio::test]
@@ -240,11 +314,11 @@ mod tests {
         });
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
         mock_cache
             .expect_get_if_present()
-            .with(eq(embedding))
-            .returning(move |_| Ok(None));
+            .with(eq(PartitionKey::default()), eq(embedding), eq(None))
+            .returning(move |_, _, _| Ok(None));
 
         // set up client mock and assert we don't reach it
         let mut mock_client = crate::clients::client::MockClient::new();
@@ -253,7 +327,10 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -262,7 +339,7 @@ mod tests {
         };
 
         // when
-        let result = get(State(app_state), axum::Json(request_body))
+        let result = get(State(app_state), HeaderMap::new(), axum::Json(request_body))
             .await
             .unwrap();
 
@@ -285,8 +362,8 @@ mod tests {
             .returning(move |_| Ok(embedding.clone()));
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
-        mock_cache.expect_try_update().returning(|_, _| {
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache.expect_try_update().returning(|_, _, _, _| {
             Err(CacheError::FaissRetrievalError(
                 faiss::error::Error::IndexDescription,
             ))
@@ -299,17 +376,21 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
         let request_body = PutRequest {
             key: String::from(prompt),
             data: String::from(body),
+            ttl_seconds: None,
         };
 
         // when
-        let result = put(State(app_state), axum::Json(request_body)).await;
+        let result = put(State(app_state), HeaderMap::new(), axum::Json(request_body)).await;
 
         // then
         match result {
@@ -344,17 +425,21 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
         let request_body = PutRequest {
             key: String::from(prompt),
             data: String::from(body),
+            ttl_seconds: None,
         };
 
         // when
-        let result = put(State(app_state), axum::Json(request_body)).await;
+        let result = put(State(app_state), HeaderMap::new(), axum::Json(request_body)).await;
 
         // then
         match result {
@@ -378,8 +463,10 @@ mod tests {
             .returning(move |_| Ok(embedding.clone()));
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
-        mock_cache.expect_try_update().returning(|_, _| Ok(true));
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache
+            .expect_try_update()
+            .returning(|_, _, _, _| Ok(true));
 
         // set up client mock and assert we don't reach it
         let mut mock_client = crate::clients::client::MockClient::new();
@@ -388,17 +475,21 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
         let request_body = PutRequest {
             key: String::from(prompt),
             data: String::from(body),
+            ttl_seconds: None,
         };
 
         // when
-        let result = put(State(app_state), axum::Json(request_body))
+        let result = put(State(app_state), HeaderMap::new(), axum::Json(request_body))
             .await
             .unwrap();
 
@@ -421,17 +512,27 @@ mod tests {
         });
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
         mock_cache
             .expect_try_update()
             .times(1)
-            .with(eq(embedding.clone()), eq(data.clone().into_bytes()))
-            .returning(|_, _| Ok(false));
+            .with(
+                eq(PartitionKey::default()),
+                eq(embedding.clone()),
+                has_body(data.clone().into_bytes()),
+                eq(Some(Duration::from_secs(60))),
+            )
+            .returning(|_, _, _, _| Ok(false));
         mock_cache
             .expect_insert()
             .times(1)
-            .with(eq(embedding.clone()), eq(data.clone().into_bytes()))
-            .returning(|_, _| Ok(()));
+            .with(
+                eq(PartitionKey::default()),
+                eq(embedding.clone()),
+                has_body(data.clone().into_bytes()),
+                eq(Some(Duration::from_secs(60))),
+            )
+            .returning(|_, _, _, _| Ok(()));
 
         // set up client mock and assert we don't reach it
         let mut mock_client = crate::clients::client::MockClient::new();
@@ -440,17 +541,21 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
         let request_body = PutRequest {
             key: String::from(prompt),
             data,
+            ttl_seconds: Some(60),
         };
 
         // when
-        let result = put(State(app_state), axum::Json(request_body))
+        let result = put(State(app_state), HeaderMap::new(), axum::Json(request_body))
             .await
             .unwrap();
 
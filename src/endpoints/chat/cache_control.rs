@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use axum::http::{HeaderMap, header::CACHE_CONTROL};
+
+use super::error::CompletionError;
+use crate::utils::header_utils::SIMILARITY_THRESHOLD_HEADER;
+
+// How a single request wants the cache to be used, read from its `Cache-Control` and
+// `x-semcache-threshold` headers
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheControl {
+    // skip the lookup, the upstream response replaces the cached one
+    pub no_cache: bool,
+    // don't cache the upstream response
+    pub no_store: bool,
+    // never call upstream, misses are answered with 504
+    pub only_if_cached: bool,
+    // cached responses older than this are treated as misses
+    pub max_age: Option<Duration>,
+    // overrides the similarity threshold of the namespace for the lookup
+    pub similarity_threshold: Option<f32>,
+}
+
+impl CacheControl {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, CompletionError> {
+        let mut control = CacheControl::default();
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = value.to_str().map_err(|_| {
+                CompletionError::InvalidRequest(format!(
+                    "Header '{CACHE_CONTROL}' is not valid ascii"
+                ))
+            })?;
+            for directive in value.split(',').map(str::trim) {
+                let (name, argument) = match directive.split_once('=') {
+                    Some((name, argument)) => {
+                        (name.trim(), Some(argument.trim().trim_matches('"')))
+                    }
+                    None => (directive, None),
+                };
+                match name.to_ascii_lowercase().as_str() {
+                    "no-cache" => control.no_cache = true,
+                    "no-store" => control.no_store = true,
+                    "only-if-cached" => control.only_if_cached = true,
+                    "max-age" => {
+                        let seconds = argument
+                            .and_then(|seconds| seconds.parse::<u64>().ok())
+                            .ok_or_else(|| {
+                                CompletionError::InvalidRequest(format!(
+                                    "Expected a number of seconds in directive 'max-age' of header '{CACHE_CONTROL}', but found: {directive:?}"
+                                ))
+                            })?;
+                        control.max_age = Some(Duration::from_secs(seconds));
+                    }
+                    // other directives, such as max-stale, don't apply to a semantic cache
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(threshold_header) = headers.get(&SIMILARITY_THRESHOLD_HEADER) {
+            let threshold = threshold_header
+                .to_str()
+                .ok()
+                .and_then(|threshold| threshold.trim().parse::<f32>().ok())
+                .filter(|threshold| (0.0..=1.0).contains(threshold))
+                .ok_or_else(|| {
+                    CompletionError::InvalidRequest(format!(
+                        "Expected a similarity between 0 and 1 in header '{}', but found: {:?}",
+                        SIMILARITY_THRESHOLD_HEADER, threshold_header
+                    ))
+                })?;
+            control.similarity_threshold = Some(threshold);
+        }
+        Ok(control)
+    }
+
+    // whether a cached response of this age may be served
+    pub fn accepts_age(&self, age: Duration) -> bool {
+        self.max_age.is_none_or(|max_age| age <= max_age)
+    }
+
+    // only requests that use the cache as configured share responses with similar requests in flight
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::{HeaderMap, header::CACHE_CONTROL};
+
+    use super::CacheControl;
+    use crate::endpoints::chat::error::CompletionError;
+    use crate::utils::header_utils::SIMILARITY_THRESHOLD_HEADER;
+
+    fn headers(cache_control: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, cache_control.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn from_headers_should_default_without_headers() {
+        // when
+        let control = CacheControl::from_headers(&HeaderMap::new()).unwrap();
+
+        // then
+        assert!(control.is_default());
+        assert!(control.accepts_age(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn from_headers_should_parse_directives() {
+        // when
+        let control = CacheControl::from_headers(&headers(
+            "No-Cache, no-store,only-if-cached, max-age=\"60\"",
+        ))
+        .unwrap();
+
+        // then
+        assert_eq!(
+            control,
+            CacheControl {
+                no_cache: true,
+                no_store: true,
+                only_if_cached: true,
+                max_age: Some(Duration::from_secs(60)),
+                similarity_threshold: None,
+            }
+        );
+        assert!(control.accepts_age(Duration::from_secs(60)));
+        assert!(!control.accepts_age(Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn from_headers_should_ignore_unknown_directives() {
+        // when
+        let control = CacheControl::from_headers(&headers("max-stale=30, no-transform")).unwrap();
+
+        // then
+        assert!(control.is_default());
+    }
+
+    #[test]
+    fn from_headers_should_reject_invalid_max_age() {
+        // when
+        let result = CacheControl::from_headers(&headers("max-age=soon"));
+
+        // then
+        match result {
+            Err(CompletionError::InvalidRequest(_)) => {}
+            _ => panic!("Expected CompletionError::InvalidRequest"),
+        }
+    }
+
+    #[test]
+    fn from_headers_should_parse_threshold() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert(&SIMILARITY_THRESHOLD_HEADER, "0.97".parse().unwrap());
+
+        // when
+        let control = CacheControl::from_headers(&headers).unwrap();
+
+        // then
+        assert_eq!(control.similarity_threshold, Some(0.97));
+        assert!(!control.is_default());
+
+        headers.insert(&SIMILARITY_THRESHOLD_HEADER, "1.5".parse().unwrap());
+        match CacheControl::from_headers(&headers) {
+            Err(CompletionError::InvalidRequest(_)) => {}
+            _ => panic!("Expected CompletionError::InvalidRequest"),
+        }
+    }
+}
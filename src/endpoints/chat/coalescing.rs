@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::cache::partition::PartitionKey;
+use crate::cache::semantic_store::flat_ip_faiss_store::into_cosine_similarity;
+use crate::metrics::metrics::COALESCED_REQUESTS;
+use crate::rerank::guard::HitGuard;
+use crate::utils::linear_algebra::{dot, normalize};
+
+const MUTEX_PANIC: &str = "Mutex poisoned, in flight requests might be corrupted, panicking";
+
+#[derive(Debug, Deserialize)]
+pub struct CoalescingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // how long a request waits for the response of a similar one before calling upstream itself
+    #[serde(default = "default_wait_timeout_ms")]
+    pub wait_timeout_ms: u64,
+}
+
+fn default_wait_timeout_ms() -> u64 {
+    30_000
+}
+
+// every miss calls upstream itself unless coalescing is enabled
+impl Default for CoalescingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            wait_timeout_ms: default_wait_timeout_ms(),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum FlightResult {
+    Pending,
+    // the leader inserted its response into the cache
+    Completed,
+    // the leader didn't get a cacheable response, or gave up
+    Failed,
+}
+
+struct InFlight {
+    id: u64,
+    // normalized, so similarity is a dot product
+    embedding: Vec<f32>,
+    prompt: String,
+    receiver: watch::Receiver<FlightResult>,
+}
+
+// Makes concurrent misses for similar prompts share a single upstream request. The first miss
+// becomes the leader of a flight and calls upstream, misses arriving while it's in flight whose
+// prompts are within the similarity threshold of the leader's wait for it to cache its response,
+// then look it up like any other hit.
+pub struct RequestCoalescer {
+    in_flight: Mutex<HashMap<PartitionKey, Vec<InFlight>>>,
+    // as cosine similarity
+    similarity_threshold: f32,
+    wait_timeout: Duration,
+    // None if any similar flight is followed, otherwise only those whose prompt the guard matches
+    guard: Option<Arc<HitGuard>>,
+    next_id: AtomicU64,
+}
+
+pub enum Flight {
+    Leader(FlightLeader),
+    Follower(FlightFollower),
+}
+
+impl RequestCoalescer {
+    pub fn new(
+        similarity_threshold: f32,
+        wait_timeout: Duration,
+        guard: Option<Arc<HitGuard>>,
+    ) -> Self {
+        RequestCoalescer {
+            in_flight: Mutex::new(HashMap::new()),
+            similarity_threshold: into_cosine_similarity(similarity_threshold),
+            wait_timeout,
+            guard,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    // Follows the most similar flight in the partition within the threshold, or starts a new one.
+    // A flight the guard would reject the leader's response of isn't worth waiting for.
+    pub fn join(
+        self: &Arc<Self>,
+        partition: PartitionKey,
+        embedding: &[f32],
+        prompt: &str,
+    ) -> Flight {
+        let embedding = normalize(embedding);
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|err| {
+            error!(error = ?err);
+            panic!("{}", MUTEX_PANIC)
+        });
+        let flights = in_flight.entry(partition).or_default();
+
+        let nearest = flights
+            .iter()
+            .map(|flight| (dot(&flight.embedding, &embedding), flight))
+            .filter(|(similarity, _)| *similarity >= self.similarity_threshold)
+            .filter(|(_, flight)| {
+                self.guard
+                    .as_ref()
+                    .is_none_or(|guard| guard.matches(prompt, &flight.prompt))
+            })
+            .max_by(|(a, _), (b, _)| a.total_cmp(b));
+        if let Some((_, flight)) = nearest {
+            return Flight::Follower(FlightFollower {
+                receiver: flight.receiver.clone(),
+                wait_timeout: self.wait_timeout,
+            });
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = watch::channel(FlightResult::Pending);
+        flights.push(InFlight {
+            id,
+            embedding,
+            prompt: String::from(prompt),
+            receiver,
+        });
+        COALESCED_REQUESTS.with_label_values(&["leader"]).inc();
+        Flight::Leader(FlightLeader {
+            coalescer: self.clone(),
+            partition,
+            id,
+            sender,
+        })
+    }
+
+    fn land(&self, partition: PartitionKey, id: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|err| {
+            error!(error = ?err);
+            panic!("{}", MUTEX_PANIC)
+        });
+        if let Some(flights) = in_flight.get_mut(&partition) {
+            flights.retain(|flight| flight.id != id);
+            if flights.is_empty() {
+                in_flight.remove(&partition);
+            }
+        }
+    }
+}
+
+// Held by the request calling upstream. Followers are told the request failed if it's dropped
+// without completing, e.g. when the client disconnects.
+pub struct FlightLeader {
+    coalescer: Arc<RequestCoalescer>,
+    partition: PartitionKey,
+    id: u64,
+    sender: watch::Sender<FlightResult>,
+}
+
+impl FlightLeader {
+    // Tells the followers the response is in the cache
+    pub fn complete(self) {
+        self.sender.send_replace(FlightResult::Completed);
+    }
+}
+
+impl Drop for FlightLeader {
+    fn drop(&mut self) {
+        self.coalescer.land(self.partition, self.id);
+        self.sender.send_if_modified(|result| {
+            if matches!(result, FlightResult::Pending) {
+                *result = FlightResult::Failed;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+pub struct FlightFollower {
+    receiver: watch::Receiver<FlightResult>,
+    wait_timeout: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FollowerOutcome {
+    // the leader's response is in the cache, for the follower to look up
+    Completed,
+    // the leader failed, or took too long
+    Fallback,
+}
+
+impl FlightFollower {
+    // Counts the fallbacks only, whether a completed flight gets served is up to the lookup
+    pub async fn wait(mut self) -> FollowerOutcome {
+        let result = tokio::time::timeout(
+            self.wait_timeout,
+            self.receiver
+                .wait_for(|result| !matches!(result, FlightResult::Pending)),
+        )
+        .await;
+        let label = match result {
+            Err(_) => "timed_out",
+            Ok(Ok(result)) if matches!(*result, FlightResult::Completed) => {
+                return FollowerOutcome::Completed;
+            }
+            // the leader failed, or is gone without sending anything
+            Ok(_) => "failed",
+        };
+        COALESCED_REQUESTS.with_label_values(&[label]).inc();
+        FollowerOutcome::Fallback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Flight, FollowerOutcome, RequestCoalescer};
+    use crate::cache::partition::PartitionKey;
+    use crate::rerank::guard::{GuardConfig, HitGuard};
+
+    const PARTITION: PartitionKey = PartitionKey(0);
+    const PROMPT: &str = "What is the capital of France?";
+
+    fn coalescer(wait_timeout: Duration) -> Arc<RequestCoalescer> {
+        Arc::new(RequestCoalescer::new(0.9, wait_timeout, None))
+    }
+
+    #[tokio::test]
+    async fn similar_request_should_wait_for_leader_to_complete() {
+        // given
+        let coalescer = coalescer(Duration::from_secs(5));
+        let Flight::Leader(leader) = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT) else {
+            panic!("Expected the first request to lead")
+        };
+        let Flight::Follower(follower) = coalescer.join(PARTITION, &[0.0, 2.0, 0.1], PROMPT) else {
+            panic!("Expected a similar request to follow")
+        };
+
+        // when
+        leader.complete();
+
+        // then
+        assert_eq!(follower.wait().await, FollowerOutcome::Completed);
+    }
+
+    #[tokio::test]
+    async fn request_rejected_by_guard_should_lead() {
+        // given
+        let coalescer = Arc::new(RequestCoalescer::new(
+            0.9,
+            Duration::from_secs(5),
+            Some(Arc::new(HitGuard::new(GuardConfig::default()))),
+        ));
+        let _leader = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], "convert 50 miles to km");
+
+        // then
+        assert!(matches!(
+            coalescer.join(PARTITION, &[0.0, 1.0, 0.0], "convert 5 miles to km"),
+            Flight::Leader(_)
+        ));
+        assert!(matches!(
+            coalescer.join(PARTITION, &[0.0, 1.0, 0.0], "convert 50 miles to km"),
+            Flight::Follower(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dissimilar_or_other_partition_requests_should_lead() {
+        let coalescer = coalescer(Duration::from_secs(5));
+        let _leader = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT);
+
+        assert!(matches!(
+            coalescer.join(PARTITION, &[1.0, 0.0, 0.0], PROMPT),
+            Flight::Leader(_)
+        ));
+        assert!(matches!(
+            coalescer.join(PartitionKey(1), &[0.0, 1.0, 0.0], PROMPT),
+            Flight::Leader(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn dropped_leader_should_make_followers_fall_back() {
+        // given
+        let coalescer = coalescer(Duration::from_secs(5));
+        let leader = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT);
+        let Flight::Follower(follower) = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT) else {
+            panic!("Expected a similar request to follow")
+        };
+
+        // when
+        drop(leader);
+
+        // then
+        assert_eq!(follower.wait().await, FollowerOutcome::Fallback);
+        // the flight has landed, so the next request leads again
+        assert!(matches!(
+            coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT),
+            Flight::Leader(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn follower_should_fall_back_after_timeout() {
+        // given
+        let coalescer = coalescer(Duration::from_millis(10));
+        let _leader = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT);
+        let Flight::Follower(follower) = coalescer.join(PARTITION, &[0.0, 1.0, 0.0], PROMPT) else {
+            panic!("Expected a similar request to follow")
+        };
+
+        // then
+        assert_eq!(follower.wait().await, FollowerOutcome::Fallback);
+    }
+}
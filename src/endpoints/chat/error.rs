@@ -5,7 +5,10 @@ use reqwest::StatusCode;
 use thiserror::Error;
 use tracing::warn;
 
-use crate::{cache::error::CacheError, embedding::error::EmbeddingError, providers::ProviderError};
+use crate::{
+    cache::error::CacheError, embedding::error::EmbeddingError, namespaces::error::NamespaceError,
+    providers::ProviderError, rerank::error::RerankError,
+};
 
 // Error type
 #[derive(Debug, Error)]
@@ -28,8 +31,17 @@ pub enum CompletionError {
     #[error("Error generating embedding: {0}")]
     InternalEmbeddingError(#[from] EmbeddingError),
 
+    #[error("Error verifying cache hit: {0}")]
+    InternalRerankError(#[from] RerankError),
+
     #[error("Provider error: {0}")]
     InternalProviderError(#[from] ProviderError),
+
+    #[error("Namespace error: {0}")]
+    Namespace(#[from] NamespaceError),
+
+    #[error("No cached response found and the request is only-if-cached")]
+    NotCached,
 }
 
 impl IntoResponse for CompletionError {
@@ -69,6 +81,10 @@ impl IntoResponse for CompletionError {
                 warn!("Internal embedding error: {}", internal_error);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong!").into_response()
             }
+            Self::InternalRerankError(internal_error) => {
+                warn!("Internal reranking error: {}", internal_error);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong!").into_response()
+            }
             Self::InvalidJsonPath(message) => {
                 warn!("Failed to parse input, {}", message);
                 (StatusCode::BAD_REQUEST, message.to_string()).into_response()
@@ -81,6 +97,11 @@ impl IntoResponse for CompletionError {
                 )
                     .into_response()
             }
+            Self::Namespace(err) => err.into_response(),
+            // what HTTP caches answer only-if-cached requests with on a miss
+            Self::NotCached => {
+                (StatusCode::GATEWAY_TIMEOUT, "No cached response found").into_response()
+            }
         }
     }
 }
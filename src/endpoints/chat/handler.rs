@@ -2,18 +2,33 @@ use axum::response::{IntoResponse, Response};
 
 use axum::{
     extract::{Json, State},
-    http::{StatusCode, header::HeaderMap},
+    http::{HeaderValue, StatusCode, header::HeaderMap},
 };
 use serde_json::Value;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::debug;
 
+use super::cache_control::CacheControl;
+use super::coalescing::{Flight, FollowerOutcome};
 use super::error::CompletionError;
+use super::partition::partition_fields;
+use super::streaming::{CacheTarget, forward_stream, replay_cached};
 use crate::app_state::AppState;
-use crate::metrics::metrics::{CACHE_HIT, CACHE_MISS, CacheStatus};
+use crate::cache::cache::CacheHit;
+use crate::cache::cached_response::CachedResponse;
+use crate::cache::error::CacheError;
+use crate::cache::partition::PartitionKey;
+use crate::metrics::metrics::{
+    CACHE_HIT, CACHE_MISS, COALESCED_REQUESTS, CacheStatus, NAMESPACE_CACHE_HIT,
+    NAMESPACE_CACHE_MISS,
+};
+use crate::namespaces::registry::Namespace;
 use crate::providers::ProviderType;
-use crate::utils::{
-    header_utils::PROXY_PROMPT_LOCATION_HEADER, json_extract::extract_prompt_from_path,
+use crate::providers::context::{NonTextContent, extract_prompt_context};
+use crate::streaming::{StreamAssembler, is_stream_request};
+use crate::utils::header_utils::{
+    CACHE_TTL_HEADER, PARTITION_HEADER, cache_hit_headers, cached_entry_headers,
 };
 
 pub async fn completions(
@@ -22,36 +37,132 @@ pub async fn completions(
     Json(request_body): Json<Value>,
     provider: ProviderType,
 ) -> Result<Response, CompletionError> {
-    let prompt = extract_prompt_from_path(
-        &request_body,
-        provider.prompt_json_path(headers.get(&PROXY_PROMPT_LOCATION_HEADER))?,
-    )?;
-    let embedding = state.embedding_service.embed(&prompt)?;
-
-    if let Some(saved_response) = state.cache.get_if_present(&embedding)? {
-        // Return cached response with 200 OK and minimal headers
-        let mut response_headers = HeaderMap::new();
-        response_headers.insert("X-Cache-Status", "hit".parse().unwrap());
-        response_headers.insert("content-type", "application/json".parse().unwrap());
-        let mut response = (StatusCode::OK, response_headers, saved_response).into_response();
+    let streaming = is_stream_request(&request_body);
+    if streaming && !StreamAssembler::supports(&provider) {
+        debug!("Stream format of provider is unknown - bypassing the cache");
+        return forward_uncached(state, headers, provider, request_body, streaming).await;
+    }
+
+    let ttl = ttl_from_headers(&headers)?;
+    let control = CacheControl::from_headers(&headers)?;
+    let context =
+        extract_prompt_context(&provider, &headers, &request_body, &state.context_config)?;
+    if !context.attachments.is_empty() && state.non_text_content == NonTextContent::Bypass {
+        debug!("Prompt contains non-text content - bypassing the cache");
+        return forward_uncached(state, headers, provider, request_body, streaming).await;
+    }
+    let namespace = state.namespaces.resolve(&headers)?;
+    let embedding = state.embedding_service.embed(&context.prompt).await?;
+    let fields = partition_fields(&provider, &request_body, &state.partition_fields, &context);
+    let partition = PartitionKey::from_fields(&fields);
+
+    // a stale hit (w.r.t. max-age) gets replaced by the upstream response, like with no-cache
+    let mut refresh = control.no_cache;
+    if !control.no_cache
+        && let Some(hit) = namespace
+            .find_hit::<CompletionError>(
+                &context.prompt,
+                partition,
+                &embedding,
+                control.similarity_threshold,
+            )
+            .await?
+    {
+        if control.accepts_age(hit.response.age()) {
+            if let Some(response) = served_hit(&provider, &namespace, hit, streaming) {
+                debug!("Cache hit - returning cached response");
+                return Ok(with_partition(response, partition));
+            }
+        } else {
+            debug!("Cached response is older than the request accepts");
+            refresh = true;
+        }
+    }
 
-        debug!("Cache hit - returning cached response");
-        CACHE_HIT.inc();
-        response.extensions_mut().insert(CacheStatus::Hit);
+    if control.only_if_cached {
+        debug!("Cache miss - not calling upstream for an only-if-cached request");
+        record_miss(&namespace);
+        return Err(CompletionError::NotCached);
+    }
 
-        return Ok(response);
+    // similar misses in flight at the same time share one upstream request
+    let leader = match namespace
+        .coalescer
+        .as_ref()
+        .filter(|_| control.is_default())
+        .map(|coalescer| coalescer.join(partition, &embedding, &context.prompt))
+    {
+        Some(Flight::Leader(leader)) => Some(leader),
+        Some(Flight::Follower(follower)) => {
+            // the leader's response is looked up like any hit, so the guard and verifier check it
+            if follower.wait().await == FollowerOutcome::Completed {
+                let hit = namespace
+                    .find_hit::<CompletionError>(
+                        &context.prompt,
+                        partition,
+                        &embedding,
+                        control.similarity_threshold,
+                    )
+                    .await?;
+                if let Some(response) =
+                    hit.and_then(|hit| served_hit(&provider, &namespace, hit, streaming))
+                {
+                    debug!("Coalesced miss - returning the response of a similar request");
+                    COALESCED_REQUESTS.with_label_values(&["served"]).inc();
+                    return Ok(with_partition(response, partition));
+                }
+                COALESCED_REQUESTS.with_label_values(&["rejected"]).inc();
+            }
+            None
+        }
+        None => None,
     };
 
+    let model = request_model(&request_body);
+    if streaming {
+        let target = (!control.no_store).then(|| CacheTarget {
+            namespace: namespace.clone(),
+            model,
+            prompt: context.prompt,
+            partition_fields: fields,
+            partition,
+            embedding,
+            ttl,
+            refresh,
+            leader,
+        });
+        let mut response = forward_stream(state, headers, provider, request_body, target).await?;
+
+        debug!("Cache miss - streaming from the upstream LLM provider");
+        record_miss(&namespace);
+        response.extensions_mut().insert(CacheStatus::Miss);
+
+        return Ok(with_partition(response, partition));
+    }
+
+    let started_at = Instant::now();
     let upstream_response = state
         .http_client
         .post_http_request(headers, provider, request_body)
         .await?;
+    let upstream_latency = started_at.elapsed();
 
     // only store the response if the status code of the response is 2XX
-    if upstream_response.status_code.is_success() {
-        state
-            .cache
-            .insert(embedding, upstream_response.response_body.clone())?;
+    if upstream_response.status_code.is_success() && !control.no_store {
+        let cached = CachedResponse::from_upstream(
+            provider,
+            model,
+            upstream_response.status_code,
+            &upstream_response.header_map,
+            upstream_response.response_body.clone(),
+        )
+        .with_prompt(context.prompt)
+        .with_partition_fields(&fields)
+        .with_upstream_latency(upstream_latency);
+        store_response(&namespace, partition, embedding, cached, ttl, refresh)?;
+        if let Some(leader) = leader {
+            leader.complete();
+        }
     }
 
     let mut response = (
@@ -62,27 +173,191 @@ pub async fn completions(
         .into_response();
 
     debug!("Cache miss - calling the upstream LLM provider");
-    CACHE_MISS.inc();
+    record_miss(&namespace);
     response.extensions_mut().insert(CacheStatus::Miss);
 
+    Ok(with_partition(response, partition))
+}
+
+// Serves a cache hit with the headers describing it, None if it can't be served in the format the
+// request asked for
+fn served_hit(
+    provider: &ProviderType,
+    namespace: &Namespace,
+    hit: CacheHit<CachedResponse>,
+    streaming: bool,
+) -> Option<Response> {
+    let hit_headers = cache_hit_headers(&hit);
+    let mut response = cached_response(provider, namespace, hit.response, streaming)?;
+    namespace.cache.record_hit(hit.entry_id);
+    response.headers_mut().extend(hit_headers);
+    Some(response)
+}
+
+// Builds a cache hit response out of a saved response, None if it can't be served in the format
+// the request asked for. The upstream status and headers are replayed, with semcache's own added.
+fn cached_response(
+    provider: &ProviderType,
+    namespace: &Namespace,
+    saved_response: CachedResponse,
+    streaming: bool,
+) -> Option<Response> {
+    let mut response = if streaming {
+        replay_cached(provider, &saved_response.body)?
+    } else {
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert("X-Cache-Status", "hit".parse().unwrap());
+        response_headers.insert("content-type", "application/json".parse().unwrap());
+        (
+            StatusCode::OK,
+            response_headers,
+            saved_response.body.clone(),
+        )
+            .into_response()
+    };
+    *response.status_mut() = saved_response.status;
+    let headers = response.headers_mut();
+    for name in saved_response.headers.keys() {
+        if !headers.contains_key(name) {
+            for value in saved_response.headers.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+    }
+    headers.extend(cached_entry_headers(&saved_response));
+    CACHE_HIT.inc();
+    NAMESPACE_CACHE_HIT
+        .with_label_values(&[&namespace.name])
+        .inc();
+    response.extensions_mut().insert(CacheStatus::Hit);
+    Some(response)
+}
+
+// Caches an upstream response, replacing the entry of the same prompt when refreshing it
+pub(super) fn store_response(
+    namespace: &Namespace,
+    partition: PartitionKey,
+    embedding: Vec<f32>,
+    response: CachedResponse,
+    ttl: Option<Duration>,
+    refresh: bool,
+) -> Result<(), CacheError> {
+    if refresh
+        && namespace
+            .cache
+            .try_update(partition, &embedding, response.clone(), ttl)?
+    {
+        return Ok(());
+    }
+    namespace.cache.insert(partition, embedding, response, ttl)
+}
+
+fn record_miss(namespace: &Namespace) {
+    CACHE_MISS.inc();
+    NAMESPACE_CACHE_MISS
+        .with_label_values(&[&namespace.name])
+        .inc();
+}
+
+// Tells the client which partition the response is cached under, so it can be invalidated
+fn with_partition(mut response: Response, partition: PartitionKey) -> Response {
+    if let Ok(value) = HeaderValue::from_str(&partition.to_string()) {
+        response.headers_mut().insert(&PARTITION_HEADER, value);
+    }
+    response
+}
+
+fn request_model(request_body: &Value) -> Option<String> {
+    request_body
+        .get("model")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+// Forwards a request that can't be served from or stored in the cache
+async fn forward_uncached(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    provider: ProviderType,
+    request_body: Value,
+    streaming: bool,
+) -> Result<Response, CompletionError> {
+    let mut response = if streaming {
+        forward_stream(state, headers, provider, request_body, None).await?
+    } else {
+        let upstream_response = state
+            .http_client
+            .post_http_request(headers, provider, request_body)
+            .await?;
+        (
+            upstream_response.status_code,
+            upstream_response.header_map,
+            upstream_response.response_body,
+        )
+            .into_response()
+    };
+    response.extensions_mut().insert(CacheStatus::NotApplicable);
     Ok(response)
 }
 
+// Reads the time to live (in seconds) a response should be cached for, if set on the request
+fn ttl_from_headers(headers: &HeaderMap) -> Result<Option<Duration>, CompletionError> {
+    let Some(ttl_header) = headers.get(&CACHE_TTL_HEADER) else {
+        return Ok(None);
+    };
+    ttl_header
+        .to_str()
+        .ok()
+        .and_then(|ttl| ttl.trim().parse::<u64>().ok())
+        .map(|seconds| Some(Duration::from_secs(seconds)))
+        .ok_or_else(|| {
+            CompletionError::InvalidRequest(format!(
+                "Expected a number of seconds in header '{}', but found: {:?}",
+                CACHE_TTL_HEADER, ttl_header
+            ))
+        })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::clients::client::UpstreamResponse;
+    use crate::cache::partition::PartitionKey;
+    use crate::clients::client::{UpstreamResponse, UpstreamStreamResponse};
+    use crate::endpoints::chat::coalescing::{Flight, RequestCoalescer};
+    use crate::endpoints::chat::partition::partition_fields;
+    use crate::metrics::metrics::CacheStatus;
     use crate::providers::ProviderType;
+    use crate::providers::context::{ContextConfig, NonTextContent, PromptContext};
+    use crate::utils::header_utils::{
+        CACHE_TTL_HEADER, ENTRY_ID_HEADER, SIMILARITY_THRESHOLD_HEADER,
+    };
     use crate::{
-        app_state::AppState, cache::cache::MockCache, cache::error::CacheError,
-        clients::client::MockClient, embedding::service::MockEmbeddingService,
-        endpoints::chat::error::CompletionError, endpoints::chat::handler::completions,
+        app_state::AppState,
+        cache::cache::{CacheHit, MockCache},
+        cache::cached_response::CachedResponse,
+        cache::error::CacheError,
+        clients::client::MockClient,
+        embedding::service::MockEmbeddingService,
+        endpoints::chat::error::CompletionError,
+        endpoints::chat::handler::{completions, ttl_from_headers},
+        namespaces::registry::Namespaces,
     };
     use axum::extract::State;
-    use axum::http::{HeaderMap, StatusCode};
+    use axum::http::{HeaderMap, StatusCode, header::CACHE_CONTROL};
     use axum::response::{IntoResponse, Response};
-    use mockall::predicate::eq;
-    use serde_json::json;
+    use futures::StreamExt;
+    use mockall::predicate::{always, eq, function};
+    use serde_json::{Value, json};
     use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    fn hit(response: CachedResponse) -> CacheHit<CachedResponse> {
+        CacheHit {
+            response,
+            entry_id: 0,
+            similarity: 1.0,
+            similarity_threshold: 0.9,
+        }
+    }
 
     #[tokio::test]
     async fn should_return_error_on_cache_failure() {
@@ -98,8 +373,8 @@ mod tests {
             .returning(move |_| Ok(embedding.clone()));
 
         // set up cache mock
-        let mut mock_cache: MockCache<Vec<u8>> = MockCache::new();
-        mock_cache.expect_get_if_present().returning(|_| {
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache.expect_get_if_present().returning(|_, _, _| {
             Err(CacheError::FaissRetrievalError(
                 faiss::error::Error::IndexDescription,
             ))
@@ -112,7 +387,10 @@ mod tests {
         // put mocked objects into the appstate
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -167,7 +445,10 @@ mod tests {
 
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -219,14 +500,23 @@ mod tests {
         let mut mock_cache = MockCache::new();
         mock_cache.expect_get_if_present().times(2).returning({
             let completion_clone = completion_json.clone();
-            move |_| Ok(Some(completion_clone.clone().into_bytes()))
+            move |_, _, _| {
+                Ok(Some(hit(CachedResponse::from_body(
+                    completion_clone.clone().into_bytes(),
+                ))))
+            }
         });
+        mock_cache
+            .expect_record_hit()
+            .with(eq(0))
+            .times(2)
+            .return_const(());
 
         // verify put is not called
         mock_cache
             .expect_insert()
             .times(0)
-            .returning(|_, _| unreachable!());
+            .returning(|_, _, _, _| unreachable!());
 
         // verify client is not called
         let mut mock_client = MockClient::new();
@@ -237,7 +527,10 @@ mod tests {
 
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -320,17 +613,26 @@ mod tests {
         mock_cache
             .expect_get_if_present()
             .times(1)
-            .returning(|_| Ok(None));
+            .returning(|_, _, _| Ok(None));
 
         // verify put is called once
         mock_cache
             .expect_insert()
             .times(1)
             .with(
+                always(),
                 eq(embedding.clone()),
-                eq(completion_json.clone().into_bytes()),
+                function({
+                    let completion_json = completion_json.clone();
+                    move |cached: &CachedResponse| {
+                        cached.body == completion_json.as_bytes()
+                            && cached.provider == Some(ProviderType::OpenAI)
+                            && cached.model.as_deref() == Some("gpt-4")
+                    }
+                }),
+                eq(None),
             )
-            .returning(|_, _| Ok(()));
+            .returning(|_, _, _, _| Ok(()));
 
         // upstream response simulation
         let mut mock_client = MockClient::new();
@@ -348,7 +650,10 @@ mod tests {
 
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -399,7 +704,7 @@ mod tests {
         mock_cache
             .expect_get_if_present()
             .times(1)
-            .returning(|_| Ok(None));
+            .returning(|_, _, _| Ok(None));
 
         // verify put is called once
         mock_cache.expect_insert().times(0);
@@ -419,7 +724,10 @@ mod tests {
 
         let app_state = Arc::new(AppState {
             embedding_service: Box::new(mock_embed),
-            cache: Box::new(mock_cache),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
             http_client: Box::new(mock_client),
         });
 
@@ -451,6 +759,537 @@ mod tests {
         assert_eq!(response, response_body.to_string());
     }
 
+    #[tokio::test]
+    async fn should_stream_from_upstream_on_cache_miss_and_cache_assembled_response() {
+        // given
+        let embedding = vec![0.1, 0.2, 0.3];
+        let chunks = [
+            r#"data: {"id":"chatcmpl-1","created":1,"model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":"Semcache"}}]}"#,
+            "\n\n",
+            r#"data: {"id":"chatcmpl-1","created":1,"model":"gpt-4o","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            "\n\ndata: [DONE]\n\n",
+        ];
+        let streamed_body = chunks.concat();
+
+        // embed returns vector
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed.expect_embed().times(1).returning({
+            let embedding_clone = embedding.clone();
+            move |_| Ok(embedding_clone.clone())
+        });
+
+        // cache miss, insert is signalled over a channel as it happens in a background task
+        let (inserted_sender, mut inserted_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut mock_cache = MockCache::new();
+        mock_cache
+            .expect_get_if_present()
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+        mock_cache
+            .expect_insert()
+            .times(1)
+            .with(always(), eq(embedding.clone()), always(), always())
+            .returning(move |_, _, body, _| {
+                inserted_sender.send(body).unwrap();
+                Ok(())
+            });
+
+        // upstream streams the body in several chunks
+        let mut mock_client = MockClient::new();
+        mock_client.expect_post_http_request().times(0);
+        mock_client
+            .expect_post_http_request_stream()
+            .times(1)
+            .returning(move |_, _, _| {
+                let body_stream =
+                    futures::stream::iter(chunks.map(|chunk| Ok(axum::body::Bytes::from(chunk))))
+                        .boxed();
+                Ok(UpstreamStreamResponse {
+                    status_code: StatusCode::OK,
+                    header_map: HeaderMap::new(),
+                    body_stream,
+                })
+            });
+
+        let app_state = Arc::new(AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
+            http_client: Box::new(mock_client),
+        });
+
+        let request_body = json!({
+            "messages": [{"role": "user", "content": "What is semcache?"}],
+            "model": "gpt-4o",
+            "stream": true
+        });
+
+        // when
+        let result = completions(
+            State(app_state),
+            HeaderMap::new(),
+            axum::Json(request_body),
+            ProviderType::OpenAI,
+        )
+        .await;
+
+        // then the client receives the upstream stream unmodified
+        let response = result.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(extract_response(response).await, streamed_body);
+
+        // and the assembled, non-streamed completion gets cached
+        let cached_body = tokio::time::timeout(Duration::from_secs(5), inserted_receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let cached: Value = serde_json::from_slice(&cached_body.body).unwrap();
+        assert_eq!(cached["object"], "chat.completion");
+        assert_eq!(cached["choices"][0]["message"]["content"], "Semcache");
+        assert_eq!(cached["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn should_replay_cached_response_as_stream_on_cache_hit() {
+        // given
+        let embedding = vec![0.1, 0.2, 0.3];
+        let cached_message = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [{"type": "text", "text": "Semcache is a semantic cache."}],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 5, "output_tokens": 7}
+        });
+
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed
+            .expect_embed()
+            .times(1)
+            .returning(move |_| Ok(embedding.clone()));
+
+        let mut mock_cache = MockCache::new();
+        mock_cache.expect_get_if_present().times(1).returning({
+            let cached_body = serde_json::to_vec(&cached_message).unwrap();
+            move |_, _, _| Ok(Some(hit(CachedResponse::from_body(cached_body.clone()))))
+        });
+        mock_cache.expect_record_hit().times(1).return_const(());
+        mock_cache.expect_insert().times(0);
+
+        // verify client is not called
+        let mut mock_client = MockClient::new();
+        mock_client.expect_post_http_request().times(0);
+        mock_client.expect_post_http_request_stream().times(0);
+
+        let app_state = Arc::new(AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
+            http_client: Box::new(mock_client),
+        });
+
+        let request_body = json!({
+            "model": "claude-3-5-sonnet-20241022",
+            "max_tokens": 1024,
+            "stream": true,
+            "messages": [{"role": "user", "content": "What is semcache?"}]
+        });
+
+        // when
+        let result = completions(
+            State(app_state),
+            HeaderMap::new(),
+            axum::Json(request_body),
+            ProviderType::Anthropic,
+        )
+        .await;
+
+        // then
+        let response = result.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        let body = extract_response(response).await;
+        assert!(body.starts_with("event: message_start\n"));
+        assert!(body.contains(r#""text":"Semcache is a semantic cache.""#));
+        assert!(body.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+    }
+
+    #[tokio::test]
+    async fn should_bypass_cache_for_non_text_content_when_configured() {
+        // given
+        let completion_text = "A cat";
+
+        // nothing is embedded or looked up
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed.expect_embed().times(0);
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache.expect_get_if_present().times(0);
+        mock_cache.expect_insert().times(0);
+
+        let mut mock_client = MockClient::new();
+        mock_client
+            .expect_post_http_request()
+            .times(1)
+            .returning(move |_, _, _| {
+                Ok(UpstreamResponse {
+                    status_code: StatusCode::OK,
+                    header_map: HeaderMap::new(),
+                    response_body: Vec::from(completion_text),
+                })
+            });
+
+        let app_state = Arc::new(AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::Bypass,
+            http_client: Box::new(mock_client),
+        });
+
+        let request_body = json!({
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What animal is this?"},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+                ]
+            }],
+            "model": "gpt-4o"
+        });
+
+        // when
+        let response = completions(
+            State(app_state),
+            HeaderMap::new(),
+            axum::Json(request_body),
+            ProviderType::OpenAI,
+        )
+        .await
+        .unwrap();
+
+        // then
+        assert!(matches!(
+            response.extensions().get::<CacheStatus>(),
+            Some(CacheStatus::NotApplicable)
+        ));
+        assert_eq!(extract_response(response).await, completion_text);
+    }
+
+    #[tokio::test]
+    async fn should_return_response_of_similar_request_in_flight() {
+        // given
+        let embedding = vec![0.1, 0.2, 0.3];
+        let completion_json = r#"{"choices":[{"message":{"content":"Paris"}}]}"#;
+
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed.expect_embed().returning({
+            let embedding = embedding.clone();
+            move |_| Ok(embedding.clone())
+        });
+        // a miss until the leader has cached its response
+        let mut mock_cache = MockCache::new();
+        mock_cache
+            .expect_get_if_present()
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+        mock_cache
+            .expect_get_if_present()
+            .times(1)
+            .returning(move |_, _, _| {
+                Ok(Some(CacheHit {
+                    response: CachedResponse::from_body(completion_json.as_bytes().to_vec()),
+                    entry_id: 3,
+                    similarity: 0.97,
+                    similarity_threshold: 0.9,
+                }))
+            });
+        mock_cache
+            .expect_record_hit()
+            .with(eq(3))
+            .times(1)
+            .return_const(());
+        mock_cache.expect_insert().times(0);
+        // the follower must not call upstream itself
+        let mut mock_client = MockClient::new();
+        mock_client.expect_post_http_request().times(0);
+
+        let coalescer = Arc::new(RequestCoalescer::new(0.9, Duration::from_secs(5), None));
+        let request_body = json!({
+            "messages": [{"role": "user", "content": "What is the capital of France?"}],
+            "model": "gpt-4"
+        });
+        let partition = PartitionKey::from_fields(&partition_fields(
+            &ProviderType::OpenAI,
+            &request_body,
+            &[],
+            &PromptContext {
+                prompt: String::from("What is the capital of France?"),
+                attachments: vec![],
+                history: None,
+            },
+        ));
+        let Flight::Leader(leader) =
+            coalescer.join(partition, &embedding, "What is the capital of France?")
+        else {
+            panic!("Expected the first request to lead")
+        };
+
+        let app_state = Arc::new(AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), Some(coalescer)),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
+            http_client: Box::new(mock_client),
+        });
+
+        // when
+        let follower = tokio::spawn(completions(
+            State(app_state),
+            HeaderMap::new(),
+            axum::Json(request_body),
+            ProviderType::OpenAI,
+        ));
+        // give the follower time to join the flight
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        leader.complete();
+        let response = follower.await.unwrap().unwrap();
+
+        // then
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(matches!(
+            response.extensions().get::<CacheStatus>(),
+            Some(CacheStatus::Hit)
+        ));
+        assert_eq!(response.headers().get(&ENTRY_ID_HEADER).unwrap(), "3");
+        assert_eq!(extract_response(response).await, completion_json);
+    }
+
+    #[tokio::test]
+    async fn should_replay_upstream_status_and_headers_on_cache_hit() {
+        // given
+        let embedding = vec![0.1, 0.2, 0.3];
+        let mut upstream_headers = HeaderMap::new();
+        upstream_headers.insert("x-request-id", "req_1".parse().unwrap());
+        upstream_headers.insert("openai-processing-ms", "120".parse().unwrap());
+        upstream_headers.insert("content-type", "text/plain".parse().unwrap());
+        let saved_response = CachedResponse::from_upstream(
+            ProviderType::OpenAI,
+            Some(String::from("gpt-4")),
+            StatusCode::CREATED,
+            &upstream_headers,
+            br#"{"choices":[]}"#.to_vec(),
+        );
+
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed
+            .expect_embed()
+            .returning(move |_| Ok(embedding.clone()));
+        let mut mock_cache = MockCache::new();
+        mock_cache
+            .expect_get_if_present()
+            .returning(move |_, _, _| {
+                Ok(Some(CacheHit {
+                    response: saved_response.clone(),
+                    entry_id: 7,
+                    similarity: 0.95,
+                    similarity_threshold: 0.9,
+                }))
+            });
+        mock_cache
+            .expect_record_hit()
+            .with(eq(7))
+            .times(1)
+            .return_const(());
+        let mut mock_client = MockClient::new();
+        mock_client.expect_post_http_request().times(0);
+
+        let app_state = Arc::new(AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
+            http_client: Box::new(mock_client),
+        });
+        let request_body = json!({
+            "messages": [{"role": "user", "content": "What is semcache?"}],
+            "model": "gpt-4"
+        });
+
+        // when
+        let response = completions(
+            State(app_state),
+            HeaderMap::new(),
+            axum::Json(request_body),
+            ProviderType::OpenAI,
+        )
+        .await
+        .unwrap();
+
+        // then
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let headers = response.headers();
+        assert_eq!(headers.get("x-request-id").unwrap(), "req_1");
+        assert_eq!(headers.get("openai-processing-ms").unwrap(), "120");
+        assert_eq!(headers.get("content-type").unwrap(), "application/json");
+        assert_eq!(headers.get("x-cache-status").unwrap(), "hit");
+        assert!(headers.contains_key("age"));
+        assert!(headers.contains_key("x-semcache-age"));
+        assert!(headers.contains_key("x-semcache-created-at"));
+        assert_eq!(headers.get("x-semcache-entry-id").unwrap(), "7");
+        assert_eq!(headers.get("x-semcache-similarity").unwrap(), "0.9500");
+        assert_eq!(headers.get("x-semcache-threshold").unwrap(), "0.9000");
+    }
+
+    fn state_with(mock_cache: MockCache<CachedResponse>, mock_client: MockClient) -> Arc<AppState> {
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed
+            .expect_embed()
+            .returning(|_| Ok(vec![0.1, 0.2, 0.3]));
+        Arc::new(AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
+            http_client: Box::new(mock_client),
+        })
+    }
+
+    fn upstream_ok() -> MockClient {
+        let mut mock_client = MockClient::new();
+        mock_client
+            .expect_post_http_request()
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(UpstreamResponse {
+                    status_code: StatusCode::OK,
+                    header_map: HeaderMap::new(),
+                    response_body: br#"{"choices":[]}"#.to_vec(),
+                })
+            });
+        mock_client
+    }
+
+    async fn complete_with(
+        state: Arc<AppState>,
+        headers: HeaderMap,
+    ) -> Result<Response, CompletionError> {
+        let request_body = json!({
+            "messages": [{"role": "user", "content": "What is semcache?"}],
+            "model": "gpt-4"
+        });
+        completions(
+            State(state),
+            headers,
+            axum::Json(request_body),
+            ProviderType::OpenAI,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn should_not_call_upstream_on_miss_when_only_if_cached() {
+        // given
+        let mut mock_cache = MockCache::new();
+        mock_cache
+            .expect_get_if_present()
+            .with(always(), always(), eq(Some(0.97)))
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+        let mut mock_client = MockClient::new();
+        mock_client.expect_post_http_request().times(0);
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, "only-if-cached".parse().unwrap());
+        headers.insert(&SIMILARITY_THRESHOLD_HEADER, "0.97".parse().unwrap());
+
+        // when
+        let result = complete_with(state_with(mock_cache, mock_client), headers).await;
+
+        // then
+        match result {
+            Err(CompletionError::NotCached) => {}
+            _ => panic!("Expected CompletionError::NotCached"),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_skip_lookup_and_refresh_entry_when_no_cache() {
+        // given
+        let mut mock_cache = MockCache::new();
+        mock_cache.expect_get_if_present().times(0);
+        mock_cache
+            .expect_try_update()
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+        mock_cache.expect_insert().times(0);
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+
+        // when
+        let response = complete_with(state_with(mock_cache, upstream_ok()), headers)
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-cache-status").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_treat_hits_older_than_max_age_as_misses() {
+        // given
+        let mut mock_cache = MockCache::new();
+        mock_cache.expect_get_if_present().returning(|_, _, _| {
+            Ok(Some(hit(CachedResponse {
+                created_at: SystemTime::now() - Duration::from_secs(120),
+                ..CachedResponse::from_body(b"stale".to_vec())
+            })))
+        });
+        // no-store, so the fresh response isn't cached either
+        mock_cache.expect_try_update().times(0);
+        mock_cache.expect_insert().times(0);
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, "max-age=60, no-store".parse().unwrap());
+
+        // when
+        let response = complete_with(state_with(mock_cache, upstream_ok()), headers)
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(extract_response(response).await, r#"{"choices":[]}"#);
+    }
+
+    #[test]
+    fn ttl_from_headers_should_parse_seconds() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(ttl_from_headers(&headers).unwrap(), None);
+
+        headers.insert(&CACHE_TTL_HEADER, "300".parse().unwrap());
+        assert_eq!(
+            ttl_from_headers(&headers).unwrap(),
+            Some(Duration::from_secs(300))
+        );
+
+        headers.insert(&CACHE_TTL_HEADER, "five minutes".parse().unwrap());
+        match ttl_from_headers(&headers) {
+            Err(CompletionError::InvalidRequest(_)) => {}
+            _ => panic!("Expected CompletionError::InvalidRequest"),
+        }
+    }
+
     async fn extract_response(response: Response) -> String {
         let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
@@ -1,3 +1,7 @@
+pub mod cache_control;
+pub mod coalescing;
 pub mod error;
 pub mod handler;
+pub mod partition;
 pub mod provider_handlers;
+pub mod streaming;
@@ -0,0 +1,265 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::providers::ProviderType;
+use crate::providers::context::PromptContext;
+
+// Request fields that must match exactly for a cached response to be eligible for a hit
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionField {
+    Model,
+    SystemPrompt,
+    Tools,
+    Temperature,
+    ResponseFormat,
+}
+
+impl PartitionField {
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::Model,
+            Self::SystemPrompt,
+            Self::Tools,
+            Self::Temperature,
+            Self::ResponseFormat,
+        ]
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Model => "model",
+            Self::SystemPrompt => "system_prompt",
+            Self::Tools => "tools",
+            Self::Temperature => "temperature",
+            Self::ResponseFormat => "response_format",
+        }
+    }
+
+    // missing fields are extracted as null, so a request without a temperature doesn't share a
+    // partition with one that sets it explicitly
+    fn extract(&self, request_body: &Value) -> Value {
+        match self {
+            Self::SystemPrompt => system_prompt(request_body),
+            other => request_body
+                .get(other.name())
+                .cloned()
+                .unwrap_or(Value::Null),
+        }
+    }
+}
+
+// Anthropic takes the system prompt as a top level field, OpenAI compatible APIs as messages with
+// the system (or developer) role. Both are collected, so this works for generic upstreams too.
+fn system_prompt(request_body: &Value) -> Value {
+    let top_level = request_body.get("system").into_iter().cloned();
+    let from_messages = request_body
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|message| {
+            matches!(
+                message.get("role").and_then(Value::as_str),
+                Some("system" | "developer")
+            )
+        })
+        .filter_map(|message| message.get("content").cloned());
+    Value::Array(top_level.chain(from_messages).collect())
+}
+
+// The values the partition key is derived from. The provider is always part of them, as cached
+// bodies are only valid in the provider's format. Parts of the prompt that can't be embedded
+// (attachments, earlier turns of a conversation that have to match exactly) are taken from `context`.
+pub fn partition_fields(
+    provider: &ProviderType,
+    request_body: &Value,
+    fields: &[PartitionField],
+    context: &PromptContext,
+) -> Value {
+    let mut key_fields = Map::new();
+    key_fields.insert(
+        String::from("provider"),
+        Value::String(provider.path().to_owned()),
+    );
+    for field in fields {
+        key_fields.insert(field.name().to_owned(), field.extract(request_body));
+    }
+    if !context.attachments.is_empty() {
+        key_fields.insert(
+            String::from("attachments"),
+            Value::Array(context.attachments.clone()),
+        );
+    }
+    if let Some(history) = &context.history {
+        key_fields.insert(String::from("history"), history.clone());
+    }
+    Value::Object(key_fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{PartitionField, partition_fields};
+    use crate::cache::partition::PartitionKey;
+    use crate::providers::ProviderType;
+    use crate::providers::context::PromptContext;
+
+    fn partition_key(
+        provider: &ProviderType,
+        request_body: &Value,
+        fields: &[PartitionField],
+        context: &PromptContext,
+    ) -> PartitionKey {
+        PartitionKey::from_fields(&partition_fields(provider, request_body, fields, context))
+    }
+
+    fn context() -> PromptContext {
+        PromptContext {
+            prompt: String::from("What is the capital of France?"),
+            attachments: vec![],
+            history: None,
+        }
+    }
+
+    #[test]
+    fn should_partition_on_model() {
+        let gpt_4o = json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "hi"}]});
+        let gpt_35 =
+            json!({"model": "gpt-3.5-turbo", "messages": [{"role": "user", "content": "hi"}]});
+
+        let fields = PartitionField::all();
+
+        assert_ne!(
+            partition_key(&ProviderType::OpenAI, &gpt_4o, &fields, &context()),
+            partition_key(&ProviderType::OpenAI, &gpt_35, &fields, &context())
+        );
+    }
+
+    #[test]
+    fn should_partition_on_system_prompt_but_not_user_messages() {
+        let first = json!({"messages": [
+            {"role": "system", "content": "Answer in French"},
+            {"role": "user", "content": "What is the capital of France?"}
+        ]});
+        let same_system = json!({"messages": [
+            {"role": "system", "content": "Answer in French"},
+            {"role": "user", "content": "Tell me the capital of France"}
+        ]});
+        let other_system = json!({"messages": [
+            {"role": "system", "content": "Answer in German"},
+            {"role": "user", "content": "What is the capital of France?"}
+        ]});
+
+        let fields = PartitionField::all();
+        let key = partition_key(&ProviderType::OpenAI, &first, &fields, &context());
+
+        assert_eq!(
+            key,
+            partition_key(&ProviderType::OpenAI, &same_system, &fields, &context())
+        );
+        assert_ne!(
+            key,
+            partition_key(&ProviderType::OpenAI, &other_system, &fields, &context())
+        );
+    }
+
+    #[test]
+    fn should_read_anthropic_top_level_system_prompt() {
+        let first = json!({"system": "Be terse", "messages": []});
+        let second = json!({"system": "Be verbose", "messages": []});
+
+        let fields = vec![PartitionField::SystemPrompt];
+
+        assert_ne!(
+            partition_key(&ProviderType::Anthropic, &first, &fields, &context()),
+            partition_key(&ProviderType::Anthropic, &second, &fields, &context())
+        );
+    }
+
+    #[test]
+    fn should_ignore_fields_not_configured() {
+        let cold = json!({"model": "gpt-4o", "temperature": 0.0});
+        let hot = json!({"model": "gpt-4o", "temperature": 1.0});
+
+        let fields = vec![PartitionField::Model];
+
+        assert_eq!(
+            partition_key(&ProviderType::OpenAI, &cold, &fields, &context()),
+            partition_key(&ProviderType::OpenAI, &hot, &fields, &context())
+        );
+        assert_ne!(
+            partition_key(
+                &ProviderType::OpenAI,
+                &cold,
+                &PartitionField::all(),
+                &context()
+            ),
+            partition_key(
+                &ProviderType::OpenAI,
+                &hot,
+                &PartitionField::all(),
+                &context()
+            )
+        );
+    }
+
+    #[test]
+    fn should_always_partition_on_provider() {
+        let body = json!({"model": "some-model"});
+
+        assert_ne!(
+            partition_key(&ProviderType::OpenAI, &body, &[], &context()),
+            partition_key(&ProviderType::Anthropic, &body, &[], &context())
+        );
+    }
+
+    #[test]
+    fn should_partition_on_history() {
+        let body = json!({"model": "gpt-4o"});
+        let spain = PromptContext {
+            history: Some(json!([{"role": "user", "text": "What is the capital of Spain?"}])),
+            ..context()
+        };
+        let italy = PromptContext {
+            history: Some(json!([{"role": "user", "text": "What is the capital of Italy?"}])),
+            ..context()
+        };
+
+        let fields = PartitionField::all();
+
+        assert_ne!(
+            partition_key(&ProviderType::OpenAI, &body, &fields, &spain),
+            partition_key(&ProviderType::OpenAI, &body, &fields, &italy)
+        );
+    }
+
+    #[test]
+    fn should_partition_on_attachments() {
+        let body = json!({"model": "claude-sonnet-4-0"});
+        let cat = PromptContext {
+            attachments: vec![
+                json!({"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}}),
+            ],
+            ..context()
+        };
+        let dog = PromptContext {
+            attachments: vec![
+                json!({"type": "image", "source": {"type": "url", "url": "https://example.com/dog.png"}}),
+            ],
+            ..context()
+        };
+
+        let fields = PartitionField::all();
+
+        assert_ne!(
+            partition_key(&ProviderType::Anthropic, &body, &fields, &cat),
+            partition_key(&ProviderType::Anthropic, &body, &fields, &dog)
+        );
+        assert_ne!(
+            partition_key(&ProviderType::Anthropic, &body, &fields, &cat),
+            partition_key(&ProviderType::Anthropic, &body, &fields, &context())
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::coalescing::FlightLeader;
+use super::error::CompletionError;
+use super::handler::store_response;
+use crate::app_state::AppState;
+use crate::cache::cached_response::CachedResponse;
+use crate::cache::partition::PartitionKey;
+use crate::clients::client::UpstreamStreamResponse;
+use crate::namespaces::registry::Namespace;
+use crate::providers::ProviderType;
+use crate::streaming::{StreamAssembler, replay};
+
+// number of upstream chunks buffered for a slow client before upstream reads are paused
+const STREAM_BUFFER_SIZE: usize = 64;
+
+// Where a streamed response gets inserted into the cache once it completed
+pub struct CacheTarget {
+    pub namespace: Arc<Namespace>,
+    // the model the request asked for
+    pub model: Option<String>,
+    // the prompt the response is cached for
+    pub prompt: String,
+    // the request fields `partition` was derived from
+    pub partition_fields: Value,
+    pub partition: PartitionKey,
+    pub embedding: Vec<f32>,
+    pub ttl: Option<Duration>,
+    // replaces the entry of the same prompt rather than adding one next to it
+    pub refresh: bool,
+    // hands the response to similar requests waiting on this one once it's cached
+    pub leader: Option<FlightLeader>,
+}
+
+// Proxies an event stream from upstream to the client chunk by chunk. The full response is assembled
+// in a background task as it passes through, and inserted into the cache under `target` once the
+// stream completes successfully. The background task keeps reading upstream if the client disconnects,
+// so an abandoned stream still populates the cache.
+pub async fn forward_stream(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    provider: ProviderType,
+    request_body: Value,
+    target: Option<CacheTarget>,
+) -> Result<Response, CompletionError> {
+    let started_at = Instant::now();
+    let UpstreamStreamResponse {
+        status_code,
+        header_map,
+        mut body_stream,
+    } = state
+        .http_client
+        .post_http_request_stream(headers, provider, request_body)
+        .await?;
+
+    // only store the response if the status code of the response is 2XX
+    let mut assembler = match target {
+        Some(_) if status_code.is_success() => StreamAssembler::for_provider(provider),
+        _ => None,
+    };
+
+    // stored with the response, the content type is set again when it's replayed
+    let cached_headers = assembler.as_ref().map(|_| header_map.clone());
+
+    let (sender, receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let mut client_connected = true;
+        while let Some(chunk) = body_stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    warn!(error = ?err, "Upstream stream failed, response will not be cached");
+                    let _ = sender.send(Err(err)).await;
+                    return;
+                }
+            };
+
+            if let Some(active) = assembler.as_mut()
+                && let Err(err) = active.push(&chunk)
+            {
+                debug!(error = ?err, "Failed to assemble streamed response, it will not be cached");
+                assembler = None;
+            }
+
+            if client_connected && sender.send(Ok(chunk)).await.is_err() {
+                debug!("Client disconnected mid-stream");
+                client_connected = false;
+            }
+            if !client_connected && assembler.is_none() {
+                return;
+            }
+        }
+        drop(sender);
+
+        let (Some(assembler), Some(target), Some(cached_headers)) =
+            (assembler, target, cached_headers)
+        else {
+            return;
+        };
+        match assembler.finish() {
+            Ok(response_body) => {
+                let cached = CachedResponse::from_upstream(
+                    provider,
+                    target.model,
+                    status_code,
+                    &cached_headers,
+                    response_body,
+                )
+                .with_prompt(target.prompt)
+                .with_partition_fields(&target.partition_fields)
+                .with_upstream_latency(started_at.elapsed());
+                match store_response(
+                    &target.namespace,
+                    target.partition,
+                    target.embedding,
+                    cached,
+                    target.ttl,
+                    target.refresh,
+                ) {
+                    Ok(()) => {
+                        if let Some(leader) = target.leader {
+                            leader.complete();
+                        }
+                    }
+                    Err(err) => warn!(error = ?err, "Failed to cache streamed response"),
+                }
+            }
+            Err(err) => debug!(error = ?err, "Streamed response not cached"),
+        }
+    });
+
+    let body = Body::from_stream(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move { receiver.recv().await.map(|chunk| (chunk, receiver)) },
+    ));
+    Ok((status_code, header_map, body).into_response())
+}
+
+// Builds an event stream response out of a cached body, or None if the body isn't a response in the
+// provider's format (e.g. it was written through the cache-aside API)
+pub fn replay_cached(provider: &ProviderType, saved_response: &[u8]) -> Option<Response> {
+    let events = replay(provider, saved_response)
+        .inspect_err(|err| debug!(error = ?err, "Cached response cannot be replayed as a stream"))
+        .ok()?;
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert("X-Cache-Status", HeaderValue::from_static("hit"));
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/event-stream"),
+    );
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    Some((StatusCode::OK, response_headers, events).into_response())
+}
@@ -0,0 +1,272 @@
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use tracing::{debug, error, warn};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::HeaderMap,
+};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::{
+    app_state::AppState,
+    cache::{error::CacheError, partition::PartitionKey},
+    embedding::error::EmbeddingError,
+    namespaces::error::NamespaceError,
+};
+
+#[derive(Debug, Error)]
+pub enum InvalidationError {
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Failed to generate embedding: {0}")]
+    InternalEmbedding(#[from] EmbeddingError),
+    #[error("Error in caching layer: {0}")]
+    InternalCache(#[from] CacheError),
+    #[error("Namespace error: {0}")]
+    Namespace(#[from] NamespaceError),
+}
+
+impl IntoResponse for InvalidationError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidRequest(message) => {
+                warn!("Invalid invalidation request, {}", message);
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            Self::InternalEmbedding(err) => {
+                error!(?err, "returning internal error to user");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+            }
+            Self::InternalCache(err) => {
+                error!(?err, "returning internal error to user");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+            }
+            Self::Namespace(err) => err.into_response(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeleteRequest {
+    pub key: String,
+    // as returned in the x-semcache-partition header, defaults to the partition of the cache
+    // aside API
+    #[serde(default)]
+    pub partition: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeleteSimilarRequest {
+    pub key: String,
+    // between 0 and 1, every entry at least this similar to the key is deleted
+    pub similarity: f32,
+    #[serde(default)]
+    pub partition: Option<String>,
+}
+
+// Deletes the entry stored under exactly this key
+pub async fn delete(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<DeleteRequest>,
+) -> Result<Response, InvalidationError> {
+    debug!("invalidation::DELETE request received");
+    let namespace = state.namespaces.resolve(&headers)?;
+    let partition = parse_partition(request.partition.as_deref())?;
+    let embedding = state.embedding_service.embed(&request.key).await?;
+    let deleted = namespace.cache.delete(partition, &embedding)? as usize;
+    Ok(deleted_response(deleted))
+}
+
+// Deletes every entry whose key is within the given similarity of this key
+pub async fn delete_similar(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<DeleteSimilarRequest>,
+) -> Result<Response, InvalidationError> {
+    debug!("invalidation::DELETE similar request received");
+    if !(0.0..=1.0).contains(&request.similarity) {
+        return Err(InvalidationError::InvalidRequest(format!(
+            "similarity must be between 0 and 1, found {}",
+            request.similarity
+        )));
+    }
+    let partition = parse_partition(request.partition.as_deref())?;
+    let namespace = state.namespaces.resolve(&headers)?;
+    let embedding = state.embedding_service.embed(&request.key).await?;
+    let deleted = namespace
+        .cache
+        .delete_similar(partition, &embedding, request.similarity)?;
+    Ok(deleted_response(deleted))
+}
+
+pub async fn delete_partition(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(partition): Path<String>,
+) -> Result<Response, InvalidationError> {
+    debug!("invalidation::DELETE partition request received");
+    let partition = parse_partition(Some(&partition))?;
+    let namespace = state.namespaces.resolve(&headers)?;
+    let deleted = namespace.cache.delete_partition(partition)?;
+    Ok(deleted_response(deleted))
+}
+
+// Only flushes the namespace of the request
+pub async fn flush(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, InvalidationError> {
+    debug!("invalidation::DELETE flush request received");
+    let namespace = state.namespaces.resolve(&headers)?;
+    let deleted = namespace.cache.flush()?;
+    Ok(deleted_response(deleted))
+}
+
+fn parse_partition(partition: Option<&str>) -> Result<PartitionKey, InvalidationError> {
+    match partition {
+        None => Ok(PartitionKey::default()),
+        Some(partition) => partition.parse().map_err(|_| {
+            InvalidationError::InvalidRequest(format!(
+                "Expected a partition key of 16 hex digits, found '{partition}'"
+            ))
+        }),
+    }
+}
+
+fn deleted_response(deleted: usize) -> Response {
+    (StatusCode::OK, Json(json!({ "deleted": deleted }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body,
+        extract::{Path, State},
+        http::HeaderMap,
+    };
+    use mockall::predicate::eq;
+    use reqwest::StatusCode;
+    use serde_json::{Value, json};
+
+    use crate::{
+        app_state::AppState,
+        cache::{cache::MockCache, cached_response::CachedResponse, partition::PartitionKey},
+        embedding::service::MockEmbeddingService,
+        endpoints::invalidation::handler::{
+            DeleteRequest, DeleteSimilarRequest, InvalidationError, delete, delete_partition,
+            delete_similar,
+        },
+        namespaces::registry::Namespaces,
+        providers::context::{ContextConfig, NonTextContent},
+    };
+
+    fn app_state(
+        mock_embed: MockEmbeddingService,
+        mock_cache: MockCache<CachedResponse>,
+    ) -> AppState {
+        let mut mock_client = crate::clients::client::MockClient::new();
+        mock_client.expect_post_http_request().times(0);
+        AppState {
+            embedding_service: Box::new(mock_embed),
+            namespaces: Namespaces::single(Box::new(mock_cache), None),
+            partition_fields: vec![],
+            context_config: ContextConfig::default(),
+            non_text_content: NonTextContent::default(),
+            http_client: Box::new(mock_client),
+        }
+    }
+
+    async fn deleted_count(response: axum::response::Response) -> Value {
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<Value>(&bytes).unwrap()["deleted"].clone()
+    }
+
+    #[tokio::test]
+    async fn delete_should_remove_entry_in_given_partition() {
+        // given
+        let embedding = vec![0.1, 0.2, 0.3];
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed.expect_embed().times(1).returning({
+            let embedding = embedding.clone();
+            move |_| Ok(embedding.clone())
+        });
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache
+            .expect_delete()
+            .with(eq(PartitionKey(0xff)), eq(embedding))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let request = DeleteRequest {
+            key: String::from("What is the capital of France?"),
+            partition: Some(String::from("00000000000000ff")),
+        };
+
+        // when
+        let response = delete(
+            State(Arc::new(app_state(mock_embed, mock_cache))),
+            HeaderMap::new(),
+            axum::Json(request),
+        )
+        .await
+        .unwrap();
+
+        // then
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(deleted_count(response).await, json!(1));
+    }
+
+    #[tokio::test]
+    async fn delete_similar_should_reject_invalid_similarity() {
+        // given
+        let mut mock_embed = MockEmbeddingService::new();
+        mock_embed.expect_embed().times(0);
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache.expect_delete_similar().times(0);
+
+        let request = DeleteSimilarRequest {
+            key: String::from("What is the capital of France?"),
+            similarity: 1.5,
+            partition: None,
+        };
+
+        // when
+        let result = delete_similar(
+            State(Arc::new(app_state(mock_embed, mock_cache))),
+            HeaderMap::new(),
+            axum::Json(request),
+        )
+        .await;
+
+        // then
+        assert!(matches!(result, Err(InvalidationError::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn delete_partition_should_reject_malformed_key() {
+        // given
+        let mut mock_cache: MockCache<CachedResponse> = MockCache::new();
+        mock_cache.expect_delete_partition().times(0);
+
+        // when
+        let result = delete_partition(
+            State(Arc::new(app_state(MockEmbeddingService::new(), mock_cache))),
+            HeaderMap::new(),
+            Path(String::from("model=gpt-4o")),
+        )
+        .await;
+
+        // then
+        assert!(matches!(result, Err(InvalidationError::InvalidRequest(_))));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod admin;
 pub mod cache_aside;
 pub mod chat;
+pub mod invalidation;
 pub mod metrics;
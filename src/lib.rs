@@ -0,0 +1,13 @@
+pub mod app_state;
+pub mod auth;
+pub mod cache;
+pub mod clients;
+pub mod config;
+pub mod embedding;
+pub mod endpoints;
+pub mod metrics;
+pub mod namespaces;
+pub mod providers;
+pub mod rerank;
+pub mod streaming;
+pub mod utils;
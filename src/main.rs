@@ -1,28 +1,34 @@
-mod app_state;
-mod cache;
-mod clients;
-mod config;
-mod embedding;
-mod endpoints;
-mod metrics;
-mod providers;
-mod utils;
-
-use crate::config::get_eviction_policy;
-use crate::endpoints::chat::provider_handlers::{
-    anthropic_handler, generic_handler, openai_handler,
-};
-use crate::endpoints::metrics::handler::prometheus_metrics_handler;
-use crate::metrics::metrics::{init_metrics, track_metrics};
-use crate::providers::OPEN_AI_REST_PATH;
-use app_state::AppState;
 use axum::Router;
 use axum::http::StatusCode;
-use axum::routing::{get, post, put};
-use config::{get_log_level, get_port, get_similarity_threshold};
-use providers::ProviderType;
+use axum::routing::{delete, get, post, put};
+use semcache::app_state::AppState;
+use semcache::auth::authenticator::{Authenticator, require_scope};
+use semcache::auth::config::Scope;
+use semcache::cache::consistency_checker::spawn_consistency_checker;
+use semcache::cache::evictor::spawn_evictor;
+use semcache::cache::memory_reconciler::spawn_memory_reconciler;
+use semcache::cache::reaper::spawn_expiry_reaper;
+use semcache::cache::snapshotter::spawn_snapshotter;
+use semcache::config::{
+    get_auth_config, get_coalescing_config, get_context_config, get_embedding_config,
+    get_eviction_policy, get_eviction_strategy, get_guard_config, get_index_type,
+    get_maintenance_config, get_namespace_config, get_non_text_content, get_partition_fields,
+    get_persistence_config, get_rerank_config, get_ttl_config,
+};
+use semcache::config::{get_log_level, get_port, get_similarity_threshold};
+use semcache::endpoints::chat::provider_handlers::{
+    anthropic_handler, generic_handler, openai_handler,
+};
+use semcache::endpoints::metrics::handler::prometheus_metrics_handler;
+use semcache::metrics::metrics::{init_metrics, track_metrics};
+use semcache::providers::OPEN_AI_REST_PATH;
+use semcache::providers::ProviderType;
+use semcache::{config, endpoints};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
+use tokio::sync::Notify;
 use tower_http::services::ServeDir;
 use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
@@ -53,7 +59,141 @@ async fn main() {
 
     info!("Eviction policy {:?}", eviction_policy);
 
-    let shared_state = Arc::new(AppState::new(similarity_threshold, eviction_policy));
+    let eviction_strategy = get_eviction_strategy(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed eviction strategy from conf");
+        panic!("Malformed eviction strategy in config")
+    });
+    info!("Eviction strategy {:?}", eviction_strategy);
+
+    let partition_fields = get_partition_fields(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed partition key from conf");
+        panic!("Malformed partition key in config")
+    });
+    info!("Partition key fields {:?}", partition_fields);
+
+    let context_config = get_context_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed context strategy config from conf");
+        panic!("Malformed context strategy config in config")
+    });
+    info!("Context strategies {:?}", context_config);
+
+    let non_text_content = get_non_text_content(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed non-text content handling from conf");
+        panic!("Malformed non-text content handling in config")
+    });
+    info!("Non-text content handling {:?}", non_text_content);
+
+    let ttl_config = get_ttl_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed ttl config from conf");
+        panic!("Malformed ttl config in config")
+    });
+    info!("TTL config {:?}", ttl_config);
+
+    let persistence_config = get_persistence_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed persistence config from conf");
+        panic!("Malformed persistence config in config")
+    });
+    info!("Persistence config {:?}", persistence_config);
+
+    let maintenance_config = get_maintenance_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed maintenance config from conf");
+        panic!("Malformed maintenance config in config")
+    });
+    info!("Maintenance config {:?}", maintenance_config);
+
+    let index_type = get_index_type(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed semantic index config from conf");
+        panic!("Malformed semantic index config in config")
+    });
+    info!("Semantic index {:?}", index_type);
+
+    let embedding_config = get_embedding_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed embedding config from conf");
+        panic!("Malformed embedding config in config")
+    });
+    info!("Embedding config {:?}", embedding_config);
+
+    let coalescing_config = get_coalescing_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed coalescing config from conf");
+        panic!("Malformed coalescing config in config")
+    });
+    info!("Coalescing config {:?}", coalescing_config);
+
+    let namespace_config = get_namespace_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed namespaces config from conf");
+        panic!("Malformed namespaces config in config")
+    });
+    info!("Namespace config {:?}", namespace_config);
+
+    let rerank_config = get_rerank_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed rerank config from conf");
+        panic!("Malformed rerank config in config")
+    });
+    info!("Rerank config {:?}", rerank_config);
+
+    let guard_config = get_guard_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed guard config from conf");
+        panic!("Malformed guard config in config")
+    });
+    info!("Guard config {:?}", guard_config);
+
+    let auth_config = get_auth_config(&config).unwrap_or_else(|err| {
+        error!(?err, "Malformed auth config from conf");
+        panic!("Malformed auth config in config")
+    });
+    info!(
+        "Auth enabled: {}, with {} keys",
+        auth_config.enabled,
+        auth_config.keys.len()
+    );
+    let authenticator = Arc::new(Authenticator::new(auth_config));
+    // rejects requests without a key that has `scope`
+    let require = |scope: Scope| {
+        axum::middleware::from_fn_with_state((authenticator.clone(), scope), require_scope)
+    };
+
+    // inserts wake the eviction task once their cache is full
+    let eviction_signal = Arc::new(Notify::new());
+    let shared_state = Arc::new(AppState::new(
+        similarity_threshold,
+        eviction_policy,
+        eviction_strategy,
+        partition_fields,
+        context_config,
+        non_text_content,
+        ttl_config.default_seconds.map(Duration::from_secs),
+        persistence_config
+            .enabled
+            .then(|| PathBuf::from(&persistence_config.directory)),
+        index_type,
+        embedding_config,
+        coalescing_config
+            .enabled
+            .then(|| Duration::from_millis(coalescing_config.wait_timeout_ms)),
+        namespace_config,
+        rerank_config,
+        guard_config,
+        eviction_signal.clone(),
+    ));
+    spawn_evictor(shared_state.clone(), eviction_signal);
+    spawn_memory_reconciler(
+        shared_state.clone(),
+        Duration::from_secs(maintenance_config.memory_reconcile_interval_seconds),
+    );
+    spawn_consistency_checker(
+        shared_state.clone(),
+        Duration::from_secs(maintenance_config.consistency_check_interval_seconds),
+    );
+    spawn_expiry_reaper(
+        shared_state.clone(),
+        Duration::from_secs(ttl_config.reap_interval_seconds),
+    );
+    if persistence_config.enabled {
+        spawn_snapshotter(
+            shared_state.clone(),
+            Duration::from_secs(persistence_config.snapshot_interval_seconds),
+        );
+    }
 
     // read through cache (proxy) routes
     let read_through_routes = Router::new()
@@ -61,26 +201,46 @@ async fn main() {
         .route(OPEN_AI_REST_PATH, post(openai_handler))
         .route(ProviderType::Anthropic.path(), post(anthropic_handler))
         .route(ProviderType::Generic.path(), post(generic_handler))
-        .layer(axum::middleware::from_fn(track_metrics));
+        .layer(axum::middleware::from_fn(track_metrics))
+        .layer(require(Scope::Proxy));
 
     // cache aside endpoints
     // todo add metrics middleware for these routes
-    let cache_aside_routes = Router::new()
+    let cache_aside_read_routes = Router::new()
         .route(
             "/semcache/v1/get",
             post(endpoints::cache_aside::handler::get),
         )
+        .layer(require(Scope::CacheRead));
+    let cache_aside_write_routes = Router::new()
         .route(
             "/semcache/v1/put",
             put(endpoints::cache_aside::handler::put),
-        );
+        )
+        .layer(require(Scope::CacheWrite));
 
-    let app = Router::new()
-        // healthcheck
-        .route("/", get(|| async { StatusCode::OK }))
-        // Provider endpoints
-        .merge(read_through_routes)
-        .merge(cache_aside_routes)
+    // invalidation endpoints
+    let invalidation_routes = Router::new()
+        .route(
+            "/semcache/v1/delete",
+            delete(endpoints::invalidation::handler::delete),
+        )
+        .route(
+            "/semcache/v1/delete/similar",
+            delete(endpoints::invalidation::handler::delete_similar),
+        )
+        .route(
+            "/semcache/v1/delete/partition/{partition}",
+            delete(endpoints::invalidation::handler::delete_partition),
+        )
+        .route(
+            "/semcache/v1/flush",
+            delete(endpoints::invalidation::handler::flush),
+        )
+        .layer(require(Scope::CacheWrite));
+
+    // admin dashboard and metrics
+    let admin_routes = Router::new()
         // Prometheus metrics
         .route("/metrics", get(prometheus_metrics_handler))
         // Admin dashboard
@@ -90,8 +250,19 @@ async fn main() {
             "/dashboard-metrics",
             get(endpoints::metrics::handler::dashboard_metrics_handler),
         )
+        .layer(require(Scope::Admin));
+
+    let app = Router::new()
+        // healthcheck
+        .route("/", get(|| async { StatusCode::OK }))
+        // Provider endpoints
+        .merge(read_through_routes)
+        .merge(cache_aside_read_routes)
+        .merge(cache_aside_write_routes)
+        .merge(invalidation_routes)
+        .merge(admin_routes)
         .nest_service("/static", ServeDir::new("assets"))
-        .with_state(shared_state);
+        .with_state(shared_state.clone());
 
     let port = get_port(&config).unwrap_or(8080);
 
@@ -112,6 +283,13 @@ async fn main() {
             error!(error = ?err);
             panic!("Failed to start axum server")
         });
+
+    // keep the logs short for the next start
+    for namespace in shared_state.namespaces.all() {
+        if let Err(err) = namespace.cache.snapshot() {
+            error!(error = ?err, namespace = namespace.name, "Failed to snapshot cache on shutdown");
+        }
+    }
 }
 
 async fn shutdown_signal() {
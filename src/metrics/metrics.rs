@@ -5,8 +5,9 @@ use axum::http::StatusCode;
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use prometheus::{
-    HistogramVec, IntCounter, IntGauge, register_histogram_vec, register_int_counter,
-    register_int_gauge,
+    Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, register_histogram,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec,
 };
 use std::sync::LazyLock;
 use std::time::Instant;
@@ -78,6 +79,155 @@ pub static CACHE_SIZE: LazyLock<IntGauge> = LazyLock::new(|| {
     })
 });
 
+pub static NAMESPACE_CACHE_HIT: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        metric_name!("namespace_cache_hit"),
+        "Cache hit, by namespace",
+        &["namespace"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating namespace cache hit metric")
+    })
+});
+
+pub static NAMESPACE_CACHE_MISS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        metric_name!("namespace_cache_miss"),
+        "Cache miss, by namespace",
+        &["namespace"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating namespace cache miss metric")
+    })
+});
+
+pub static NAMESPACE_CACHE_SIZE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        metric_name!("namespace_cache_size"),
+        "The number of entries in the cache, by namespace",
+        &["namespace"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating namespace cache size metric")
+    })
+});
+
+pub static EMBEDDING_QUEUE_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        metric_name!("embedding_queue_depth"),
+        "The number of prompts waiting to be embedded"
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating embedding queue depth metric")
+    })
+});
+
+pub static EMBEDDING_BATCH_SIZE: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        metric_name!("embedding_batch_size"),
+        "The number of prompts embedded together in one batch",
+        vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating embedding batch size metric")
+    })
+});
+
+// Outcomes of coalesced cache misses: "leader" for misses which called upstream while similar ones
+// waited, "served" for misses answered by a leader, "failed" and "timed_out" for misses which had
+// to call upstream themselves
+pub static COALESCED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        metric_name!("coalesced_requests"),
+        "Cache misses coalesced with a concurrent similar miss, by outcome",
+        &["outcome"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating coalesced requests metric")
+    })
+});
+
+// lookups which found similar entries, none of which the reranker accepted
+pub static RERANK_REJECTIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        metric_name!("rerank_rejections"),
+        "Lookups whose candidates were all rejected by the reranker"
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating rerank rejections metric")
+    })
+});
+
+pub static GUARD_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        metric_name!("guard_rejections"),
+        "Cache candidates rejected by the hit guard, by what didn't match the prompt",
+        &["reason"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating guard rejections metric")
+    })
+});
+
+// Entries removed to make room or because they expired: "entry_limit" and "memory_limit" for
+// evictions by the eviction policy, "admission" for new entries TinyLFU didn't admit, "expired"
+// for entries removed by the reaper
+pub static EVICTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        metric_name!("evictions"),
+        "Entries removed from the cache by eviction or expiry, by reason",
+        &["reason"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating evictions metric")
+    })
+});
+
+pub static ORPHANS_REPAIRED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        metric_name!("orphans_repaired"),
+        "Responses without a vector and vectors without a response removed from the cache, by store",
+        &["store"]
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating orphans repaired metric")
+    })
+});
+
+// the caches' own estimate of their memory usage, which memory limits are enforced on
+pub static CACHE_MEMORY_ESTIMATE_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        metric_name!("cache_memory_estimate_bytes"),
+        "Estimated memory taken up by the responses and semantic indexes of all caches"
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating cache memory estimate metric")
+    })
+});
+
+// the cgroup's memory usage minus the cache estimate, negative if the estimate is too high
+pub static MEMORY_UNACCOUNTED_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        metric_name!("memory_unaccounted_bytes"),
+        "Memory used by the process according to its cgroup, besides the estimated cache memory"
+    )
+    .unwrap_or_else(|err| {
+        error!(error = ?err);
+        panic!("Issue creating unaccounted memory metric")
+    })
+});
+
 pub fn init_metrics() {
     initialize_metrics_collection();
 }
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::error::NamespaceError;
+use super::registry::validate_name;
+use crate::cache::cache_impl::EvictionPolicy;
+use crate::cache::eviction::EvictionStrategy;
+
+const DEFAULT_MAX_NAMESPACES: usize = 100;
+
+// Where the namespace of a request comes from
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NamespaceSource {
+    // the x-semcache-namespace header
+    #[default]
+    Header,
+    // a hash of the api key the request is sent upstream with
+    ApiKey,
+}
+
+// Settings of a namespace which differ from the top level cache settings
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NamespaceOverrides {
+    pub similarity_threshold: Option<f32>,
+    pub eviction_policy: Option<EvictionPolicy>,
+    pub eviction_strategy: Option<EvictionStrategy>,
+    pub ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NamespaceConfig {
+    pub enabled: bool,
+    #[serde(default)]
+    pub source: NamespaceSource,
+    // most namespaces created besides the default one
+    #[serde(default = "default_max_namespaces")]
+    pub max_namespaces: usize,
+    #[serde(default)]
+    pub overrides: HashMap<String, NamespaceOverrides>,
+}
+
+fn default_max_namespaces() -> usize {
+    DEFAULT_MAX_NAMESPACES
+}
+
+impl Default for NamespaceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: NamespaceSource::default(),
+            max_namespaces: DEFAULT_MAX_NAMESPACES,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl NamespaceConfig {
+    pub fn validate(self) -> Result<Self, NamespaceError> {
+        for (name, overrides) in &self.overrides {
+            validate_name(name)?;
+            if let Some(threshold) = overrides.similarity_threshold
+                && !(0.0..=1.0).contains(&threshold)
+            {
+                return Err(NamespaceError::InvalidConfig(format!(
+                    "similarity_threshold of namespace '{name}' must be between 0 and 1"
+                )));
+            }
+            if let Some(eviction_policy) = &overrides.eviction_policy {
+                eviction_policy.clone().validate().map_err(|err| {
+                    NamespaceError::InvalidConfig(format!("namespace '{name}': {err}"))
+                })?;
+            }
+        }
+        Ok(self)
+    }
+}
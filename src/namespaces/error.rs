@@ -0,0 +1,40 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::cache::error::CacheError;
+
+#[derive(Debug, Error)]
+pub enum NamespaceError {
+    #[error("Invalid namespace {0}, expected 1 to 64 letters, digits, '-' or '_'")]
+    InvalidName(String),
+
+    #[error("Invalid namespace config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Namespace limit of {0} reached")]
+    LimitReached(usize),
+
+    #[error("Failed to create namespace cache: {0}")]
+    InternalCache(#[from] CacheError),
+}
+
+impl IntoResponse for NamespaceError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidName(_) | Self::InvalidConfig(_) => {
+                warn!("Invalid namespace, {}", self);
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Self::LimitReached(_) => {
+                warn!("Refusing to create namespace, {}", self);
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string()).into_response()
+            }
+            Self::InternalCache(err) => {
+                error!(?err, "returning internal error to user");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use sha2::{Digest, Sha256};
+use tokio::sync::Notify;
+use tracing::{error, info};
+
+use super::config::{NamespaceConfig, NamespaceOverrides, NamespaceSource};
+use super::error::NamespaceError;
+use crate::cache::cache::{Cache, CacheHit};
+use crate::cache::cache_impl::{CacheImpl, EvictionPolicy};
+use crate::cache::cached_response::CachedResponse;
+use crate::cache::error::CacheError;
+use crate::cache::eviction::EvictionStrategy;
+use crate::cache::partition::PartitionKey;
+use crate::cache::persistence::Persistence;
+use crate::cache::response_store::ResponseStore;
+use crate::cache::semantic_store::index_type::IndexType;
+use crate::endpoints::chat::coalescing::RequestCoalescer;
+use crate::rerank::error::RerankError;
+use crate::rerank::guard::HitGuard;
+use crate::rerank::verifier::HitVerifier;
+use crate::utils::header_utils::{API_KEY_HEADER, NAMESPACE_HEADER};
+
+// Requests without a namespace use the default one, which is persisted at the top of the
+// persistence directory like before namespaces existed
+pub const DEFAULT_NAMESPACE: &str = "default";
+const MAX_NAME_LENGTH: usize = 64;
+// named namespaces are persisted under <persistence directory>/namespaces/<name>
+const NAMESPACES_DIR: &str = "namespaces";
+const LOCK_PANIC: &str = "Namespace lock poisoned, unrecoverable error in namespace registry";
+
+// An isolated cache, nothing cached in one namespace can be served in another
+pub struct Namespace {
+    pub name: String,
+    pub cache: Box<dyn Cache<CachedResponse>>,
+    // None if concurrent misses aren't coalesced
+    pub coalescer: Option<Arc<RequestCoalescer>>,
+    // None if the nearest entry is served without a check
+    pub verifier: Option<Arc<HitVerifier>>,
+    // None if prompts aren't compared for numbers, dates, negations and quotes
+    pub guard: Option<Arc<HitGuard>>,
+}
+
+impl Namespace {
+    // The cached response to serve for `prompt`, checked by the guard and verifier if there are
+    // any. Candidates the guard rejects are never passed on to the verifier.
+    pub async fn find_hit<E>(
+        &self,
+        prompt: &str,
+        partition: PartitionKey,
+        embedding: &[f32],
+        similarity_threshold: Option<f32>,
+    ) -> Result<Option<CacheHit<CachedResponse>>, E>
+    where
+        E: From<CacheError> + From<RerankError>,
+    {
+        let top_k = match (&self.verifier, &self.guard) {
+            (None, None) => {
+                return Ok(self.cache.get_if_present(
+                    partition,
+                    embedding,
+                    similarity_threshold,
+                )?);
+            }
+            (Some(verifier), _) => verifier.top_k,
+            (None, Some(guard)) => guard.top_k,
+        };
+        let mut candidates =
+            self.cache
+                .get_candidates(partition, embedding, top_k, similarity_threshold)?;
+        if let Some(guard) = &self.guard {
+            candidates = guard.retain(prompt, candidates);
+        }
+        match &self.verifier {
+            Some(verifier) => Ok(verifier.select(prompt, candidates).await?),
+            None => Ok(candidates.into_iter().next()),
+        }
+    }
+}
+
+// How the cache of a namespace is built, unless the namespace overrides it
+pub struct CacheSettings {
+    pub similarity_threshold: f32,
+    pub eviction_policy: EvictionPolicy,
+    pub eviction_strategy: EvictionStrategy,
+    pub default_ttl: Option<Duration>,
+    pub index_type: IndexType,
+    pub dimensionality: u32,
+    // None if caches only live in memory
+    pub persistence_directory: Option<PathBuf>,
+    // None if concurrent misses aren't coalesced
+    pub coalescing_wait_timeout: Option<Duration>,
+    // shared by all namespaces, None if hits aren't verified
+    pub verifier: Option<Arc<HitVerifier>>,
+    // shared by all namespaces, None if hits aren't guarded
+    pub guard: Option<Arc<HitGuard>>,
+    // wakes the background eviction task, None if inserts evict themselves
+    pub eviction_signal: Option<Arc<Notify>>,
+}
+
+impl CacheSettings {
+    fn build(
+        &self,
+        name: &str,
+        overrides: Option<&NamespaceOverrides>,
+    ) -> Result<Namespace, NamespaceError> {
+        let overrides = overrides.cloned().unwrap_or_default();
+        let similarity_threshold = overrides
+            .similarity_threshold
+            .unwrap_or(self.similarity_threshold);
+        let eviction_policy = overrides
+            .eviction_policy
+            .unwrap_or_else(|| self.eviction_policy.clone());
+        let eviction_strategy = overrides
+            .eviction_strategy
+            .unwrap_or(self.eviction_strategy);
+        let default_ttl = overrides
+            .ttl_seconds
+            .map(Duration::from_secs)
+            .or(self.default_ttl);
+
+        let cache = CacheImpl::new(
+            self.index_type.new_store(self.dimensionality),
+            ResponseStore::new(),
+            similarity_threshold,
+            eviction_policy,
+            default_ttl,
+        )
+        .with_eviction_strategy(eviction_strategy)
+        .with_namespace(name);
+        let cache = match &self.eviction_signal {
+            Some(signal) => cache.with_eviction_signal(signal.clone()),
+            None => cache,
+        };
+        // restore the entries persisted by a previous run
+        let cache = match &self.persistence_directory {
+            Some(directory) => {
+                let directory = match name {
+                    DEFAULT_NAMESPACE => directory.clone(),
+                    name => directory.join(NAMESPACES_DIR).join(name),
+                };
+                cache.with_persistence(Persistence::open(directory)?)?
+            }
+            None => cache,
+        };
+        let coalescer = self.coalescing_wait_timeout.map(|wait_timeout| {
+            Arc::new(RequestCoalescer::new(
+                similarity_threshold,
+                wait_timeout,
+                self.guard.clone(),
+            ))
+        });
+        Ok(Namespace {
+            name: String::from(name),
+            cache: Box::new(cache),
+            coalescer,
+            verifier: self.verifier.clone(),
+            guard: self.guard.clone(),
+        })
+    }
+}
+
+// Maps requests to the namespace they are cached in. Namespaces are created on first use.
+pub struct Namespaces {
+    default: Arc<Namespace>,
+    // None if every request uses the default namespace
+    isolated: Option<IsolatedNamespaces>,
+}
+
+struct IsolatedNamespaces {
+    source: NamespaceSource,
+    max_namespaces: usize,
+    overrides: HashMap<String, NamespaceOverrides>,
+    settings: CacheSettings,
+    namespaces: RwLock<HashMap<String, Arc<Namespace>>>,
+}
+
+impl Namespaces {
+    pub fn new(settings: CacheSettings, config: NamespaceConfig) -> Result<Self, NamespaceError> {
+        let default =
+            Arc::new(settings.build(DEFAULT_NAMESPACE, config.overrides.get(DEFAULT_NAMESPACE))?);
+        let isolated = config.enabled.then(|| IsolatedNamespaces {
+            source: config.source,
+            max_namespaces: config.max_namespaces,
+            overrides: config.overrides,
+            settings,
+            namespaces: RwLock::new(HashMap::new()),
+        });
+        Ok(Self { default, isolated })
+    }
+
+    // Every request uses a default namespace made of `cache` and `coalescer`
+    #[cfg(test)]
+    pub fn single(
+        cache: Box<dyn Cache<CachedResponse>>,
+        coalescer: Option<Arc<RequestCoalescer>>,
+    ) -> Self {
+        Self {
+            default: Arc::new(Namespace {
+                name: String::from(DEFAULT_NAMESPACE),
+                cache,
+                coalescer,
+                verifier: None,
+                guard: None,
+            }),
+            isolated: None,
+        }
+    }
+
+    pub fn resolve(&self, headers: &HeaderMap) -> Result<Arc<Namespace>, NamespaceError> {
+        let Some(isolated) = &self.isolated else {
+            return Ok(self.default.clone());
+        };
+        match isolated.source.name(headers)? {
+            Some(name) if name != DEFAULT_NAMESPACE => isolated.get_or_create(name),
+            _ => Ok(self.default.clone()),
+        }
+    }
+
+    // The default namespace followed by every namespace created so far
+    pub fn all(&self) -> Vec<Arc<Namespace>> {
+        let mut all = vec![self.default.clone()];
+        if let Some(isolated) = &self.isolated {
+            let namespaces = isolated.namespaces.read().expect(LOCK_PANIC);
+            all.extend(namespaces.values().cloned());
+        }
+        all
+    }
+}
+
+impl IsolatedNamespaces {
+    fn get_or_create(&self, name: String) -> Result<Arc<Namespace>, NamespaceError> {
+        if let Some(namespace) = self.namespaces.read().expect(LOCK_PANIC).get(&name) {
+            return Ok(namespace.clone());
+        }
+
+        // creating a namespace is rare, restoring its persisted entries under the lock keeps two
+        // requests from opening the same persistence directory
+        let mut namespaces = self.namespaces.write().expect(LOCK_PANIC);
+        if let Some(namespace) = namespaces.get(&name) {
+            return Ok(namespace.clone());
+        }
+        if namespaces.len() >= self.max_namespaces {
+            return Err(NamespaceError::LimitReached(self.max_namespaces));
+        }
+        let namespace = Arc::new(
+            self.settings
+                .build(&name, self.overrides.get(&name))
+                .inspect_err(
+                    |err| error!(error = ?err, namespace = name, "Failed to create namespace"),
+                )?,
+        );
+        info!(namespace = name, "Created namespace");
+        namespaces.insert(name, namespace.clone());
+        Ok(namespace)
+    }
+}
+
+impl NamespaceSource {
+    // None if the request doesn't name a namespace
+    fn name(&self, headers: &HeaderMap) -> Result<Option<String>, NamespaceError> {
+        match self {
+            NamespaceSource::Header => {
+                let Some(value) = headers.get(&NAMESPACE_HEADER) else {
+                    return Ok(None);
+                };
+                let name = value
+                    .to_str()
+                    .map_err(|_| NamespaceError::InvalidName(format!("{value:?}")))?;
+                validate_name(name)?;
+                Ok(Some(String::from(name)))
+            }
+            NamespaceSource::ApiKey => {
+                let api_key = headers
+                    .get(AUTHORIZATION)
+                    .or_else(|| headers.get(&API_KEY_HEADER))
+                    .map(|value| value.as_bytes());
+                Ok(api_key.map(api_key_namespace))
+            }
+        }
+    }
+}
+
+// Named after the first 16 hex digits of the sha256 of the api key, so keys never end up in logs,
+// metrics or on disk. The "Bearer " prefix is left out.
+fn api_key_namespace(api_key: &[u8]) -> String {
+    let api_key = api_key.strip_prefix(b"Bearer ").unwrap_or(api_key);
+    let digest = Sha256::digest(api_key);
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    format!("key-{:016x}", u64::from_be_bytes(prefix))
+}
+
+// Names end up in paths and metric labels, so only a safe set of characters is allowed
+pub fn validate_name(name: &str) -> Result<(), NamespaceError> {
+    let valid = (1..=MAX_NAME_LENGTH).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(NamespaceError::InvalidName(format!("'{name}'")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{CacheSettings, DEFAULT_NAMESPACE, Namespaces, api_key_namespace};
+    use crate::cache::cache_impl::EvictionPolicy;
+    use crate::cache::cached_response::CachedResponse;
+    use crate::cache::eviction::EvictionStrategy;
+    use crate::cache::partition::PartitionKey;
+    use crate::cache::semantic_store::index_type::IndexType;
+    use crate::endpoints::chat::error::CompletionError;
+    use crate::namespaces::config::{NamespaceConfig, NamespaceOverrides, NamespaceSource};
+    use crate::namespaces::error::NamespaceError;
+    use crate::rerank::guard::{GuardConfig, HitGuard};
+    use crate::utils::header_utils::NAMESPACE_HEADER;
+
+    fn settings() -> CacheSettings {
+        CacheSettings {
+            similarity_threshold: 0.9,
+            eviction_policy: EvictionPolicy::EntryLimit(10),
+            eviction_strategy: EvictionStrategy::default(),
+            default_ttl: None,
+            index_type: IndexType::default(),
+            dimensionality: 3,
+            persistence_directory: None,
+            coalescing_wait_timeout: Some(Duration::from_secs(1)),
+            verifier: None,
+            guard: None,
+            eviction_signal: None,
+        }
+    }
+
+    fn namespaces(max_namespaces: usize) -> Namespaces {
+        let config = NamespaceConfig {
+            enabled: true,
+            max_namespaces,
+            ..NamespaceConfig::default()
+        };
+        Namespaces::new(settings(), config).unwrap()
+    }
+
+    fn headers(namespace: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(&NAMESPACE_HEADER, HeaderValue::from_static(namespace));
+        headers
+    }
+
+    #[test]
+    fn namespaces_should_not_share_entries() {
+        // given
+        let namespaces = namespaces(10);
+        let team_a = namespaces.resolve(&headers("team-a")).unwrap();
+        let team_b = namespaces.resolve(&headers("team-b")).unwrap();
+        team_a
+            .cache
+            .insert(
+                PartitionKey::default(),
+                vec![0.0, 1.0, 0.0],
+                CachedResponse::from_body(b"a".to_vec()),
+                None,
+            )
+            .unwrap();
+
+        // when
+        let from_b = team_b
+            .cache
+            .get_if_present(PartitionKey::default(), &[0.0, 1.0, 0.0], None)
+            .unwrap();
+        let from_a_again = namespaces
+            .resolve(&headers("team-a"))
+            .unwrap()
+            .cache
+            .get_if_present(PartitionKey::default(), &[0.0, 1.0, 0.0], None)
+            .unwrap();
+
+        // then
+        assert_eq!(from_b, None);
+        assert_eq!(from_a_again.unwrap().response.body, b"a".to_vec());
+        assert_eq!(namespaces.all().len(), 3);
+    }
+
+    #[test]
+    fn requests_without_namespace_should_use_default() {
+        // given
+        let namespaces = namespaces(10);
+
+        // when
+        let namespace = namespaces.resolve(&HeaderMap::new()).unwrap();
+
+        // then
+        assert_eq!(namespace.name, DEFAULT_NAMESPACE);
+        assert_eq!(namespaces.all().len(), 1);
+    }
+
+    #[test]
+    fn disabled_namespaces_should_ignore_header() {
+        // given
+        let namespaces = Namespaces::new(settings(), NamespaceConfig::default()).unwrap();
+
+        // when
+        let namespace = namespaces.resolve(&headers("team-a")).unwrap();
+
+        // then
+        assert_eq!(namespace.name, DEFAULT_NAMESPACE);
+    }
+
+    #[test]
+    fn should_reject_invalid_names_and_namespaces_over_limit() {
+        // given
+        let namespaces = namespaces(1);
+        namespaces.resolve(&headers("team-a")).unwrap();
+
+        // then
+        assert!(matches!(
+            namespaces.resolve(&headers("../etc")),
+            Err(NamespaceError::InvalidName(_))
+        ));
+        assert!(matches!(
+            namespaces.resolve(&headers("team-b")),
+            Err(NamespaceError::LimitReached(1))
+        ));
+        // existing namespaces are still served
+        assert!(namespaces.resolve(&headers("team-a")).is_ok());
+    }
+
+    #[test]
+    fn overrides_should_apply_to_their_namespace_only() {
+        // given
+        let config = NamespaceConfig {
+            enabled: true,
+            overrides: HashMap::from([(
+                String::from("strict"),
+                NamespaceOverrides {
+                    similarity_threshold: Some(1.0),
+                    ..NamespaceOverrides::default()
+                },
+            )]),
+            ..NamespaceConfig::default()
+        };
+        let namespaces = Namespaces::new(settings(), config).unwrap();
+        let strict = namespaces.resolve(&headers("strict")).unwrap();
+        let relaxed = namespaces.resolve(&headers("relaxed")).unwrap();
+        for namespace in [&strict, &relaxed] {
+            namespace
+                .cache
+                .insert(
+                    PartitionKey::default(),
+                    vec![0.0, 1.0, 0.0],
+                    CachedResponse::from_body(b"hit".to_vec()),
+                    None,
+                )
+                .unwrap();
+        }
+
+        // when
+        let similar = [0.0, 1.0, 0.2];
+        let from_strict = strict
+            .cache
+            .get_if_present(PartitionKey::default(), &similar, None)
+            .unwrap();
+        let from_relaxed = relaxed
+            .cache
+            .get_if_present(PartitionKey::default(), &similar, None)
+            .unwrap();
+
+        // then
+        assert_eq!(from_strict, None);
+        assert_eq!(from_relaxed.unwrap().response.body, b"hit".to_vec());
+    }
+
+    #[test]
+    fn api_key_source_should_hash_key() {
+        // given
+        let config = NamespaceConfig {
+            enabled: true,
+            source: NamespaceSource::ApiKey,
+            ..NamespaceConfig::default()
+        };
+        let namespaces = Namespaces::new(settings(), config).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("Bearer sk-secret"),
+        );
+
+        // when
+        let namespace = namespaces.resolve(&headers).unwrap();
+
+        // then
+        assert_eq!(namespace.name, api_key_namespace(b"sk-secret"));
+        assert!(namespace.name.starts_with("key-"));
+        assert!(!namespace.name.contains("secret"));
+        assert!(Arc::ptr_eq(
+            &namespace,
+            &namespaces.resolve(&headers).unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn find_hit_should_skip_candidates_rejected_by_guard() {
+        // given
+        let settings = CacheSettings {
+            guard: Some(Arc::new(HitGuard::new(GuardConfig::default()))),
+            ..settings()
+        };
+        let namespaces = Namespaces::new(settings, NamespaceConfig::default()).unwrap();
+        let namespace = namespaces.resolve(&HeaderMap::new()).unwrap();
+        for (embedding, prompt) in [
+            (vec![0.0, 1.0, 0.0], "convert 50 miles to km"),
+            (vec![0.0, 1.0, 0.1], "convert 5 miles to km"),
+        ] {
+            namespace
+                .cache
+                .insert(
+                    PartitionKey::default(),
+                    embedding,
+                    CachedResponse::from_body(prompt.as_bytes().to_vec()).with_prompt(prompt),
+                    None,
+                )
+                .unwrap();
+        }
+
+        // when
+        let hit = namespace
+            .find_hit::<CompletionError>(
+                "convert 5 miles to km",
+                PartitionKey::default(),
+                &[0.0, 1.0, 0.0],
+                None,
+            )
+            .await
+            .unwrap();
+        let negated = namespace
+            .find_hit::<CompletionError>(
+                "don't convert 5 miles to km",
+                PartitionKey::default(),
+                &[0.0, 1.0, 0.0],
+                None,
+            )
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(
+            hit.unwrap().response.body,
+            b"convert 5 miles to km".to_vec()
+        );
+        assert_eq!(negated, None);
+    }
+}
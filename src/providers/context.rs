@@ -0,0 +1,383 @@
+use std::str::FromStr;
+
+use axum::http::{HeaderMap, HeaderValue};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{ProviderError, ProviderType};
+use crate::endpoints::chat::error::CompletionError;
+use crate::utils::header_utils::{CONTEXT_STRATEGY_HEADER, PROXY_PROMPT_LOCATION_HEADER};
+use crate::utils::json_extract::{
+    Content, Turn, extract_prompt_from_path, extract_turns_from_path,
+};
+
+// Decides how much of a conversation is taken into account when looking up a cached response
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ContextStrategy {
+    // embed only the final message, earlier turns are ignored
+    #[default]
+    LastMessage,
+    // embed the final `turns` messages together with their roles
+    LastTurns {
+        turns: usize,
+    },
+    // embed only the final message, but require all earlier turns to match exactly
+    HashedHistory,
+}
+
+// What to do with requests whose embedded messages contain non-text content blocks
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonTextContent {
+    // fold the blocks into the partition key, so they have to match exactly for a hit
+    #[default]
+    Partition,
+    // forward the request upstream without reading from or writing to the cache
+    Bypass,
+}
+
+// What gets embedded for the semantic lookup, and the exact-match parts of the request (if any)
+// which have to be folded into the partition key
+#[derive(Debug, PartialEq)]
+pub struct PromptContext {
+    pub prompt: String,
+    // non-text content blocks of the embedded messages
+    pub attachments: Vec<Value>,
+    pub history: Option<Value>,
+}
+
+impl From<Content> for PromptContext {
+    fn from(content: Content) -> Self {
+        PromptContext {
+            prompt: content.text,
+            attachments: content.attachments,
+            history: None,
+        }
+    }
+}
+
+impl ContextStrategy {
+    fn validate(self) -> Result<Self, ProviderError> {
+        match self {
+            ContextStrategy::LastTurns { turns: 0 } => Err(ProviderError::InvalidContextStrategy(
+                String::from("last_turns requires at least 1 turn"),
+            )),
+            other => Ok(other),
+        }
+    }
+
+    fn apply(&self, mut turns: Vec<Turn>) -> Result<PromptContext, CompletionError> {
+        let Some(last_turn) = turns.pop() else {
+            return Err(CompletionError::InvalidRequest(String::from(
+                "Request contains no messages",
+            )));
+        };
+
+        match self {
+            ContextStrategy::LastMessage => Ok(last_turn.content.into()),
+            ContextStrategy::LastTurns { turns: count } => {
+                let skip = (turns.len() + 1).saturating_sub(*count);
+                let included: Vec<&Turn> = turns
+                    .iter()
+                    .chain(std::iter::once(&last_turn))
+                    .skip(skip)
+                    .collect();
+                let prompt = included
+                    .iter()
+                    .map(|turn| format!("{}: {}", turn.role, turn.content.text))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let attachments = included
+                    .iter()
+                    .flat_map(|turn| turn.content.attachments.iter().cloned())
+                    .collect();
+                Ok(PromptContext {
+                    prompt,
+                    attachments,
+                    history: None,
+                })
+            }
+            ContextStrategy::HashedHistory => Ok(PromptContext {
+                history: Some(serde_json::to_value(&turns)?),
+                ..last_turn.content.into()
+            }),
+        }
+    }
+}
+
+// Header values are `last_message`, `hashed_history` or `last_turns=<n>`
+impl FromStr for ContextStrategy {
+    type Err = ProviderError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let strategy = match value.trim().split_once('=') {
+            None if value.trim() == "last_message" => ContextStrategy::LastMessage,
+            None if value.trim() == "hashed_history" => ContextStrategy::HashedHistory,
+            Some(("last_turns", turns)) => {
+                let turns = turns.trim().parse().map_err(|_| {
+                    ProviderError::InvalidContextStrategy(format!("invalid turn count '{turns}'"))
+                })?;
+                ContextStrategy::LastTurns { turns }
+            }
+            _ => {
+                return Err(ProviderError::InvalidContextStrategy(format!(
+                    "unknown strategy '{value}'"
+                )));
+            }
+        };
+        strategy.validate()
+    }
+}
+
+// Context strategy used per provider when a request doesn't set one through the header
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ContextConfig {
+    #[serde(default)]
+    pub openai: ContextStrategy,
+    #[serde(default)]
+    pub anthropic: ContextStrategy,
+}
+
+impl ContextConfig {
+    pub fn validate(self) -> Result<Self, ProviderError> {
+        self.openai.validate()?;
+        self.anthropic.validate()?;
+        Ok(self)
+    }
+
+    fn strategy(
+        &self,
+        provider: &ProviderType,
+        maybe_strategy_header: Option<&HeaderValue>,
+    ) -> Result<ContextStrategy, ProviderError> {
+        if let Some(strategy_header) = maybe_strategy_header {
+            return strategy_header.to_str()?.parse();
+        }
+        match provider {
+            ProviderType::OpenAI => Ok(self.openai),
+            ProviderType::Anthropic => Ok(self.anthropic),
+            ProviderType::Generic => Ok(ContextStrategy::LastMessage),
+        }
+    }
+}
+
+// Extracts what should be embedded for a request. A custom prompt location always takes precedence,
+// it points at exactly the value to embed, so context strategies only apply to the default layouts.
+pub fn extract_prompt_context(
+    provider: &ProviderType,
+    headers: &HeaderMap,
+    request_body: &Value,
+    config: &ContextConfig,
+) -> Result<PromptContext, CompletionError> {
+    let maybe_prompt_location = headers.get(&PROXY_PROMPT_LOCATION_HEADER);
+    let strategy = config.strategy(provider, headers.get(&CONTEXT_STRATEGY_HEADER))?;
+
+    match (strategy, provider.messages_json_path()) {
+        (ContextStrategy::LastMessage, _) => {}
+        (_, None) => {
+            return Err(ProviderError::InvalidContextStrategy(String::from(
+                "context strategies are not supported for the generic provider",
+            ))
+            .into());
+        }
+        (strategy, Some(messages_path)) if maybe_prompt_location.is_none() => {
+            let turns = extract_turns_from_path(request_body, messages_path)?;
+            return strategy.apply(turns);
+        }
+        _ => {}
+    }
+
+    let prompt = extract_prompt_from_path(
+        request_body,
+        provider.prompt_json_path(maybe_prompt_location)?,
+    )?;
+    Ok(prompt.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+    use serde_json::{Value, json};
+
+    use super::{ContextConfig, ContextStrategy, PromptContext, extract_prompt_context};
+    use crate::providers::ProviderType;
+    use crate::utils::header_utils::CONTEXT_STRATEGY_HEADER;
+
+    fn conversation() -> Value {
+        json!({
+            "messages": [
+                {"role": "user", "content": "What is the capital of Spain?"},
+                {"role": "assistant", "content": "Madrid"},
+                {"role": "user", "content": "and what about in France?"}
+            ]
+        })
+    }
+
+    #[test]
+    fn should_parse_strategy_from_header_value() {
+        assert_eq!(
+            "last_message".parse::<ContextStrategy>().unwrap(),
+            ContextStrategy::LastMessage
+        );
+        assert_eq!(
+            "hashed_history".parse::<ContextStrategy>().unwrap(),
+            ContextStrategy::HashedHistory
+        );
+        assert_eq!(
+            "last_turns=3".parse::<ContextStrategy>().unwrap(),
+            ContextStrategy::LastTurns { turns: 3 }
+        );
+        assert!("last_turns=0".parse::<ContextStrategy>().is_err());
+        assert!("everything".parse::<ContextStrategy>().is_err());
+    }
+
+    #[test]
+    fn last_message_should_embed_final_message_only() {
+        // when
+        let context = extract_prompt_context(
+            &ProviderType::OpenAI,
+            &HeaderMap::new(),
+            &conversation(),
+            &ContextConfig::default(),
+        )
+        .unwrap();
+
+        // then
+        assert_eq!(
+            context,
+            PromptContext {
+                prompt: String::from("and what about in France?"),
+                attachments: vec![],
+                history: None,
+            }
+        );
+    }
+
+    #[test]
+    fn last_turns_should_embed_turns_with_roles() {
+        // given
+        let config = ContextConfig {
+            openai: ContextStrategy::LastTurns { turns: 2 },
+            ..ContextConfig::default()
+        };
+
+        // when
+        let context = extract_prompt_context(
+            &ProviderType::OpenAI,
+            &HeaderMap::new(),
+            &conversation(),
+            &config,
+        )
+        .unwrap();
+
+        // then
+        assert_eq!(
+            context.prompt,
+            "assistant: Madrid\nuser: and what about in France?"
+        );
+        assert_eq!(context.history, None);
+    }
+
+    #[test]
+    fn hashed_history_should_return_prior_turns() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            &CONTEXT_STRATEGY_HEADER,
+            HeaderValue::from_static("hashed_history"),
+        );
+
+        // when
+        let context = extract_prompt_context(
+            &ProviderType::Anthropic,
+            &headers,
+            &conversation(),
+            &ContextConfig::default(),
+        )
+        .unwrap();
+
+        // then
+        assert_eq!(context.prompt, "and what about in France?");
+        assert_eq!(
+            context.history,
+            Some(json!([
+                {"role": "user", "text": "What is the capital of Spain?"},
+                {"role": "assistant", "text": "Madrid"}
+            ]))
+        );
+    }
+
+    #[test]
+    fn header_should_override_configured_strategy() {
+        // given
+        let config = ContextConfig {
+            openai: ContextStrategy::LastTurns { turns: 3 },
+            ..ContextConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            &CONTEXT_STRATEGY_HEADER,
+            HeaderValue::from_static("last_message"),
+        );
+
+        // when
+        let context =
+            extract_prompt_context(&ProviderType::OpenAI, &headers, &conversation(), &config)
+                .unwrap();
+
+        // then
+        assert_eq!(context.prompt, "and what about in France?");
+    }
+
+    #[test]
+    fn generic_provider_should_reject_context_strategies() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            &CONTEXT_STRATEGY_HEADER,
+            HeaderValue::from_static("hashed_history"),
+        );
+
+        // when
+        let result = extract_prompt_context(
+            &ProviderType::Generic,
+            &headers,
+            &conversation(),
+            &ContextConfig::default(),
+        );
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn last_turns_should_collect_attachments_of_included_turns() {
+        // given
+        let image =
+            json!({"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}});
+        let body = json!({
+            "messages": [
+                {"role": "user", "content": [{"type": "text", "text": "What animal is this?"}, image.clone()]},
+                {"role": "assistant", "content": "A cat"},
+                {"role": "user", "content": "What colour is it?"}
+            ]
+        });
+        let config = ContextConfig {
+            openai: ContextStrategy::LastTurns { turns: 3 },
+            ..ContextConfig::default()
+        };
+
+        // when
+        let context =
+            extract_prompt_context(&ProviderType::OpenAI, &HeaderMap::new(), &body, &config)
+                .unwrap();
+
+        // then
+        assert_eq!(
+            context.prompt,
+            "user: What animal is this?\nassistant: A cat\nuser: What colour is it?"
+        );
+        assert_eq!(context.attachments, vec![image]);
+    }
+}
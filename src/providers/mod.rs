@@ -1,3 +1,5 @@
+pub mod context;
+
 use std::sync::LazyLock;
 
 use axum::http::HeaderValue;
@@ -23,6 +25,10 @@ pub static OPEN_AI_REST_PATH: &str = "/chat/completions";
 static ANTHROPIC_PROMPT_PATH: &str = "$.messages[-1].content";
 static OPEN_AI_PROMPT_PATH: &str = "$.messages[-1].content";
 
+// JSON MESSAGES PATH
+static ANTHROPIC_MESSAGES_PATH: &str = "$.messages";
+static OPEN_AI_MESSAGES_PATH: &str = "$.messages";
+
 #[derive(Error, Debug)]
 pub enum ProviderError {
     #[error("{0}")]
@@ -31,9 +37,11 @@ pub enum ProviderError {
     UrlParsingHeaderError(#[from] ParseError),
     #[error("Invalid generic provider: {0}")]
     InvalidGenericProvider(String),
+    #[error("Invalid context strategy: {0}")]
+    InvalidContextStrategy(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ProviderType {
     Anthropic,
     OpenAI,
@@ -67,6 +75,15 @@ impl ProviderType {
         }
     }
 
+    // location of the conversation history, None if the request format of the provider is unknown
+    pub fn messages_json_path(&self) -> Option<&'static str> {
+        match self {
+            ProviderType::Anthropic => Some(ANTHROPIC_MESSAGES_PATH),
+            ProviderType::OpenAI => Some(OPEN_AI_MESSAGES_PATH),
+            ProviderType::Generic => None,
+        }
+    }
+
     pub fn url(
         &self,
         maybe_upstream_url: Option<&HeaderValue>,
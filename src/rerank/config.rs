@@ -0,0 +1,85 @@
+use serde::Deserialize;
+
+use crate::rerank::cross_encoder::{CrossEncoderConfig, CrossEncoderReranker};
+use crate::rerank::error::RerankError;
+use crate::rerank::lexical::{LexicalConfig, LexicalReranker};
+use crate::rerank::reranker::Reranker;
+
+// How candidate prompts are scored against the prompt of a request
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RerankerConfig {
+    // by word overlap, and matching numbers and names
+    Lexical(LexicalConfig),
+    // in process, with a fastembed cross-encoder model
+    CrossEncoder(CrossEncoderConfig),
+}
+
+impl Default for RerankerConfig {
+    fn default() -> Self {
+        RerankerConfig::Lexical(LexicalConfig::default())
+    }
+}
+
+impl RerankerConfig {
+    pub fn new_reranker(self) -> Box<dyn Reranker> {
+        match self {
+            RerankerConfig::Lexical(config) => Box::new(LexicalReranker::new(config)),
+            RerankerConfig::CrossEncoder(config) => Box::new(CrossEncoderReranker::new(&config)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RerankConfig {
+    pub enabled: bool,
+    // candidates fetched from the semantic index and scored, most similar first
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    // lowest score a candidate needs to be served
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+    #[serde(default)]
+    pub reranker: RerankerConfig,
+}
+
+fn default_top_k() -> usize {
+    5
+}
+
+fn default_min_score() -> f32 {
+    0.5
+}
+
+impl Default for RerankConfig {
+    fn default() -> Self {
+        RerankConfig {
+            enabled: false,
+            top_k: default_top_k(),
+            min_score: default_min_score(),
+            reranker: RerankerConfig::default(),
+        }
+    }
+}
+
+impl RerankConfig {
+    pub fn validate(self) -> Result<Self, RerankError> {
+        if self.top_k == 0 {
+            return Err(RerankError::SetupError(String::from(
+                "top_k must be positive",
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.min_score) {
+            return Err(RerankError::SetupError(String::from(
+                "min_score must be between 0.0 and 1.0",
+            )));
+        }
+        let reranker = match self.reranker {
+            RerankerConfig::CrossEncoder(config) => {
+                RerankerConfig::CrossEncoder(config.validate()?)
+            }
+            lexical => lexical,
+        };
+        Ok(Self { reranker, ..self })
+    }
+}
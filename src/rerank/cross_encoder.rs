@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use fastembed::{
+    RerankInitOptions, RerankInitOptionsUserDefined, RerankerModel, TextRerank, TokenizerFiles,
+    UserDefinedRerankingModel,
+};
+use serde::Deserialize;
+use tracing::{error, info};
+
+use crate::rerank::error::RerankError;
+use crate::rerank::reranker::Reranker;
+
+const DEFAULT_MODEL: &str = "BGERerankerBase";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CrossEncoderConfig {
+    // named as in fastembed's RerankerModel, e.g. BGERerankerBase or JINARerankerV1TurboEn
+    #[serde(default = "default_model")]
+    pub model: String,
+    // directory holding the model's files, for hosts which can't download them
+    pub model_directory: Option<String>,
+}
+
+fn default_model() -> String {
+    String::from(DEFAULT_MODEL)
+}
+
+impl Default for CrossEncoderConfig {
+    fn default() -> Self {
+        CrossEncoderConfig {
+            model: default_model(),
+            model_directory: None,
+        }
+    }
+}
+
+impl CrossEncoderConfig {
+    pub fn validate(self) -> Result<Self, RerankError> {
+        self.reranker_model()?;
+        Ok(self)
+    }
+
+    fn reranker_model(&self) -> Result<RerankerModel, RerankError> {
+        let models = TextRerank::list_supported_models();
+        models
+            .iter()
+            .find(|info| format!("{:?}", info.model).eq_ignore_ascii_case(&self.model))
+            .or_else(|| {
+                models
+                    .iter()
+                    .find(|info| info.model_code.eq_ignore_ascii_case(&self.model))
+            })
+            .map(|info| info.model.clone())
+            .ok_or_else(|| {
+                RerankError::SetupError(format!("Unknown reranker model '{}'", self.model))
+            })
+    }
+}
+
+// Scores candidates with a cross-encoder, which reads the prompt and a candidate together rather
+// than comparing embeddings, so it notices when similar wording asks a different question.
+// The model runs on the blocking thread pool.
+pub struct CrossEncoderReranker {
+    model: Arc<TextRerank>,
+}
+
+impl CrossEncoderReranker {
+    pub fn new(config: &CrossEncoderConfig) -> Self {
+        Self::try_new(config).unwrap_or_else(|err| {
+            error!(error = ?err);
+            panic!("failed to init reranker")
+        })
+    }
+
+    fn try_new(config: &CrossEncoderConfig) -> Result<Self, RerankError> {
+        let model = config.reranker_model()?;
+        let text_rerank = match &config.model_directory {
+            Some(directory) => {
+                info!("Loading reranker model {:?} from {}", model, directory);
+                TextRerank::try_new_from_user_defined(
+                    load_local_model(&model, Path::new(directory))?,
+                    RerankInitOptionsUserDefined::default(),
+                )
+            }
+            None => {
+                TextRerank::try_new(RerankInitOptions::new(model).with_show_download_progress(true))
+            }
+        }
+        .map_err(|err| RerankError::SetupError(err.to_string()))?;
+        Ok(Self {
+            model: Arc::new(text_rerank),
+        })
+    }
+}
+
+// Reads the files of `model` from `directory`, laid out as in the model's huggingface repository
+fn load_local_model(
+    model: &RerankerModel,
+    directory: &Path,
+) -> Result<UserDefinedRerankingModel, RerankError> {
+    let read = |file_name: &str| {
+        let path = directory.join(file_name);
+        fs::read(&path).map_err(|err| {
+            RerankError::SetupError(format!("Failed to read {}: {err}", path.display()))
+        })
+    };
+    let model_info = TextRerank::get_model_info(model);
+    let tokenizer_files = TokenizerFiles {
+        tokenizer_file: read("tokenizer.json")?,
+        config_file: read("config.json")?,
+        special_tokens_map_file: read("special_tokens_map.json")?,
+        tokenizer_config_file: read("tokenizer_config.json")?,
+    };
+    Ok(UserDefinedRerankingModel::new(
+        read(&model_info.model_file)?,
+        tokenizer_files,
+    ))
+}
+
+// cross-encoders return logits, the sigmoid makes them comparable with a threshold in [0, 1]
+fn sigmoid(logit: f32) -> f32 {
+    1.0 / (1.0 + (-logit).exp())
+}
+
+#[async_trait]
+impl Reranker for CrossEncoderReranker {
+    async fn score(&self, prompt: &str, candidates: &[String]) -> Result<Vec<f32>, RerankError> {
+        let model = self.model.clone();
+        let prompt = String::from(prompt);
+        let candidates = candidates.to_vec();
+        tokio::task::spawn_blocking(move || {
+            let results = model
+                .rerank(&prompt, candidates.iter().collect(), false, None)
+                .map_err(|err| RerankError::ScoringError(err.to_string()))?;
+            // results are sorted by score, put them back in the order of the candidates
+            let mut scores = vec![0.0; candidates.len()];
+            for result in results {
+                scores[result.index] = sigmoid(result.score);
+            }
+            Ok(scores)
+        })
+        .await
+        .map_err(|err| RerankError::ScoringError(err.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fastembed::RerankerModel;
+
+    use super::{CrossEncoderConfig, sigmoid};
+
+    #[test]
+    fn should_resolve_model_by_variant_name_or_model_code() {
+        assert_eq!(
+            CrossEncoderConfig::default().reranker_model().unwrap(),
+            RerankerModel::BGERerankerBase
+        );
+        let config = CrossEncoderConfig {
+            model: String::from("jinaai/jina-reranker-v1-turbo-en"),
+            model_directory: None,
+        };
+        assert_eq!(
+            config.reranker_model().unwrap(),
+            RerankerModel::JINARerankerV1TurboEn
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_model() {
+        let config = CrossEncoderConfig {
+            model: String::from("bm25"),
+            model_directory: None,
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn sigmoid_should_map_logits_into_unit_interval() {
+        assert_eq!(sigmoid(0.0), 0.5);
+        assert!(sigmoid(8.0) > 0.99);
+        assert!(sigmoid(-8.0) < 0.01);
+    }
+}
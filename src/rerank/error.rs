@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RerankError {
+    #[error("Failed to score candidates: {0}")]
+    ScoringError(String),
+    #[error("Failed to set up reranker: {0}")]
+    SetupError(String),
+}
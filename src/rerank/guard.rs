@@ -0,0 +1,460 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::cache::cache::CacheHit;
+use crate::cache::cached_response::CachedResponse;
+use crate::metrics::metrics::GUARD_REJECTIONS;
+use crate::rerank::error::RerankError;
+
+// contractions are listed without their apostrophe, which tokens are stripped of
+const NEGATIONS: [&str; 28] = [
+    "not", "no", "never", "none", "nobody", "nothing", "nowhere", "neither", "nor", "without",
+    "cannot", "cant", "dont", "doesnt", "didnt", "isnt", "arent", "wasnt", "werent", "wont",
+    "wouldnt", "couldnt", "shouldnt", "hasnt", "havent", "hadnt", "mustnt", "aint",
+];
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+const RELATIVE_DAYS: [&str; 11] = [
+    "today",
+    "tonight",
+    "tomorrow",
+    "yesterday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuardConfig {
+    pub enabled: bool,
+    // candidates fetched from the semantic index, unless a reranker sets how many
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    #[serde(default = "default_true")]
+    pub compare_numbers: bool,
+    #[serde(default = "default_true")]
+    pub compare_dates: bool,
+    #[serde(default = "default_true")]
+    pub compare_negations: bool,
+    #[serde(default = "default_true")]
+    pub compare_quotes: bool,
+}
+
+fn default_top_k() -> usize {
+    3
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        GuardConfig {
+            enabled: false,
+            top_k: default_top_k(),
+            compare_numbers: true,
+            compare_dates: true,
+            compare_negations: true,
+            compare_quotes: true,
+        }
+    }
+}
+
+impl GuardConfig {
+    pub fn validate(self) -> Result<Self, RerankError> {
+        if self.top_k == 0 {
+            return Err(RerankError::SetupError(String::from(
+                "guard top_k must be positive",
+            )));
+        }
+        Ok(self)
+    }
+}
+
+// Why a candidate was rejected, the label of the guard rejections metric
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mismatch {
+    Numbers,
+    Dates,
+    Negation,
+    Quotes,
+    // entries cached without their prompt can't be compared
+    MissingPrompt,
+}
+
+impl Mismatch {
+    fn label(&self) -> &'static str {
+        match self {
+            Mismatch::Numbers => "numbers",
+            Mismatch::Dates => "dates",
+            Mismatch::Negation => "negation",
+            Mismatch::Quotes => "quotes",
+            Mismatch::MissingPrompt => "missing_prompt",
+        }
+    }
+}
+
+// The parts of a prompt which change its meaning without moving its embedding much
+struct Features {
+    // normalized, e.g. "1,000.0" is "1000"
+    numbers: HashSet<String>,
+    // numeric dates as written, month names and relative days
+    dates: HashSet<String>,
+    negations: usize,
+    // as written, between double quotes, backticks or single quotes
+    quotes: HashSet<String>,
+}
+
+// Rejects candidates whose prompt differs from the request's in a number, date, negation or
+// quoted string. Embeddings place such prompts close together, e.g. "convert 5 miles to km" and
+// "convert 50 miles to km", or "is X safe" and "is X not safe", but their answers differ.
+pub struct HitGuard {
+    config: GuardConfig,
+    pub top_k: usize,
+}
+
+impl HitGuard {
+    pub fn new(config: GuardConfig) -> Self {
+        HitGuard {
+            top_k: config.top_k,
+            config,
+        }
+    }
+
+    // Keeps the candidates whose prompt matches `prompt`, in their order
+    pub fn retain(
+        &self,
+        prompt: &str,
+        candidates: Vec<CacheHit<CachedResponse>>,
+    ) -> Vec<CacheHit<CachedResponse>> {
+        if candidates.is_empty() {
+            return candidates;
+        }
+        let prompt_features = features(prompt);
+        candidates
+            .into_iter()
+            .filter(|candidate| {
+                let mismatch = match &candidate.response.prompt {
+                    Some(candidate_prompt) => {
+                        self.mismatch(&prompt_features, &features(candidate_prompt))
+                    }
+                    None => Some(Mismatch::MissingPrompt),
+                };
+                let Some(mismatch) = mismatch else {
+                    return true;
+                };
+                debug!(
+                    "Guard rejected cache candidate {} for mismatched {}",
+                    candidate.entry_id,
+                    mismatch.label()
+                );
+                GUARD_REJECTIONS
+                    .with_label_values(&[mismatch.label()])
+                    .inc();
+                false
+            })
+            .collect()
+    }
+
+    // Whether the response to `other_prompt` could be served for `prompt`
+    pub fn matches(&self, prompt: &str, other_prompt: &str) -> bool {
+        self.mismatch(&features(prompt), &features(other_prompt))
+            .is_none()
+    }
+
+    fn mismatch(&self, prompt: &Features, candidate: &Features) -> Option<Mismatch> {
+        if self.config.compare_numbers && prompt.numbers != candidate.numbers {
+            return Some(Mismatch::Numbers);
+        }
+        if self.config.compare_dates && prompt.dates != candidate.dates {
+            return Some(Mismatch::Dates);
+        }
+        if self.config.compare_negations && prompt.negations != candidate.negations {
+            return Some(Mismatch::Negation);
+        }
+        if self.config.compare_quotes && prompt.quotes != candidate.quotes {
+            return Some(Mismatch::Quotes);
+        }
+        None
+    }
+}
+
+fn features(text: &str) -> Features {
+    let mut numbers = HashSet::new();
+    let mut dates = HashSet::new();
+    let mut negations = 0;
+
+    let tokens: Vec<String> = text
+        .split_whitespace()
+        .map(|token| {
+            token
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .replace(['\'', '’'], "")
+                .to_lowercase()
+        })
+        .filter(|token| !token.is_empty())
+        .collect();
+    let has_number = |index: Option<usize>| {
+        index
+            .and_then(|index| tokens.get(index))
+            .is_some_and(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+    };
+    for (index, token) in tokens.iter().enumerate() {
+        if NEGATIONS.contains(&token.as_str()) {
+            negations += 1;
+        } else if is_numeric_date(token) || RELATIVE_DAYS.contains(&token.as_str()) {
+            dates.insert(token.clone());
+        } else if let Some(month) = month(token) {
+            // "may" is a month only next to a day or year, as in "may 5" or "5 may"
+            if month != "may" || has_number(index.checked_sub(1)) || has_number(Some(index + 1)) {
+                dates.insert(String::from(month));
+            }
+        } else {
+            numbers.extend(numbers_in(token));
+        }
+    }
+
+    Features {
+        numbers,
+        dates,
+        negations,
+        quotes: quoted(text),
+    }
+}
+
+// 2024-03-05, 05/03/2024 or 5.3.2024
+fn is_numeric_date(token: &str) -> bool {
+    ['-', '/', '.'].into_iter().any(|separator| {
+        let parts: Vec<&str> = token.split(separator).collect();
+        parts.len() == 3
+            && parts
+                .iter()
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+            && parts.iter().any(|part| part.len() == 4 || part.len() == 2)
+            && parts.iter().all(|part| part.len() <= 4)
+    })
+}
+
+// the full name of a month, also from abbreviations such as "jan" or "sept"
+fn month(token: &str) -> Option<&'static str> {
+    MONTHS
+        .iter()
+        .copied()
+        .find(|month| token == *month || (token.len() >= 3 && month.starts_with(token)))
+}
+
+// The numbers in a token, "5km" has 5 and "1,000.50" has 1000.5
+fn numbers_in(token: &str) -> Vec<String> {
+    token
+        .split(|c: char| !c.is_ascii_digit() && c != '.' && c != ',')
+        .flat_map(|run| {
+            let groups: Vec<&str> = run.split(',').collect();
+            // commas separate thousands if every group after the first has three digits
+            let thousands = groups.len() > 1
+                && groups[1..]
+                    .iter()
+                    .all(|group| group.split('.').next().is_some_and(|g| g.len() == 3));
+            if thousands {
+                vec![groups.concat()]
+            } else {
+                groups.into_iter().map(String::from).collect()
+            }
+        })
+        .filter_map(|number| number.trim_matches('.').parse::<f64>().ok())
+        .map(|number| number.to_string())
+        .collect()
+}
+
+// Strings between double quotes, backticks, curly quotes, or single quotes which aren't apostrophes
+fn quoted(text: &str) -> HashSet<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let is_word = |index: usize| chars.get(index).is_some_and(|c| c.is_alphanumeric());
+    let mut quotes = HashSet::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let close = match chars[index] {
+            '"' => Some('"'),
+            '`' => Some('`'),
+            '“' => Some('”'),
+            '‘' => Some('’'),
+            '\'' if index == 0 || !is_word(index - 1) => Some('\''),
+            _ => None,
+        };
+        let end = close.and_then(|close| {
+            (index + 1..chars.len())
+                .find(|&end| chars[end] == close && (close != '\'' || !is_word(end + 1)))
+        });
+        match end {
+            Some(end) => {
+                let quote: String = chars[index + 1..end].iter().collect();
+                let quote = quote.trim();
+                if !quote.is_empty() {
+                    quotes.insert(String::from(quote));
+                }
+                index = end + 1;
+            }
+            None => index += 1,
+        }
+    }
+    quotes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuardConfig, HitGuard, Mismatch, features, quoted};
+    use crate::cache::cache::CacheHit;
+    use crate::cache::cached_response::CachedResponse;
+
+    fn mismatch(config: GuardConfig, prompt: &str, candidate: &str) -> Option<Mismatch> {
+        HitGuard::new(config).mismatch(&features(prompt), &features(candidate))
+    }
+
+    fn guarded(prompt: &str, candidate: &str) -> Option<Mismatch> {
+        mismatch(GuardConfig::default(), prompt, candidate)
+    }
+
+    #[test]
+    fn should_reject_mismatched_numbers() {
+        assert_eq!(
+            guarded("convert 5 miles to km", "convert 50 miles to km"),
+            Some(Mismatch::Numbers)
+        );
+        assert_eq!(
+            guarded("Convert 5 miles to km.", "convert 5.0 miles into km"),
+            None
+        );
+        assert_eq!(guarded("what is 1,000 + 5km", "what's 1000 + 5 km?"), None);
+        assert_eq!(
+            guarded("what is 1,5 + 2", "what is 1.5 + 2"),
+            Some(Mismatch::Numbers)
+        );
+    }
+
+    #[test]
+    fn should_reject_mismatched_dates() {
+        assert_eq!(
+            guarded("events on 2024-03-05", "events on 2024-03-06"),
+            Some(Mismatch::Dates)
+        );
+        assert_eq!(
+            guarded("weather in Paris today", "weather in Paris tomorrow"),
+            Some(Mismatch::Dates)
+        );
+        assert_eq!(guarded("holidays in Jan", "holidays in January"), None);
+        // "may" is only a month next to a number
+        assert_eq!(
+            guarded("may I ask a question", "can I ask a question"),
+            None
+        );
+        assert_eq!(
+            guarded("what happened on 5 May", "what happened on 5 June"),
+            Some(Mismatch::Dates)
+        );
+    }
+
+    #[test]
+    fn should_reject_mismatched_negations() {
+        assert_eq!(
+            guarded("is aspirin safe for dogs", "is aspirin not safe for dogs"),
+            Some(Mismatch::Negation)
+        );
+        assert_eq!(
+            guarded(
+                "why isn't my code compiling",
+                "why is my code not compiling"
+            ),
+            None
+        );
+        assert_eq!(
+            guarded("why doesn’t it work", "why does it work"),
+            Some(Mismatch::Negation)
+        );
+    }
+
+    #[test]
+    fn should_reject_mismatched_quotes() {
+        assert_eq!(
+            guarded(
+                "translate \"good morning\" to French",
+                "translate \"good night\" to French"
+            ),
+            Some(Mismatch::Quotes)
+        );
+        assert_eq!(
+            guarded("what does `ls -a` do", "what does `ls -a` do?"),
+            None
+        );
+        assert_eq!(
+            quoted("it's 'quoted' and isn't 'this one's'"),
+            ["quoted", "this one's"].map(String::from).into()
+        );
+    }
+
+    #[test]
+    fn should_only_compare_configured_features() {
+        // given
+        let config = GuardConfig {
+            compare_numbers: false,
+            compare_negations: false,
+            ..GuardConfig::default()
+        };
+
+        // when
+        let numbers = mismatch(config.clone(), "convert 5 miles", "convert 50 miles");
+        let negations = mismatch(config, "is it safe", "is it not safe");
+
+        // then
+        assert_eq!(numbers, None);
+        assert_eq!(negations, None);
+    }
+
+    #[test]
+    fn retain_should_keep_matching_candidates_in_order() {
+        // given
+        let candidate = |entry_id: u64, prompt: Option<&str>| {
+            let response = CachedResponse::from_body(b"{}".to_vec());
+            CacheHit {
+                response: match prompt {
+                    Some(prompt) => response.with_prompt(prompt),
+                    None => response,
+                },
+                entry_id,
+                similarity: 0.95,
+                similarity_threshold: 0.9,
+            }
+        };
+        let candidates = vec![
+            candidate(0, Some("convert 50 miles to km")),
+            candidate(1, None),
+            candidate(2, Some("convert 5 miles into km")),
+            candidate(3, Some("Convert 5 miles to km")),
+        ];
+
+        // when
+        let retained =
+            HitGuard::new(GuardConfig::default()).retain("convert 5 miles to km", candidates);
+
+        // then
+        let entry_ids: Vec<u64> = retained.iter().map(|hit| hit.entry_id).collect();
+        assert_eq!(entry_ids, vec![2, 3]);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::rerank::error::RerankError;
+use crate::rerank::reranker::Reranker;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LexicalConfig {
+    // reject candidates with a number the prompt doesn't have, or the other way around
+    #[serde(default = "default_true")]
+    pub compare_numbers: bool,
+    // same for capitalized words within a sentence, which are likely names
+    #[serde(default = "default_true")]
+    pub compare_names: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for LexicalConfig {
+    fn default() -> Self {
+        LexicalConfig {
+            compare_numbers: true,
+            compare_names: true,
+        }
+    }
+}
+
+// Scores candidates by the overlap of their words with the prompt's. Cheap, and catches the short
+// prompts embedding models place close together, such as "yes" and "no", or questions which
+// only differ in a number or a name.
+pub struct LexicalReranker {
+    config: LexicalConfig,
+}
+
+struct Tokens {
+    // all words, lowercased
+    words: HashSet<String>,
+    // numbers and names, lowercased, which have to appear in the other prompt as well
+    key_words: HashSet<String>,
+}
+
+impl LexicalReranker {
+    pub fn new(config: LexicalConfig) -> Self {
+        LexicalReranker { config }
+    }
+
+    fn tokenize(&self, text: &str) -> Tokens {
+        let mut words = HashSet::new();
+        let mut key_words = HashSet::new();
+        let mut sentence_start = true;
+        for word in text.split(|c: char| !c.is_alphanumeric() && c != '.' && c != '?' && c != '!') {
+            let ends_sentence = word.ends_with(['.', '?', '!']);
+            let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+            if word.is_empty() {
+                sentence_start |= ends_sentence;
+                continue;
+            }
+            let is_number = word.chars().any(|c| c.is_ascii_digit());
+            let is_name =
+                !sentence_start && word.starts_with(char::is_uppercase) && word.chars().count() > 1;
+            let word = word.to_lowercase();
+            if (is_number && self.config.compare_numbers) || (is_name && self.config.compare_names)
+            {
+                key_words.insert(word.clone());
+            }
+            words.insert(word);
+            sentence_start = ends_sentence;
+        }
+        Tokens { words, key_words }
+    }
+
+    fn score_tokens(prompt: &Tokens, candidate: &Tokens) -> f32 {
+        let mismatched = |a: &Tokens, b: &Tokens| a.key_words.iter().any(|w| !b.words.contains(w));
+        if mismatched(prompt, candidate) || mismatched(candidate, prompt) {
+            return 0.0;
+        }
+        let union = prompt.words.union(&candidate.words).count();
+        if union == 0 {
+            return 1.0;
+        }
+        prompt.words.intersection(&candidate.words).count() as f32 / union as f32
+    }
+}
+
+#[async_trait]
+impl Reranker for LexicalReranker {
+    async fn score(&self, prompt: &str, candidates: &[String]) -> Result<Vec<f32>, RerankError> {
+        let prompt = self.tokenize(prompt);
+        Ok(candidates
+            .iter()
+            .map(|candidate| Self::score_tokens(&prompt, &self.tokenize(candidate)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LexicalConfig, LexicalReranker};
+    use crate::rerank::reranker::Reranker;
+
+    async fn score(config: LexicalConfig, prompt: &str, candidate: &str) -> f32 {
+        LexicalReranker::new(config)
+            .score(prompt, &[String::from(candidate)])
+            .await
+            .unwrap()[0]
+    }
+
+    #[tokio::test]
+    async fn should_score_word_overlap() {
+        assert_eq!(
+            score(
+                LexicalConfig::default(),
+                "What is Semcache?",
+                "what is semcache"
+            )
+            .await,
+            1.0
+        );
+        assert_eq!(
+            score(
+                LexicalConfig::default(),
+                "how do I sort a list",
+                "how do I sort a vec"
+            )
+            .await,
+            5.0 / 7.0
+        );
+        assert_eq!(score(LexicalConfig::default(), "yes", "no").await, 0.0);
+    }
+
+    #[tokio::test]
+    async fn should_reject_mismatched_numbers_and_names() {
+        // when
+        let numbers = score(
+            LexicalConfig::default(),
+            "What is 12 times 4?",
+            "What is 12 times 5?",
+        )
+        .await;
+        let names = score(
+            LexicalConfig::default(),
+            "What is the capital of France?",
+            "What is the capital of Spain?",
+        )
+        .await;
+        let lowercase_name = score(
+            LexicalConfig::default(),
+            "What is the capital of France?",
+            "what is the capital of france?",
+        )
+        .await;
+
+        // then
+        assert_eq!(numbers, 0.0);
+        assert_eq!(names, 0.0);
+        assert_eq!(lowercase_name, 1.0);
+    }
+
+    #[tokio::test]
+    async fn should_only_compare_key_words_when_configured() {
+        // given
+        let config = LexicalConfig {
+            compare_numbers: false,
+            compare_names: false,
+        };
+
+        // when
+        let score = score(config, "What is 12 times 4?", "What is 12 times 5?").await;
+
+        // then
+        assert_eq!(score, 4.0 / 6.0);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod config;
+pub mod cross_encoder;
+pub mod error;
+pub mod guard;
+pub mod lexical;
+pub mod reranker;
+pub mod verifier;
@@ -0,0 +1,12 @@
+use async_trait::async_trait;
+use mockall::automock;
+
+use crate::rerank::error::RerankError;
+
+#[automock]
+#[async_trait]
+pub trait Reranker: Send + Sync {
+    // How well each candidate prompt matches `prompt`, in the order of `candidates`. Scores are
+    // between 0 (unrelated) and 1 (the same question).
+    async fn score(&self, prompt: &str, candidates: &[String]) -> Result<Vec<f32>, RerankError>;
+}
@@ -0,0 +1,180 @@
+use tracing::debug;
+
+use crate::cache::cache::CacheHit;
+use crate::cache::cached_response::CachedResponse;
+use crate::metrics::metrics::RERANK_REJECTIONS;
+use crate::rerank::config::RerankConfig;
+use crate::rerank::error::RerankError;
+use crate::rerank::reranker::Reranker;
+
+// Checks the nearest entries of a lookup with a reranker before one is served, as the nearest
+// vector alone gives false positives for prompts which embed closely but ask different things
+pub struct HitVerifier {
+    reranker: Box<dyn Reranker>,
+    // how many candidates a lookup fetches
+    pub top_k: usize,
+    min_score: f32,
+}
+
+impl HitVerifier {
+    pub fn new(config: RerankConfig) -> Self {
+        HitVerifier {
+            reranker: config.reranker.new_reranker(),
+            top_k: config.top_k,
+            min_score: config.min_score,
+        }
+    }
+
+    // The candidate the reranker scores highest, if any reaches the minimum score. Candidates are
+    // expected most similar first, which wins ties. Entries cached without their prompt can't be
+    // verified and are never served.
+    pub async fn select(
+        &self,
+        prompt: &str,
+        candidates: Vec<CacheHit<CachedResponse>>,
+    ) -> Result<Option<CacheHit<CachedResponse>>, RerankError> {
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let (candidates, candidate_prompts): (Vec<_>, Vec<_>) = candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let candidate_prompt = candidate.response.prompt.clone()?;
+                Some((candidate, candidate_prompt))
+            })
+            .unzip();
+        let scores = if candidates.is_empty() {
+            vec![]
+        } else {
+            self.reranker.score(prompt, &candidate_prompts).await?
+        };
+
+        let mut best: Option<(usize, f32)> = None;
+        for (index, score) in scores.into_iter().enumerate() {
+            if score >= self.min_score && best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((index, score));
+            }
+        }
+        let Some((index, score)) = best else {
+            debug!("Reranker rejected all cache candidates");
+            RERANK_REJECTIONS.inc();
+            return Ok(None);
+        };
+        debug!(
+            "Reranker accepted cache candidate {} with score {}",
+            index, score
+        );
+        Ok(candidates.into_iter().nth(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::{always, eq};
+
+    use super::HitVerifier;
+    use crate::cache::cache::CacheHit;
+    use crate::cache::cached_response::CachedResponse;
+    use crate::rerank::reranker::MockReranker;
+
+    fn verifier(reranker: MockReranker) -> HitVerifier {
+        HitVerifier {
+            reranker: Box::new(reranker),
+            top_k: 3,
+            min_score: 0.5,
+        }
+    }
+
+    fn candidate(entry_id: u64, prompt: Option<&str>) -> CacheHit<CachedResponse> {
+        let response = CachedResponse::from_body(b"{}".to_vec());
+        CacheHit {
+            response: match prompt {
+                Some(prompt) => response.with_prompt(prompt),
+                None => response,
+            },
+            entry_id,
+            similarity: 0.95,
+            similarity_threshold: 0.9,
+        }
+    }
+
+    #[tokio::test]
+    async fn select_should_return_best_scored_candidate() {
+        // given
+        let mut reranker = MockReranker::new();
+        reranker
+            .expect_score()
+            .with(eq("prompt"), always())
+            .returning(|_, _| Ok(vec![0.6, 0.9, 0.9]));
+        let candidates = vec![
+            candidate(0, Some("a")),
+            candidate(1, Some("b")),
+            candidate(2, Some("c")),
+        ];
+
+        // when
+        let selected = verifier(reranker)
+            .select("prompt", candidates)
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(selected.unwrap().entry_id, 1);
+    }
+
+    #[tokio::test]
+    async fn select_should_reject_candidates_below_min_score() {
+        // given
+        let mut reranker = MockReranker::new();
+        reranker
+            .expect_score()
+            .returning(|_, _| Ok(vec![0.2, 0.49]));
+
+        // when
+        let selected = verifier(reranker)
+            .select(
+                "yes",
+                vec![candidate(0, Some("no")), candidate(1, Some("nope"))],
+            )
+            .await
+            .unwrap();
+
+        // then
+        assert!(selected.is_none());
+    }
+
+    #[tokio::test]
+    async fn select_should_skip_candidates_without_prompt() {
+        // given
+        let mut reranker = MockReranker::new();
+        reranker
+            .expect_score()
+            .withf(|_, candidates| candidates == [String::from("b")])
+            .returning(|_, _| Ok(vec![0.8]));
+
+        // when
+        let selected = verifier(reranker)
+            .select("prompt", vec![candidate(0, None), candidate(1, Some("b"))])
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(selected.unwrap().entry_id, 1);
+    }
+
+    #[tokio::test]
+    async fn select_should_not_score_without_candidates() {
+        // given
+        let mut reranker = MockReranker::new();
+        reranker.expect_score().times(0);
+
+        // when
+        let selected = verifier(reranker)
+            .select("prompt", vec![candidate(0, None)])
+            .await
+            .unwrap();
+
+        // then
+        assert!(selected.is_none());
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value, json};
+
+use super::error::StreamError;
+use super::sse::SseEvent;
+
+// Rebuilds a `message` object out of the events of an Anthropic messages stream
+#[derive(Default)]
+pub struct AnthropicStreamAssembler {
+    message: Option<Map<String, Value>>,
+    blocks: BTreeMap<u64, Map<String, Value>>,
+    // tool_use inputs are streamed as partial json strings and only parsed once the block stops
+    partial_inputs: BTreeMap<u64, String>,
+    stopped: bool,
+}
+
+impl AnthropicStreamAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: &SseEvent) -> Result<(), StreamError> {
+        let payload: Value = serde_json::from_str(&event.data)?;
+        let event_type = payload
+            .get("type")
+            .and_then(Value::as_str)
+            .or(event.event.as_deref())
+            .unwrap_or_default();
+
+        match event_type {
+            "message_start" => {
+                let message = payload
+                    .get("message")
+                    .and_then(Value::as_object)
+                    .cloned()
+                    .ok_or_else(|| missing("message_start.message"))?;
+                self.message = Some(message);
+            }
+            "content_block_start" => {
+                let index = block_index(&payload)?;
+                let block = payload
+                    .get("content_block")
+                    .and_then(Value::as_object)
+                    .cloned()
+                    .ok_or_else(|| missing("content_block_start.content_block"))?;
+                self.blocks.insert(index, block);
+            }
+            "content_block_delta" => {
+                let index = block_index(&payload)?;
+                let delta = payload
+                    .get("delta")
+                    .ok_or_else(|| missing("content_block_delta.delta"))?;
+                self.apply_delta(index, delta)?;
+            }
+            "content_block_stop" => {
+                let index = block_index(&payload)?;
+                if let Some(partial_input) = self.partial_inputs.remove(&index) {
+                    let input = if partial_input.is_empty() {
+                        json!({})
+                    } else {
+                        serde_json::from_str(&partial_input)?
+                    };
+                    let block = self.blocks.entry(index).or_default();
+                    block.insert(String::from("input"), input);
+                }
+            }
+            "message_delta" => {
+                let message = self
+                    .message
+                    .as_mut()
+                    .ok_or_else(|| missing("message_start"))?;
+                if let Some(delta) = payload.get("delta").and_then(Value::as_object) {
+                    for (field, value) in delta {
+                        message.insert(field.clone(), value.clone());
+                    }
+                }
+                if let Some(usage) = payload.get("usage").and_then(Value::as_object) {
+                    let merged = message
+                        .entry("usage")
+                        .or_insert_with(|| json!({}))
+                        .as_object_mut()
+                        .ok_or_else(|| missing("message.usage"))?;
+                    for (field, value) in usage {
+                        merged.insert(field.clone(), value.clone());
+                    }
+                }
+            }
+            "message_stop" => self.stopped = true,
+            "error" => {
+                let error = payload.get("error").unwrap_or(&payload);
+                return Err(StreamError::UpstreamError(error.to_string()));
+            }
+            // pings and unknown event types are safe to ignore per the Anthropic versioning policy
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn apply_delta(&mut self, index: u64, delta: &Value) -> Result<(), StreamError> {
+        let block = self.blocks.entry(index).or_default();
+        let append = |block: &mut Map<String, Value>, field: &str, fragment: &str| {
+            let accumulated = block.get(field).and_then(Value::as_str).unwrap_or_default();
+            let joined = format!("{accumulated}{fragment}");
+            block.insert(field.to_owned(), Value::String(joined));
+        };
+
+        match delta
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "text_delta" => append(block, "text", str_field(delta, "text")?),
+            "thinking_delta" => append(block, "thinking", str_field(delta, "thinking")?),
+            "signature_delta" => {
+                let signature = str_field(delta, "signature")?;
+                block.insert(String::from("signature"), json!(signature));
+            }
+            "input_json_delta" => self
+                .partial_inputs
+                .entry(index)
+                .or_default()
+                .push_str(str_field(delta, "partial_json")?),
+            other => {
+                return Err(StreamError::UnsupportedFormat(format!(
+                    "unknown delta type '{other}'"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Value, StreamError> {
+        if !self.stopped {
+            return Err(StreamError::Incomplete);
+        }
+        let mut message = self.message.ok_or_else(|| missing("message_start"))?;
+        let content: Vec<Value> = self.blocks.into_values().map(Value::Object).collect();
+        message.insert(String::from("content"), Value::Array(content));
+        Ok(Value::Object(message))
+    }
+}
+
+// Turns a stored `message` object back into the event sequence Anthropic would have streamed
+pub fn replay(message: &Value) -> Result<Vec<SseEvent>, StreamError> {
+    let content = message
+        .get("content")
+        .and_then(Value::as_array)
+        .ok_or_else(|| StreamError::UnsupportedFormat(String::from("missing content")))?;
+
+    let mut start_message = message.clone();
+    start_message["content"] = json!([]);
+    start_message["stop_reason"] = Value::Null;
+    start_message["stop_sequence"] = Value::Null;
+
+    let mut events = vec![event(
+        "message_start",
+        json!({"type": "message_start", "message": start_message}),
+    )];
+
+    for (index, block) in content.iter().enumerate() {
+        let mut start_block = block.clone();
+        let delta = match block.get("type").and_then(Value::as_str) {
+            Some("text") => {
+                start_block["text"] = json!("");
+                vec![
+                    json!({"type": "text_delta", "text": block.get("text").cloned().unwrap_or(json!(""))}),
+                ]
+            }
+            Some("thinking") => {
+                start_block["thinking"] = json!("");
+                let mut deltas = vec![json!({
+                    "type": "thinking_delta",
+                    "thinking": block.get("thinking").cloned().unwrap_or(json!("")),
+                })];
+                if let Some(signature) = block.get("signature") {
+                    start_block["signature"] = json!("");
+                    deltas.push(json!({"type": "signature_delta", "signature": signature}));
+                }
+                deltas
+            }
+            Some("tool_use") => {
+                start_block["input"] = json!({});
+                let input = block.get("input").cloned().unwrap_or(json!({}));
+                vec![json!({"type": "input_json_delta", "partial_json": input.to_string()})]
+            }
+            // other block types (e.g. redacted_thinking) are sent whole in the start event
+            _ => vec![],
+        };
+
+        events.push(event(
+            "content_block_start",
+            json!({"type": "content_block_start", "index": index, "content_block": start_block}),
+        ));
+        for delta in delta {
+            events.push(event(
+                "content_block_delta",
+                json!({"type": "content_block_delta", "index": index, "delta": delta}),
+            ));
+        }
+        events.push(event(
+            "content_block_stop",
+            json!({"type": "content_block_stop", "index": index}),
+        ));
+    }
+
+    let output_tokens = message
+        .pointer("/usage/output_tokens")
+        .cloned()
+        .unwrap_or(json!(0));
+    events.push(event(
+        "message_delta",
+        json!({
+            "type": "message_delta",
+            "delta": {
+                "stop_reason": message.get("stop_reason").cloned().unwrap_or(Value::Null),
+                "stop_sequence": message.get("stop_sequence").cloned().unwrap_or(Value::Null),
+            },
+            "usage": {"output_tokens": output_tokens},
+        }),
+    ));
+    events.push(event("message_stop", json!({"type": "message_stop"})));
+    Ok(events)
+}
+
+fn event(name: &str, payload: Value) -> SseEvent {
+    SseEvent::named(name, payload.to_string())
+}
+
+fn block_index(payload: &Value) -> Result<u64, StreamError> {
+    payload
+        .get("index")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| missing("index"))
+}
+
+fn str_field<'a>(value: &'a Value, field: &str) -> Result<&'a str, StreamError> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| missing(field))
+}
+
+fn missing(field: &str) -> StreamError {
+    StreamError::UnsupportedFormat(format!("missing field '{field}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{AnthropicStreamAssembler, replay};
+    use crate::streaming::{error::StreamError, sse::SseEvent};
+
+    fn event(payload: Value) -> SseEvent {
+        let name = payload["type"].as_str().unwrap().to_owned();
+        SseEvent::named(name, payload.to_string())
+    }
+
+    fn message_start() -> SseEvent {
+        event(json!({
+            "type": "message_start",
+            "message": {
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-3-5-sonnet-20241022",
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": {"input_tokens": 10, "output_tokens": 1}
+            }
+        }))
+    }
+
+    #[test]
+    fn should_assemble_text_blocks_into_message() {
+        let mut assembler = AnthropicStreamAssembler::new();
+        let events = vec![
+            message_start(),
+            event(
+                json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            ),
+            event(json!({"type": "ping"})),
+            event(
+                json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hello"}}),
+            ),
+            event(
+                json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": " world"}}),
+            ),
+            event(json!({"type": "content_block_stop", "index": 0})),
+            event(
+                json!({"type": "message_delta", "delta": {"stop_reason": "end_turn", "stop_sequence": null}, "usage": {"output_tokens": 3}}),
+            ),
+            event(json!({"type": "message_stop"})),
+        ];
+
+        for event in &events {
+            assembler.push(event).unwrap();
+        }
+        let message = assembler.finish().unwrap();
+
+        assert_eq!(message["id"], "msg_1");
+        assert_eq!(
+            message["content"],
+            json!([{"type": "text", "text": "Hello world"}])
+        );
+        assert_eq!(message["stop_reason"], "end_turn");
+        assert_eq!(message["usage"]["input_tokens"], 10);
+        assert_eq!(message["usage"]["output_tokens"], 3);
+    }
+
+    #[test]
+    fn should_parse_streamed_tool_input() {
+        let mut assembler = AnthropicStreamAssembler::new();
+        let events = vec![
+            message_start(),
+            event(
+                json!({"type": "content_block_start", "index": 0, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {}}}),
+            ),
+            event(
+                json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": "{\"city\": "}}),
+            ),
+            event(
+                json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": "\"Paris\"}"}}),
+            ),
+            event(json!({"type": "content_block_stop", "index": 0})),
+            event(json!({"type": "message_stop"})),
+        ];
+
+        for event in &events {
+            assembler.push(event).unwrap();
+        }
+        let message = assembler.finish().unwrap();
+
+        assert_eq!(message["content"][0]["input"], json!({"city": "Paris"}));
+    }
+
+    #[test]
+    fn should_fail_on_error_event() {
+        let mut assembler = AnthropicStreamAssembler::new();
+        assembler.push(&message_start()).unwrap();
+
+        let result = assembler.push(&event(
+            json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}),
+        ));
+
+        match result {
+            Err(StreamError::UpstreamError(_)) => {}
+            _ => panic!("Expected StreamError::UpstreamError"),
+        }
+    }
+
+    #[test]
+    fn replay_should_round_trip_through_assembler() {
+        let message = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Paris"}}
+            ],
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 20}
+        });
+
+        let events = replay(&message).unwrap();
+
+        let mut assembler = AnthropicStreamAssembler::new();
+        for event in &events {
+            assembler.push(event).unwrap();
+        }
+        assert_eq!(assembler.finish().unwrap(), message);
+    }
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("Invalid JSON in stream event: {0}")]
+    InvalidEvent(#[from] serde_json::Error),
+    #[error("Unsupported stream format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Upstream reported an error mid-stream: {0}")]
+    UpstreamError(String),
+    #[error("Stream ended before the response was complete")]
+    Incomplete,
+}
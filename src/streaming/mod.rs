@@ -0,0 +1,144 @@
+pub mod anthropic;
+pub mod error;
+pub mod openai;
+pub mod sse;
+
+use axum::body::Bytes;
+use serde_json::Value;
+
+use crate::providers::ProviderType;
+use anthropic::AnthropicStreamAssembler;
+use error::StreamError;
+use openai::OpenAIStreamAssembler;
+use sse::SseParser;
+
+pub fn is_stream_request(request_body: &Value) -> bool {
+    request_body
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+enum Format {
+    OpenAI(OpenAIStreamAssembler),
+    Anthropic(AnthropicStreamAssembler),
+}
+
+// Accumulates the raw bytes of an upstream event stream into the equivalent non-streamed response,
+// which is what gets cached so that both streamed and non-streamed requests can be served from it
+pub struct StreamAssembler {
+    parser: SseParser,
+    format: Format,
+}
+
+impl StreamAssembler {
+    pub fn supports(provider: &ProviderType) -> bool {
+        !matches!(provider, ProviderType::Generic)
+    }
+
+    // Returns None for providers whose stream format is unknown, such streams are passed through uncached
+    pub fn for_provider(provider: ProviderType) -> Option<Self> {
+        let format = match provider {
+            ProviderType::OpenAI => Format::OpenAI(OpenAIStreamAssembler::new()),
+            ProviderType::Anthropic => Format::Anthropic(AnthropicStreamAssembler::new()),
+            ProviderType::Generic => return None,
+        };
+        Some(Self {
+            parser: SseParser::new(),
+            format,
+        })
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), StreamError> {
+        for event in self.parser.push(chunk) {
+            match &mut self.format {
+                Format::OpenAI(assembler) => assembler.push(&event)?,
+                Format::Anthropic(assembler) => assembler.push(&event)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Vec<u8>, StreamError> {
+        if let Some(event) = self.parser.finish() {
+            match &mut self.format {
+                Format::OpenAI(assembler) => assembler.push(&event)?,
+                Format::Anthropic(assembler) => assembler.push(&event)?,
+            }
+        }
+        let response = match self.format {
+            Format::OpenAI(assembler) => assembler.finish()?,
+            Format::Anthropic(assembler) => assembler.finish()?,
+        };
+        Ok(serde_json::to_vec(&response)?)
+    }
+}
+
+// Replays a cached non-streamed response body as a synthetic event stream in the provider's format
+pub fn replay(provider: &ProviderType, cached_body: &[u8]) -> Result<Bytes, StreamError> {
+    let response: Value = serde_json::from_slice(cached_body)?;
+    let events = match provider {
+        ProviderType::OpenAI => openai::replay(&response)?,
+        ProviderType::Anthropic => anthropic::replay(&response)?,
+        ProviderType::Generic => {
+            return Err(StreamError::UnsupportedFormat(String::from(
+                "streaming is not supported for the generic provider",
+            )));
+        }
+    };
+    let encoded: Vec<u8> = events
+        .iter()
+        .flat_map(|event| event.to_bytes().to_vec())
+        .collect();
+    Ok(Bytes::from(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{StreamAssembler, is_stream_request, replay};
+    use crate::providers::ProviderType;
+
+    #[test]
+    fn is_stream_request_should_read_stream_flag() {
+        assert!(is_stream_request(&json!({"stream": true})));
+        assert!(!is_stream_request(&json!({"stream": false})));
+        assert!(!is_stream_request(&json!({"messages": []})));
+    }
+
+    #[test]
+    fn generic_provider_should_not_be_assembled() {
+        assert!(!StreamAssembler::supports(&ProviderType::Generic));
+        assert!(StreamAssembler::for_provider(ProviderType::Generic).is_none());
+        assert!(replay(&ProviderType::Generic, b"{}").is_err());
+    }
+
+    #[test]
+    fn replayed_stream_should_assemble_back_into_cached_body() {
+        let cached = json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Paris"},
+                "logprobs": null,
+                "finish_reason": "stop"
+            }]
+        });
+        let cached_body = serde_json::to_vec(&cached).unwrap();
+
+        let stream = replay(&ProviderType::OpenAI, &cached_body).unwrap();
+
+        // feed the stream in small chunks to exercise event reassembly
+        let mut assembler = StreamAssembler::for_provider(ProviderType::OpenAI).unwrap();
+        for chunk in stream.chunks(7) {
+            assembler.push(chunk).unwrap();
+        }
+        let assembled: serde_json::Value =
+            serde_json::from_slice(&assembler.finish().unwrap()).unwrap();
+        assert_eq!(assembled, cached);
+    }
+}
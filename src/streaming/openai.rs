@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value, json};
+
+use super::error::StreamError;
+use super::sse::SseEvent;
+
+const DONE_MARKER: &str = "[DONE]";
+
+#[derive(Default)]
+struct ChoiceState {
+    role: Option<Value>,
+    content: Option<String>,
+    refusal: Option<String>,
+    tool_calls: BTreeMap<u64, Map<String, Value>>,
+    finish_reason: Value,
+    logprobs: Value,
+}
+
+// Rebuilds a `chat.completion` object out of the `chat.completion.chunk` events of a stream
+#[derive(Default)]
+pub struct OpenAIStreamAssembler {
+    envelope: Map<String, Value>,
+    choices: BTreeMap<u64, ChoiceState>,
+    usage: Option<Value>,
+    done: bool,
+}
+
+impl OpenAIStreamAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, event: &SseEvent) -> Result<(), StreamError> {
+        if event.data == DONE_MARKER {
+            self.done = true;
+            return Ok(());
+        }
+
+        let chunk: Value = serde_json::from_str(&event.data)?;
+        if let Some(error) = chunk.get("error") {
+            return Err(StreamError::UpstreamError(error.to_string()));
+        }
+
+        for field in [
+            "id",
+            "created",
+            "model",
+            "system_fingerprint",
+            "service_tier",
+        ] {
+            if let Some(value) = chunk.get(field) {
+                self.envelope.insert(field.to_owned(), value.clone());
+            }
+        }
+        if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+            self.usage = Some(usage.clone());
+        }
+
+        let choices = chunk
+            .get("choices")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for choice in choices {
+            let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0);
+            let state = self.choices.entry(index).or_default();
+            if let Some(delta) = choice.get("delta") {
+                apply_delta(state, delta);
+            }
+            if let Some(finish_reason) = choice.get("finish_reason").filter(|v| !v.is_null()) {
+                state.finish_reason = finish_reason.clone();
+            }
+            if let Some(logprobs) = choice.get("logprobs").filter(|v| !v.is_null()) {
+                state.logprobs = logprobs.clone();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Value, StreamError> {
+        if !self.done {
+            return Err(StreamError::Incomplete);
+        }
+
+        let choices: Vec<Value> = self
+            .choices
+            .into_iter()
+            .map(|(index, state)| {
+                let mut message = Map::new();
+                message.insert(
+                    String::from("role"),
+                    state.role.unwrap_or_else(|| json!("assistant")),
+                );
+                message.insert(
+                    String::from("content"),
+                    state.content.map(Value::String).unwrap_or(Value::Null),
+                );
+                if let Some(refusal) = state.refusal {
+                    message.insert(String::from("refusal"), Value::String(refusal));
+                }
+                if !state.tool_calls.is_empty() {
+                    let tool_calls = state.tool_calls.into_values().map(Value::Object).collect();
+                    message.insert(String::from("tool_calls"), Value::Array(tool_calls));
+                }
+                json!({
+                    "index": index,
+                    "message": message,
+                    "logprobs": state.logprobs,
+                    "finish_reason": state.finish_reason,
+                })
+            })
+            .collect();
+
+        let mut completion = self.envelope;
+        completion.insert(String::from("object"), json!("chat.completion"));
+        completion.insert(String::from("choices"), Value::Array(choices));
+        if let Some(usage) = self.usage {
+            completion.insert(String::from("usage"), usage);
+        }
+        Ok(Value::Object(completion))
+    }
+}
+
+fn apply_delta(state: &mut ChoiceState, delta: &Value) {
+    if let Some(role) = delta.get("role").filter(|v| !v.is_null()) {
+        state.role = Some(role.clone());
+    }
+    if let Some(content) = delta.get("content").and_then(Value::as_str) {
+        state
+            .content
+            .get_or_insert_with(String::new)
+            .push_str(content);
+    }
+    if let Some(refusal) = delta.get("refusal").and_then(Value::as_str) {
+        state
+            .refusal
+            .get_or_insert_with(String::new)
+            .push_str(refusal);
+    }
+    let tool_call_deltas = delta
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for tool_call_delta in tool_call_deltas {
+        let index = tool_call_delta
+            .get("index")
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        let tool_call = state.tool_calls.entry(index).or_default();
+        for field in ["id", "type"] {
+            if let Some(value) = tool_call_delta.get(field).filter(|v| !v.is_null()) {
+                tool_call.insert(field.to_owned(), value.clone());
+            }
+        }
+        if let Some(function_delta) = tool_call_delta.get("function") {
+            let function = tool_call
+                .entry("function")
+                .or_insert_with(|| json!({"name": "", "arguments": ""}));
+            for field in ["name", "arguments"] {
+                if let Some(fragment) = function_delta.get(field).and_then(Value::as_str) {
+                    let accumulated = function[field].as_str().unwrap_or_default();
+                    function[field] = Value::String(format!("{accumulated}{fragment}"));
+                }
+            }
+        }
+    }
+}
+
+// Turns a stored `chat.completion` object back into the chunks OpenAI would have streamed
+pub fn replay(completion: &Value) -> Result<Vec<SseEvent>, StreamError> {
+    let choices = completion
+        .get("choices")
+        .and_then(Value::as_array)
+        .ok_or_else(|| StreamError::UnsupportedFormat(String::from("missing choices")))?;
+
+    let mut envelope = Map::new();
+    for field in [
+        "id",
+        "created",
+        "model",
+        "system_fingerprint",
+        "service_tier",
+    ] {
+        if let Some(value) = completion.get(field) {
+            envelope.insert(field.to_owned(), value.clone());
+        }
+    }
+    envelope.insert(String::from("object"), json!("chat.completion.chunk"));
+    let chunk = |choices: Value| {
+        let mut chunk = envelope.clone();
+        chunk.insert(String::from("choices"), choices);
+        SseEvent::data(Value::Object(chunk).to_string())
+    };
+
+    let mut events = Vec::new();
+    for (position, choice) in choices.iter().enumerate() {
+        let index = choice
+            .get("index")
+            .and_then(Value::as_u64)
+            .unwrap_or(position as u64);
+        let message = choice.get("message").cloned().unwrap_or_else(|| json!({}));
+
+        let mut delta = Map::new();
+        delta.insert(
+            String::from("role"),
+            message.get("role").cloned().unwrap_or(json!("assistant")),
+        );
+        for field in ["content", "refusal"] {
+            if let Some(value) = message.get(field).filter(|v| !v.is_null()) {
+                delta.insert(field.to_owned(), value.clone());
+            }
+        }
+        if let Some(tool_calls) = message.get("tool_calls").and_then(Value::as_array) {
+            let indexed: Vec<Value> = tool_calls
+                .iter()
+                .enumerate()
+                .map(|(tool_index, tool_call)| {
+                    let mut tool_call = tool_call.clone();
+                    tool_call["index"] = json!(tool_index);
+                    tool_call
+                })
+                .collect();
+            delta.insert(String::from("tool_calls"), Value::Array(indexed));
+        }
+
+        events.push(chunk(json!([{
+            "index": index,
+            "delta": delta,
+            "logprobs": choice.get("logprobs").cloned().unwrap_or(Value::Null),
+            "finish_reason": null,
+        }])));
+        events.push(chunk(json!([{
+            "index": index,
+            "delta": {},
+            "logprobs": null,
+            "finish_reason": choice.get("finish_reason").cloned().unwrap_or(json!("stop")),
+        }])));
+    }
+
+    if let Some(usage) = completion.get("usage").filter(|usage| !usage.is_null()) {
+        let mut usage_chunk = envelope.clone();
+        usage_chunk.insert(String::from("choices"), json!([]));
+        usage_chunk.insert(String::from("usage"), usage.clone());
+        events.push(SseEvent::data(Value::Object(usage_chunk).to_string()));
+    }
+
+    events.push(SseEvent::data(DONE_MARKER));
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{OpenAIStreamAssembler, replay};
+    use crate::streaming::{error::StreamError, sse::SseEvent};
+
+    fn chunk(choices: Value) -> SseEvent {
+        SseEvent::data(
+            json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion.chunk",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": choices,
+            })
+            .to_string(),
+        )
+    }
+
+    #[test]
+    fn should_assemble_content_deltas_into_completion() {
+        let mut assembler = OpenAIStreamAssembler::new();
+        let events = vec![
+            chunk(
+                json!([{"index": 0, "delta": {"role": "assistant", "content": ""}, "finish_reason": null}]),
+            ),
+            chunk(json!([{"index": 0, "delta": {"content": "Hello"}, "finish_reason": null}])),
+            chunk(json!([{"index": 0, "delta": {"content": " world"}, "finish_reason": null}])),
+            chunk(json!([{"index": 0, "delta": {}, "finish_reason": "stop"}])),
+            SseEvent::data("[DONE]"),
+        ];
+
+        for event in &events {
+            assembler.push(event).unwrap();
+        }
+        let completion = assembler.finish().unwrap();
+
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(completion["id"], "chatcmpl-123");
+        assert_eq!(completion["model"], "gpt-4o");
+        assert_eq!(completion["choices"][0]["message"]["role"], "assistant");
+        assert_eq!(
+            completion["choices"][0]["message"]["content"],
+            "Hello world"
+        );
+        assert_eq!(completion["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[test]
+    fn should_assemble_tool_call_arguments() {
+        let mut assembler = OpenAIStreamAssembler::new();
+        let events = vec![
+            chunk(
+                json!([{"index": 0, "delta": {"role": "assistant", "tool_calls": [
+                    {"index": 0, "id": "call_1", "type": "function", "function": {"name": "weather", "arguments": ""}}
+                ]}}]),
+            ),
+            chunk(
+                json!([{"index": 0, "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":"}}]}}]),
+            ),
+            chunk(
+                json!([{"index": 0, "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "\"Paris\"}"}}]}}]),
+            ),
+            chunk(json!([{"index": 0, "delta": {}, "finish_reason": "tool_calls"}])),
+            SseEvent::data("[DONE]"),
+        ];
+
+        for event in &events {
+            assembler.push(event).unwrap();
+        }
+        let completion = assembler.finish().unwrap();
+
+        let tool_call = &completion["choices"][0]["message"]["tool_calls"][0];
+        assert_eq!(tool_call["id"], "call_1");
+        assert_eq!(tool_call["function"]["name"], "weather");
+        assert_eq!(tool_call["function"]["arguments"], "{\"city\":\"Paris\"}");
+        assert_eq!(completion["choices"][0]["message"]["content"], Value::Null);
+    }
+
+    #[test]
+    fn finish_should_fail_when_stream_did_not_complete() {
+        let mut assembler = OpenAIStreamAssembler::new();
+        assembler
+            .push(&chunk(json!([{"index": 0, "delta": {"content": "Hel"}}])))
+            .unwrap();
+
+        match assembler.finish() {
+            Err(StreamError::Incomplete) => {}
+            _ => panic!("Expected StreamError::Incomplete"),
+        }
+    }
+
+    #[test]
+    fn replay_should_round_trip_through_assembler() {
+        let completion = json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Paris"},
+                "logprobs": null,
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+        });
+
+        let events = replay(&completion).unwrap();
+        assert_eq!(events.last().unwrap(), &SseEvent::data("[DONE]"));
+
+        let mut assembler = OpenAIStreamAssembler::new();
+        for event in &events {
+            assembler.push(event).unwrap();
+        }
+        assert_eq!(assembler.finish().unwrap(), completion);
+    }
+
+    #[test]
+    fn replay_should_reject_non_completion_bodies() {
+        assert!(replay(&json!({"answer": "Paris"})).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use axum::body::Bytes;
+
+// A single server-sent event, as defined by the WHATWG event stream format
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl SseEvent {
+    pub fn data(data: impl Into<String>) -> Self {
+        Self {
+            event: None,
+            data: data.into(),
+        }
+    }
+
+    pub fn named(event: impl Into<String>, data: impl Into<String>) -> Self {
+        Self {
+            event: Some(event.into()),
+            data: data.into(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut encoded = String::new();
+        if let Some(event) = &self.event {
+            encoded.push_str("event: ");
+            encoded.push_str(event);
+            encoded.push('\n');
+        }
+        for line in self.data.split('\n') {
+            encoded.push_str("data: ");
+            encoded.push_str(line);
+            encoded.push('\n');
+        }
+        encoded.push('\n');
+        Bytes::from(encoded)
+    }
+}
+
+// Incremental parser for an event stream, network chunks can split events (and lines) at any byte
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Feeds a chunk of bytes into the parser and returns any events that were completed by it
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    events.push(event);
+                }
+                continue;
+            }
+            self.process_line(line);
+        }
+        events
+    }
+
+    // Flushes an event that was not terminated by a blank line before the stream ended
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buffer.is_empty() {
+            let line = String::from_utf8_lossy(&self.buffer).into_owned();
+            self.buffer.clear();
+            self.process_line(line.trim_end_matches('\r'));
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) {
+        // lines starting with a colon are comments, used by providers as keep-alives
+        if line.starts_with(':') {
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => self.data.push(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseEvent { event, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SseEvent, SseParser};
+
+    #[test]
+    fn should_parse_events_split_across_chunks() {
+        let mut parser = SseParser::new();
+
+        let first = parser.push(b"event: message_start\nda");
+        assert!(first.is_empty());
+
+        let second = parser.push(b"ta: {\"a\":1}\n\ndata: [DONE]\n\n");
+        assert_eq!(
+            second,
+            vec![
+                SseEvent::named("message_start", "{\"a\":1}"),
+                SseEvent::data("[DONE]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_handle_crlf_comments_and_multiline_data() {
+        let mut parser = SseParser::new();
+
+        let events = parser.push(b": keep-alive\r\n\r\ndata: line one\r\ndata: line two\r\n\r\n");
+
+        assert_eq!(events, vec![SseEvent::data("line one\nline two")]);
+    }
+
+    #[test]
+    fn finish_should_flush_unterminated_event() {
+        let mut parser = SseParser::new();
+
+        assert!(parser.push(b"data: trailing").is_empty());
+
+        assert_eq!(parser.finish(), Some(SseEvent::data("trailing")));
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn to_bytes_should_round_trip_through_parser() {
+        let event = SseEvent::named("content_block_delta", "{\"text\":\"hi\"}");
+
+        let mut parser = SseParser::new();
+        let parsed = parser.push(&event.to_bytes());
+
+        assert_eq!(parsed, vec![event]);
+    }
+}
@@ -1,11 +1,35 @@
 use std::sync::LazyLock;
 
-use axum::http::{HeaderMap, HeaderName};
+use axum::http::header::{AGE, CACHE_CONTROL};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::cache::cache::CacheHit;
+use crate::cache::cached_response::CachedResponse;
 
 // HEADERS
 pub static PROXY_UPSTREAM_HOST_HEADER: HeaderName = HeaderName::from_static("x-llm-proxy-host");
 pub static PROXY_UPSTREAM_HEADER: HeaderName = HeaderName::from_static("x-llm-proxy-upstream");
 pub static PROXY_PROMPT_LOCATION_HEADER: HeaderName = HeaderName::from_static("x-llm-prompt");
+pub static CONTEXT_STRATEGY_HEADER: HeaderName = HeaderName::from_static("x-semcache-context");
+pub static CACHE_TTL_HEADER: HeaderName = HeaderName::from_static("x-semcache-ttl");
+// the api key of semcache itself, as opposed to the one of the llm provider
+pub static SEMCACHE_KEY_HEADER: HeaderName = HeaderName::from_static("x-semcache-key");
+pub static NAMESPACE_HEADER: HeaderName = HeaderName::from_static("x-semcache-namespace");
+// upstream api key header of anthropic, openai uses the authorization header
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+// set on cache hits, when the response was received from upstream
+pub static CREATED_AT_HEADER: HeaderName = HeaderName::from_static("x-semcache-created-at");
+// set on cache hits, the age of the entry in seconds
+pub static ENTRY_AGE_HEADER: HeaderName = HeaderName::from_static("x-semcache-age");
+// set on cache hits, which entry was served and how closely it matched the request
+pub static ENTRY_ID_HEADER: HeaderName = HeaderName::from_static("x-semcache-entry-id");
+pub static SIMILARITY_HEADER: HeaderName = HeaderName::from_static("x-semcache-similarity");
+// set on cache hits, and on requests to override the threshold of the lookup
+pub static SIMILARITY_THRESHOLD_HEADER: HeaderName =
+    HeaderName::from_static("x-semcache-threshold");
+// set on responses, the partition the response is cached under
+pub static PARTITION_HEADER: HeaderName = HeaderName::from_static("x-semcache-partition");
 pub static HOP_HEADERS: LazyLock<[HeaderName; 12]> = LazyLock::new(|| {
     [
         HeaderName::from_static("connection"),
@@ -38,6 +62,42 @@ pub fn prepare_upstream_headers(headers: HeaderMap) -> HeaderMap {
     // remove semcache headers
     upstream_headers.remove(&PROXY_UPSTREAM_HEADER);
     upstream_headers.remove(&PROXY_PROMPT_LOCATION_HEADER);
+    upstream_headers.remove(&CONTEXT_STRATEGY_HEADER);
+    upstream_headers.remove(&CACHE_TTL_HEADER);
+    upstream_headers.remove(&NAMESPACE_HEADER);
+    upstream_headers.remove(&SEMCACHE_KEY_HEADER);
+    // cache control of the request is handled by semcache
+    upstream_headers.remove(CACHE_CONTROL);
+    upstream_headers.remove(&SIMILARITY_THRESHOLD_HEADER);
 
     upstream_headers
 }
+
+// Headers describing a cached entry: when it was received from upstream and how old it is
+pub fn cached_entry_headers(entry: &CachedResponse) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let age = HeaderValue::from(entry.age().as_secs());
+    headers.insert(AGE, age.clone());
+    headers.insert(&ENTRY_AGE_HEADER, age);
+    let created_at =
+        DateTime::<Utc>::from(entry.created_at).to_rfc3339_opts(SecondsFormat::Secs, true);
+    if let Ok(created_at) = HeaderValue::from_str(&created_at) {
+        headers.insert(&CREATED_AT_HEADER, created_at);
+    }
+    headers
+}
+
+// Headers describing how a cache hit was found, similarities are on the [0, 1] scale of the threshold
+pub fn cache_hit_headers<T>(hit: &CacheHit<T>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(&ENTRY_ID_HEADER, HeaderValue::from(hit.entry_id));
+    for (name, value) in [
+        (&SIMILARITY_HEADER, hit.similarity),
+        (&SIMILARITY_THRESHOLD_HEADER, hit.similarity_threshold),
+    ] {
+        if let Ok(value) = HeaderValue::from_str(&format!("{value:.4}")) {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
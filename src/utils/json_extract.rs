@@ -1,8 +1,62 @@
 use crate::endpoints::chat::error::CompletionError;
 use jsonpath_rust::JsonPath;
+use serde::Serialize;
 use serde_json::Value;
 
-pub fn extract_prompt_from_path(data: &Value, path: &str) -> Result<String, CompletionError> {
+// block types whose text is embedded, OpenAI uses `input_text` in some of its newer request formats
+const TEXT_BLOCK_TYPES: [&str; 2] = ["text", "input_text"];
+
+// The embeddable part of a message content. Content is either a plain string, or an array of blocks
+// in which case the text blocks are joined and any other blocks (images, documents, tool calls and
+// results...) are kept whole as attachments, as they can only be matched exactly.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Content {
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Value>,
+}
+
+impl Content {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(text) => Some(Content {
+                text: text.clone(),
+                attachments: vec![],
+            }),
+            Value::Array(blocks) => {
+                let mut texts = Vec::new();
+                let mut attachments = Vec::new();
+                for block in blocks {
+                    let block_type = block.get("type").and_then(Value::as_str);
+                    let text = block.get("text").and_then(Value::as_str);
+                    match (block_type, text) {
+                        (Some(block_type), Some(text))
+                            if TEXT_BLOCK_TYPES.contains(&block_type) =>
+                        {
+                            texts.push(text)
+                        }
+                        _ => attachments.push(block.clone()),
+                    }
+                }
+                Some(Content {
+                    text: texts.join("\n"),
+                    attachments,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// A single message of a conversation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Turn {
+    pub role: String,
+    #[serde(flatten)]
+    pub content: Content,
+}
+
+pub fn extract_prompt_from_path(data: &Value, path: &str) -> Result<Content, CompletionError> {
     let query_results = data.query_with_path(path)?;
 
     query_results
@@ -13,19 +67,55 @@ pub fn extract_prompt_from_path(data: &Value, path: &str) -> Result<String, Comp
         .and_then(|last_query_ref| {
             let value_ref: &Value = last_query_ref.clone().val();
 
-            value_ref.as_str().map(str::to_owned).ok_or_else(|| {
+            Content::from_value(value_ref).ok_or_else(|| {
                 CompletionError::InvalidRequest(format!(
-                    "Expected a string at path '{}', but found: {:?}",
+                    "Expected a string or an array of content blocks at path '{}', but found: {:?}",
                     path, value_ref
                 ))
             })
         })
 }
 
+// Extracts the conversation found at `path`, which has to be an array of messages with a role and
+// content
+pub fn extract_turns_from_path(data: &Value, path: &str) -> Result<Vec<Turn>, CompletionError> {
+    let query_results = data.query_with_path(path)?;
+    let messages = query_results
+        .last()
+        .map(|last_query_ref| last_query_ref.clone().val())
+        .and_then(Value::as_array)
+        .ok_or_else(|| {
+            CompletionError::InvalidRequest(format!(
+                "No array of messages found at path '{}'",
+                path
+            ))
+        })?;
+
+    messages
+        .iter()
+        .map(|message| {
+            let role = message.get("role").and_then(Value::as_str);
+            let content = message.get("content").and_then(Content::from_value);
+            match (role, content) {
+                (Some(role), Some(content)) => Ok(Turn {
+                    role: role.to_owned(),
+                    content,
+                }),
+                _ => Err(CompletionError::InvalidRequest(format!(
+                    "Expected a role and content in message at path '{}', but found: {:?}",
+                    path, message
+                ))),
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::endpoints::chat::error::CompletionError;
-    use crate::utils::json_extract::extract_prompt_from_path;
+    use crate::utils::json_extract::{
+        Content, Turn, extract_prompt_from_path, extract_turns_from_path,
+    };
     use axum::Json;
     use serde_json::json;
 
@@ -43,7 +133,7 @@ mod tests {
 
         assert!(result.is_ok());
         let prompt = result.unwrap();
-        assert_eq!(prompt, "My extremely interesting prompt");
+        assert_eq!(prompt.text, "My extremely interesting prompt");
 
         let body = Json(json!({
             "messages": [
@@ -55,7 +145,7 @@ mod tests {
 
         assert!(result.is_ok());
         let prompt = result.unwrap();
-        assert_eq!(prompt, "Single message");
+        assert_eq!(prompt.text, "Single message");
     }
 
     #[test]
@@ -92,6 +182,95 @@ mod tests {
 
         assert!(result.is_ok());
         let prompt = result.unwrap();
-        assert_eq!(prompt, "What is the capital of France?");
+        assert_eq!(prompt.text, "What is the capital of France?");
+    }
+
+    #[test]
+    fn should_extract_turns_with_roles() {
+        let body = json!({
+            "messages": [
+                {"role": "user", "content": "What is the capital of Spain?"},
+                {"role": "assistant", "content": "Madrid"},
+                {"role": "user", "content": "and what about in France?"}
+            ]
+        });
+
+        let turns = extract_turns_from_path(&body, "$.messages").unwrap();
+
+        assert_eq!(turns.len(), 3);
+        assert_eq!(
+            turns[1],
+            Turn {
+                role: String::from("assistant"),
+                content: Content {
+                    text: String::from("Madrid"),
+                    attachments: vec![],
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn extract_turns_should_return_error_when_messages_missing() {
+        let body = json!({"prompt": "What is the capital of France?"});
+
+        let result = extract_turns_from_path(&body, "$.messages");
+
+        match result {
+            Err(CompletionError::InvalidRequest(x)) => {
+                assert_eq!("No array of messages found at path '$.messages'", x)
+            }
+            _ => panic!("Expected CompletionError::InvalidRequest"),
+        }
+    }
+
+    #[test]
+    fn should_join_text_blocks_and_keep_other_blocks_as_attachments() {
+        let image = json!({"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}});
+        let body = json!({
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is in this picture?"},
+                    image.clone(),
+                    {"type": "text", "text": "Answer in one word."}
+                ]
+            }]
+        });
+
+        let prompt = extract_prompt_from_path(&body, "$.messages[-1].content").unwrap();
+
+        assert_eq!(prompt.text, "What is in this picture?\nAnswer in one word.");
+        assert_eq!(prompt.attachments, vec![image]);
+    }
+
+    #[test]
+    fn should_extract_openai_multimodal_content() {
+        let body = json!({
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "Describe this"},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+                ]
+            }]
+        });
+
+        let turns = extract_turns_from_path(&body, "$.messages").unwrap();
+
+        assert_eq!(turns[0].content.text, "Describe this");
+        assert_eq!(turns[0].content.attachments.len(), 1);
+    }
+
+    #[test]
+    fn should_return_error_when_content_is_not_text_or_blocks() {
+        let body = json!({"messages": [{"role": "user", "content": 42}]});
+
+        let result = extract_prompt_from_path(&body, "$.messages[-1].content");
+
+        match result {
+            Err(CompletionError::InvalidRequest(_)) => {}
+            _ => panic!("Expected CompletionError::InvalidRequest"),
+        }
     }
 }
@@ -2,3 +2,7 @@ pub fn normalize(vec: &[f32]) -> Vec<f32> {
     let norm = vec.iter().map(|x| x * x).sum::<f32>().sqrt();
     vec.iter().map(|x| x / norm).collect()
 }
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}